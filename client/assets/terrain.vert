@@ -1,13 +1,19 @@
 attribute vec3 position;
 attribute float health;
+attribute vec2 feature; // water, rock
 
 uniform mat4 view;
 uniform sampler2D health_lut;
+uniform vec3 tint;
 
 varying vec4 vcolor;
 
 void main() {
 	vec4 pos = view * vec4(position, 1.0);
 	gl_Position = vec4(pos.xyz, 1.0);
-	vcolor = texture2D(health_lut, vec2(health, 0.5));
+	vec4 land = texture2D(health_lut, vec2(health, 0.5));
+	vec4 rock = vec4(0.55, 0.53, 0.5, 1.0);
+	vec4 water = vec4(0.25, 0.5, 0.8, 1.0);
+
+	vcolor = mix(mix(land, rock, feature.y), water, feature.x) * vec4(tint, 1.0);
 }
@@ -4,6 +4,7 @@ varying vec3 vnormal;
 
 uniform mat4 normal_xform;
 uniform vec3 color;
+uniform float alpha;
 
 vec3 gamma_correct(vec3 c) {
 	return pow(c, vec3(1.0/2.2));
@@ -29,6 +30,6 @@ void main() {
 	float ndotl2 = clamp(dot(vnormal, secondary), 0.0, 1.0);
 
 	// gl_FragColor = vec4(col * (ndotl * primcol + ndotl2 * seccol), 1.0);
-	gl_FragColor = vec4(gamma_correct(col * (primcol * ndotl + seccol * ndotl2)), 1.0);
+	gl_FragColor = vec4(gamma_correct(col * (primcol * ndotl + seccol * ndotl2)), alpha);
 	// gl_FragData[1] = vec4(vnormal*0.5 + 0.5, 1.0);
 }
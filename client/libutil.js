@@ -23,4 +23,48 @@ mergeInto(LibraryManager.library, {
 			window.location.href = data.replace('image/png', 'application/octet-stream');
 		}
 	},
+
+	storage_set_raw: function(key, keyLen, value, valueLen) {
+		try {
+			window.localStorage.setItem(Pointer_stringify(key, keyLen), Pointer_stringify(value, valueLen));
+		} catch(e) {
+			console.log("Failed to write to local storage: " + e);
+		}
+	},
+
+	// Returns a malloc'd string that the caller must free, or null if the key isn't set
+	storage_get_raw: function(key, keyLen) {
+		var value = null;
+
+		try {
+			value = window.localStorage.getItem(Pointer_stringify(key, keyLen));
+		} catch(e) {
+			console.log("Failed to read from local storage: " + e);
+		}
+
+		if(value === null) return 0;
+
+		var size = lengthBytesUTF8(value) + 1;
+		var ptr = _malloc(size);
+		stringToUTF8(value, ptr, size);
+		return ptr;
+	},
+
+	// Returns a malloc'd "host:port" string that the caller must free.
+	// index.html sets Module.websocket.url from /config.json or the page's origin, and sockets
+	// open that url whatever address they're given, but the address still has to resolve
+	websocket_address_raw: function() {
+		var url = (Module.websocket && Module.websocket.url) || window.location.href;
+		var parser = document.createElement("a");
+		parser.href = url;
+
+		var secure = parser.protocol === "wss:" || parser.protocol === "https:";
+		var port = parser.port || (secure ? "443" : "80");
+		var address = parser.hostname.replace(/^\[|\]$/g, "") + ":" + port;
+
+		var size = lengthBytesUTF8(address) + 1;
+		var ptr = _malloc(size);
+		stringToUTF8(address, ptr, size);
+		return ptr;
+	},
 });
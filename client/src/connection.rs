@@ -3,9 +3,7 @@ use std::ffi::CString;
 use std::ptr;
 use libc::*;
 use ems;
-
-use std::borrow::BorrowMut;
-use std::mem::transmute;
+use util;
 
 use common::Packet;
 
@@ -34,10 +32,10 @@ impl Connection {
 		});
 
 		unsafe {
-			let ptr: *mut u8 = transmute(b.borrow_mut() as *mut Connection);
-			ems::emscripten_set_socket_open_callback(ptr as *mut u8, on_open);
-			ems::emscripten_set_socket_close_callback(ptr as *mut u8, on_close);
-			ems::emscripten_set_socket_message_callback(ptr as *mut u8, on_message);
+			let ptr = &mut *b as *mut Connection as *mut u8;
+			ems::emscripten_set_socket_open_callback(ptr, Some(on_open));
+			ems::emscripten_set_socket_close_callback(ptr, Some(on_close));
+			ems::emscripten_set_socket_message_callback(ptr, Some(on_message));
 		}
 
 		b
@@ -47,7 +45,7 @@ impl Connection {
 		use std::os::unix::io::FromRawFd;
 
 		fn errno() -> i32 {
-			extern { fn __errno_location() -> *mut i32; }
+			extern "C" { fn __errno_location() -> *mut i32; }
 			unsafe{ *__errno_location() }
 		}
 
@@ -80,10 +78,12 @@ impl Connection {
 				ai_next: ptr::null_mut(),
 			};
 
-			let host_address = env!("PUBLIC_ADDRESS");
+			// Only has to resolve - the socket opens Module.websocket.url, which is wss:// when the page came over https
+			let (host_address, port) = util::websocket_address();
 			let chost_address = CString::new(host_address).unwrap();
+			let cport = CString::new(port).unwrap();
 
-			let gairet = getaddrinfo(chost_address.as_bytes_with_nul().as_ptr(), "9001\0".as_ptr(), &hint, &mut addresses);
+			let gairet = getaddrinfo(chost_address.as_bytes_with_nul().as_ptr(), cport.as_bytes_with_nul().as_ptr(), &hint, &mut addresses);
 			if gairet < 0 {
 				// let error = gai_strerror(gairet);
 				// let error = std::str::from_utf8(&error);
@@ -117,7 +117,7 @@ impl Connection {
 
 		let len = p.write(&mut buf);
 
-		if let Err(e) = self.stream.as_mut().unwrap().write_all(&mut buf[..len]) {
+		if let Err(e) = self.stream.as_mut().unwrap().write_all(&buf[..len]) {
 			println!("send failed {}", e);
 			return false
 		}
@@ -128,35 +128,35 @@ impl Connection {
 
 impl Drop for Connection {
 	fn drop(&mut self) {
-		use std::ptr::{null, null_mut};
+		use std::ptr::null_mut;
 
 		unsafe {
 			if self.socket_fd >= 0 {
 				close(self.socket_fd);
 			}
 
-			ems::emscripten_set_socket_open_callback(null_mut(), transmute(null() as *const u8));
-			ems::emscripten_set_socket_close_callback(null_mut(), transmute(null() as *const u8));
-			ems::emscripten_set_socket_message_callback(null_mut(), transmute(null() as *const u8));
-		}		
+			ems::emscripten_set_socket_open_callback(null_mut(), None);
+			ems::emscripten_set_socket_close_callback(null_mut(), None);
+			ems::emscripten_set_socket_message_callback(null_mut(), None);
+		}
 	}
 }
 
-extern fn on_open(fd: i32, ctx: *mut u8) {
+extern "C" fn on_open(fd: i32, ctx: *mut u8) {
 	use std::os::unix::io::FromRawFd;
 
-	let mut ctx: &mut Connection = unsafe{ transmute(ctx) };
+	let ctx = unsafe{ &mut *(ctx as *mut Connection) };
 	ctx.stream = unsafe{ Some(TcpStream::from_raw_fd(fd)) };
 	ctx.event_queue.push(ConnectionEvent::Connect);
 }
 
-extern fn on_retry(ctx: *mut u8) {
-	let ctx: &mut Connection = unsafe{ transmute(ctx) };
+extern "C" fn on_retry(ctx: *mut u8) {
+	let ctx = unsafe{ &mut *(ctx as *mut Connection) };
 	ctx.attempt_connect();
 }
 
-extern fn on_close(_: i32, vctx: *mut u8) {
-	let ctx: &mut Connection = unsafe{ transmute(vctx) };
+extern "C" fn on_close(_: i32, vctx: *mut u8) {
+	let ctx = unsafe{ &mut *(vctx as *mut Connection) };
 
 	unsafe { close(ctx.socket_fd); }
 
@@ -171,18 +171,17 @@ extern fn on_close(_: i32, vctx: *mut u8) {
 	unsafe{ ems::emscripten_async_call(on_retry, vctx, 1500) };
 }
 
-extern fn on_message(_: i32, ctx: *mut u8) {
+extern "C" fn on_message(_: i32, ctx: *mut u8) {
 	use std::io::Read;
 
-	let mut ctx: &mut Connection = unsafe{ transmute(ctx) };
+	let ctx = unsafe{ &mut *(ctx as *mut Connection) };
 	if ctx.stream.is_none() { return }
 
 	let mut buf = [0u8; 8<<10];
 
 	let len = match ctx.stream.as_mut().unwrap().read(&mut buf) {
-		Ok(len) =>
-			if len <= 0 { return }
-			else { len },
+		Ok(0) => return,
+		Ok(len) => len,
 
 		Err(e) => {
 			println!("recv failed {}", e);
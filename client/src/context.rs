@@ -3,12 +3,18 @@ use rendering::RenderingContext;
 use rendering::uibuilder::UIBuilder;
 use rendering::worldview::WorldView;
 use connection::Connection;
+use local::LocalSession;
 
 use common::*;
-use common::world::Species;
+use common::world::{Species, SpeciesRegistry};
+use common::terrain::Terrain;
+use rendering::types::Color;
 use ui::{self, InputTarget};
 
 const DRAG_THRESHOLD: f32 = 10.0;
+// Seconds between refreshing the stats graph
+const STATS_REQUEST_INTERVAL: u64 = 5;
+const STATS_HISTORY_LENGTH: u16 = 60;
 
 #[derive(Copy, Clone)]
 enum ScreenState {
@@ -19,8 +25,13 @@ enum ScreenState {
 pub struct MainContext {
 	connection: Box<Connection>,
 	auth_token: Option<u32>,
+	// Stands in for the server when playing offline
+	local: Option<LocalSession>,
+	// Filled in as TerrainData arrives, and shown once it's all here
+	partial_terrain: Option<Terrain>,
 
 	prev_frame: time::Instant,
+	last_stats_request: time::Instant,
 
 	render_ctx: RenderingContext,
 	ui_builder: UIBuilder,
@@ -52,7 +63,10 @@ impl MainContext {
 		MainContext {
 			connection,
 			auth_token: None,
+			local: None,
+			partial_terrain: None,
 			prev_frame: time::Instant::now(),
+			last_stats_request: time::Instant::now(),
 
 			render_ctx,
 			ui_builder: UIBuilder::new(),
@@ -66,7 +80,7 @@ impl MainContext {
 			is_dragging: false,
 			is_mouse_down: false,
 
-			selected_species: Species::A,
+			selected_species: Species(0),
 
 			touch_id: None,
 			touch_enabled: false,
@@ -95,8 +109,12 @@ impl MainContext {
 		let diff = now - self.prev_frame;
 		self.prev_frame = now;
 
-		let udt = diff.subsec_nanos() / 1000;
-		let dt = udt as f32 / 1000_000.0;
+		let udt = diff.subsec_micros();
+		let dt = udt as f32 / 1_000_000.0;
+
+		if let Some(ref mut local) = self.local {
+			local.update(dt);
+		}
 
 		match self.screen_state {
 			ScreenState::AuthScreen => {
@@ -115,6 +133,13 @@ impl MainContext {
 						self.connection.send(&Packet::RequestNewSession);
 					}
 
+					Some(Action::PlayOffline) => {
+						println!("Playing offline");
+						self.local = Some(LocalSession::new());
+						self.auth_screen.on_offline();
+						self.enter_world();
+					}
+
 					Some(Action::EnterGame) => {
 						println!("Pls enter game");
 						self.screen_state = ScreenState::MainScreen;
@@ -138,19 +163,65 @@ impl MainContext {
 
 						Action::ClickWorld(p) => {
 							let pos = self.world_view.convert_to_world_coords(p);
-							self.connection.send(&Packet::RequestPlaceTree(pos.x, pos.z,
-								self.selected_species));
+
+							match self.world_view.get_tree_near(Vec2::new(pos.x, pos.z), 0.5) {
+								Some(id) => {
+									self.send(&Packet::InspectTree(id));
+								}
+
+								None => {
+									let species = self.selected_species;
+									self.main_screen.set_inspected_genome(None);
+									self.send(&Packet::RequestPlaceTree(pos.x, pos.z, species));
+								}
+							}
 						}
 
 						Action::SetSpecies(s) => {
 							self.selected_species = s;
 						}
+
+						Action::SetOverlay(overlay) => {
+							self.world_view.set_overlay(overlay);
+							self.send(&Packet::RequestSoilOverlay(overlay));
+						}
+
+						Action::JoinWorld(world) => {
+							self.send(&Packet::JoinWorld(world));
+						}
 					}
 				}
+
+				let region = self.world_view.get_visible_region(&self.render_ctx.get_viewport());
+				if self.world_view.set_region(region) {
+					self.send(&Packet::SubscribeRegion(region));
+				}
+
+				if now - self.last_stats_request > time::Duration::from_secs(STATS_REQUEST_INTERVAL) {
+					self.send(&Packet::RequestStats(STATS_HISTORY_LENGTH));
+					self.last_stats_request = now;
+				}
 			}
 		}
 	}
 
+	fn send(&mut self, p: &Packet) -> bool {
+		match self.local {
+			Some(ref mut local) => local.send(p),
+			None => self.connection.send(p),
+		}
+	}
+
+	// Once auth succeeds, a world is joined or offline play starts
+	fn enter_world(&mut self) {
+		// Forces a fresh SubscribeRegion once we're on the main screen
+		self.world_view.clear_region();
+
+		// The server doesn't remember overlays between connections or worlds
+		self.world_view.set_overlay(None);
+		self.main_screen.set_overlay(None);
+	}
+
 	pub fn on_render(&mut self) {
 		self.render_ctx.fit_target_to_viewport();
 		let vp = self.render_ctx.get_viewport();
@@ -181,7 +252,7 @@ impl MainContext {
 		self.ui_builder.render();
 	}
 
-	fn get_input_target<'a>(&'a mut self) -> &'a mut InputTarget {
+	fn get_input_target(&mut self) -> &mut dyn InputTarget {
 		match self.screen_state {
 			ScreenState::AuthScreen => &mut self.auth_screen,
 			ScreenState::MainScreen => &mut self.main_screen,
@@ -235,6 +306,12 @@ impl MainContext {
 	}
 
 	pub fn process_packets(&mut self) {
+		if self.local.is_some() {
+			// The server is ignored for good once playing offline
+			self.connection.event_queue.clear();
+			self.connection.packet_queue.clear();
+		}
+
 		for e in self.connection.event_queue.clone() {
 			use connection::ConnectionEvent as CE;
 
@@ -244,7 +321,13 @@ impl MainContext {
 			}
 		}
 
-		for packet in self.connection.packet_queue.clone() {
+		let mut packets = self.connection.packet_queue.clone();
+
+		if let Some(ref mut local) = self.local {
+			packets.append(&mut local.packet_queue);
+		}
+
+		for packet in packets {
 			match packet {
 				Packet::AuthSuccessful(token) => {
 					println!("Auth success: {}", token);
@@ -252,8 +335,7 @@ impl MainContext {
 					// Hide screen
 					self.auth_screen.on_auth_success();
 					self.auth_token = Some(token);
-
-					self.connection.send(&Packet::RequestDownloadWorld);
+					self.enter_world();
 				}
 
 				Packet::AuthFail => {
@@ -266,23 +348,104 @@ impl MainContext {
 					self.auth_screen.set_key(token);
 				}
 
-				Packet::TreePlaced(id, pos_x, pos_y, species) => {
-					self.world_view.place_tree(id, Vec3::new(pos_x, 0.0, pos_y), species);
+				Packet::WorldList(worlds) => {
+					for (i, &(ref name, players, max_players)) in worlds.iter().enumerate() {
+						println!("World {} '{}': {}/{} players", i, name, players, max_players);
+					}
+
+					self.main_screen.set_worlds(worlds);
+				}
+
+				Packet::WorldJoined(world) => {
+					println!("Joined world {}", world);
+					self.main_screen.set_current_world(Some(world));
+					self.main_screen.set_inspected_genome(None);
+					self.main_screen.set_stats_history(Vec::new());
+					self.enter_world();
+				}
+
+				Packet::SpeciesRegistry(species) => {
+					println!("Received {} species", species.len());
+
+					let colors = species.iter()
+						.map(|info| match info.palette.last() {
+							Some(c) => Color::rgb(c[0], c[1], c[2]),
+							None => Color::grey(0.5),
+						})
+						.collect();
+
+					self.main_screen.set_species_colors(colors);
+					self.world_view.set_species_registry(SpeciesRegistry::new(species));
+
+					if self.selected_species.0 as usize >= self.world_view.species_count() {
+						self.selected_species = Species(0);
+					}
+				}
+
+				Packet::TerrainData(seed, width, height, first_row, tiles) => {
+					let (width, height, first_row) = (width as usize, height as usize, first_row as usize);
+
+					// Pieces arrive in order, the first one starting afresh
+					if first_row == 0 {
+						self.partial_terrain = Some(Terrain { seed, .. Terrain::flat(width, height) });
+					}
+
+					let complete = match self.partial_terrain {
+						Some(ref mut terrain) if terrain.seed == seed && terrain.width == width && terrain.height == height =>
+							terrain.set_rows(first_row, &tiles) && (first_row * width + tiles.len() == width * height),
+						_ => false,
+					};
+
+					if complete {
+						let terrain = self.partial_terrain.take().unwrap();
+						println!("Received terrain ({})", terrain.seed);
+						self.world_view.set_terrain(terrain);
+					}
+				}
+
+				Packet::ClimateUpdate(season, weather) => {
+					println!("Climate: {:?}, {:?}", season, weather);
+					self.world_view.set_climate(season, weather);
+				}
+
+				Packet::TreePlaced(id, pos_x, pos_y, species, _natural, variation) => {
+					self.world_view.place_tree(id, Vec3::new(pos_x, 0.0, pos_y), species, variation);
+				}
+
+				Packet::TreeInfo(id, species, generation, genome) => {
+					println!("Tree {} [{:?}] generation {}: {:?}", id, species, generation, genome);
+					self.main_screen.set_inspected_genome(Some(genome));
+				}
+
+				Packet::StatsHistory(samples) => {
+					self.main_screen.set_stats_history(samples);
+				}
+
+				Packet::TreeAfflicted(id, affliction, origin) => {
+					self.world_view.afflict_tree(id, affliction, origin);
 				}
 
 				Packet::TreeDied(id) => {
 					self.world_view.kill_tree(id);
 				}
 
-				Packet::HealthUpdate(health_state) => {
+				Packet::HealthUpdate(x, y, health_state) => {
 					// println!("HealthUpdate {:?}", health_state);
-					self.world_view.update_health_state(health_state);
+					self.world_view.update_health_state((x, y), health_state);
+				}
+
+				Packet::FaunaUpdate(animals) => {
+					self.world_view.update_fauna(animals);
 				}
 
 				Packet::TreeUpdate(tree_maturities) => {
 					self.world_view.update_tree_maturities(tree_maturities);
 				}
 
+				Packet::SoilUpdate(x, y, ch, soil_state) => {
+					self.world_view.update_soil_state((x, y), ch, soil_state);
+				}
+
 				_ => {}
 			}
 		}
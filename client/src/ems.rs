@@ -1,7 +1,6 @@
 #![allow(dead_code)]
 
 use context::MainContext;
-use std::mem::transmute;
 use std::ffi::CString;
 use std::ptr;
 
@@ -61,14 +60,14 @@ pub const RESULT_NO_DATA: i32             = -7;
 pub const RESULT_TIMED_OUT: i32           = -8;
 
 pub type EmWebGLContext = i32;
-pub type EmSocketCallback = extern fn(fd: i32, ud: *mut u8);
-type EmMouseCallback = extern fn(etype: i32, evt: *const EmscriptenMouseEvent, ud: *mut u8) -> i32;
-type EmTouchCallback = extern fn(etype: i32, evt: *const EmscriptenTouchEvent, ud: *mut u8) -> i32;
-type EmArgCallback = extern fn(ud: *mut u8);
+pub type EmSocketCallback = Option<extern "C" fn(fd: i32, ud: *mut u8)>;
+type EmMouseCallback = extern "C" fn(etype: i32, evt: *const EmscriptenMouseEvent, ud: *mut u8) -> i32;
+type EmTouchCallback = extern "C" fn(etype: i32, evt: *const EmscriptenTouchEvent, ud: *mut u8) -> i32;
+type EmArgCallback = extern "C" fn(ud: *mut u8);
 
 #[allow(improper_ctypes)]
-extern {
-	fn emscripten_set_main_loop_arg(func: extern fn(arg: *mut u8), arg: *mut u8, fps: i32, simulate_infinite_loop: i32);
+extern "C" {
+	fn emscripten_set_main_loop_arg(func: extern "C" fn(arg: *mut u8), arg: *mut u8, fps: i32, simulate_infinite_loop: i32);
 	fn emscripten_exit_with_live_runtime();
 
 	pub fn emscripten_set_socket_open_callback(ud: *mut u8, callback: EmSocketCallback);
@@ -95,45 +94,44 @@ extern {
 }
 
 pub trait Interop {
-	fn as_int(self, _: &mut Vec<CString>) -> i32;
+	fn into_int(self, _: &mut Vec<CString>) -> i32;
 }
 
 impl Interop for i32 {
-	fn as_int(self, _: &mut Vec<CString>) -> i32 {
-		return self;
+	fn into_int(self, _: &mut Vec<CString>) -> i32 {
+		self
 	}
 }
 
-impl<'a> Interop for &'a str {
-	fn as_int(self, arena: &mut Vec<CString>) -> i32 {
+impl Interop for &str {
+	fn into_int(self, arena: &mut Vec<CString>) -> i32 {
 		let c = CString::new(self).unwrap();
 		let ret = c.as_ptr() as i32;
 		arena.push(c);
-		return ret;
+		ret
 	}
 }
 
-impl<'a> Interop for *const u8 {
-	fn as_int(self, _: &mut Vec<CString>) -> i32 {
-		return self as i32;
+impl Interop for *const u8 {
+	fn into_int(self, _: &mut Vec<CString>) -> i32 {
+		self as i32
 	}
 }
 
-#[macro_export]
 macro_rules! js {
 	( ($( $x:expr ),*) $y:expr ) => {
 		{
 			use std::ffi::CString;
 			let mut arena: Vec<CString> = Vec::new();
 			#[allow(dead_code)]
-			const LOCAL: &'static [u8] = $y;
-			unsafe { ::ems::emscripten_asm_const_int(&LOCAL[0] as *const _ as *const u8, $(::ems::Interop::as_int($x, &mut arena)),*) }
+			const LOCAL: &[u8] = $y;
+			unsafe { ::ems::emscripten_asm_const_int(&LOCAL[0] as *const _ as *const u8, $(::ems::Interop::into_int($x, &mut arena)),*) }
 		}
 	};
 	( $y:expr ) => {
 		{
 			#[allow(dead_code)]
-			const LOCAL: &'static [u8] = $y;
+			const LOCAL: &[u8] = $y;
 			unsafe { ::ems::emscripten_asm_const_int(&LOCAL[0] as *const _ as *const u8) }
 		}
 	};
@@ -157,16 +155,16 @@ pub fn start(ctx_ptr: *mut MainContext) {
 	}
 }
 
-extern fn on_update(ud: *mut u8) {
-	let mut ctx: &mut MainContext = unsafe{ transmute(ud) };
+extern "C" fn on_update(ud: *mut u8) {
+	let ctx = unsafe{ &mut *(ud as *mut MainContext) };
 
 	ctx.process_packets();
 	ctx.on_update();
 	ctx.on_render();
 }
 
-extern fn on_mouse_down(_: i32, ev: *const EmscriptenMouseEvent, ud: *mut u8) -> i32 {
-	let mut ctx: &mut MainContext = unsafe{ transmute(ud) };
+extern "C" fn on_mouse_down(_: i32, ev: *const EmscriptenMouseEvent, ud: *mut u8) -> i32 {
+	let ctx = unsafe{ &mut *(ud as *mut MainContext) };
 	if ctx.touch_enabled { return 0 }
 
 	let ev = unsafe { &*ev };
@@ -175,8 +173,8 @@ extern fn on_mouse_down(_: i32, ev: *const EmscriptenMouseEvent, ud: *mut u8) ->
 	1
 }
 
-extern fn on_mouse_up(_: i32, ev: *const EmscriptenMouseEvent, ud: *mut u8) -> i32 {
-	let mut ctx: &mut MainContext = unsafe{ transmute(ud) };
+extern "C" fn on_mouse_up(_: i32, ev: *const EmscriptenMouseEvent, ud: *mut u8) -> i32 {
+	let ctx = unsafe{ &mut *(ud as *mut MainContext) };
 	if ctx.touch_enabled { return 0 }
 
 	let ev = unsafe { &*ev };
@@ -185,8 +183,8 @@ extern fn on_mouse_up(_: i32, ev: *const EmscriptenMouseEvent, ud: *mut u8) -> i
 	1
 }
 
-extern fn on_mouse_move(_: i32, ev: *const EmscriptenMouseEvent, ud: *mut u8) -> i32 {
-	let mut ctx: &mut MainContext = unsafe{ transmute(ud) };
+extern "C" fn on_mouse_move(_: i32, ev: *const EmscriptenMouseEvent, ud: *mut u8) -> i32 {
+	let ctx = unsafe{ &mut *(ud as *mut MainContext) };
 	if ctx.touch_enabled { return 0 }
 
 	let ev = unsafe { &*ev };
@@ -196,8 +194,8 @@ extern fn on_mouse_move(_: i32, ev: *const EmscriptenMouseEvent, ud: *mut u8) ->
 }
 
 
-extern fn on_touch_down(_: i32, ev: *const EmscriptenTouchEvent, ud: *mut u8) -> i32 {
-	let mut ctx: &mut MainContext = unsafe{ transmute(ud) };
+extern "C" fn on_touch_down(_: i32, ev: *const EmscriptenTouchEvent, ud: *mut u8) -> i32 {
+	let ctx = unsafe{ &mut *(ud as *mut MainContext) };
 	let ev = unsafe { &*ev };
 
 	ctx.touch_enabled = true;
@@ -213,8 +211,8 @@ extern fn on_touch_down(_: i32, ev: *const EmscriptenTouchEvent, ud: *mut u8) ->
 	0
 }
 
-extern fn on_touch_up(_: i32, ev: *const EmscriptenTouchEvent, ud: *mut u8) -> i32 {
-	let mut ctx: &mut MainContext = unsafe{ transmute(ud) };
+extern "C" fn on_touch_up(_: i32, ev: *const EmscriptenTouchEvent, ud: *mut u8) -> i32 {
+	let ctx = unsafe{ &mut *(ud as *mut MainContext) };
 	let ev = unsafe { &*ev };
 
 	if ctx.touch_id.is_none() { return 0 }
@@ -233,8 +231,8 @@ extern fn on_touch_up(_: i32, ev: *const EmscriptenTouchEvent, ud: *mut u8) -> i
 	0
 }
 
-extern fn on_touch_move(_: i32, ev: *const EmscriptenTouchEvent, ud: *mut u8) -> i32 {
-	let mut ctx: &mut MainContext = unsafe{ transmute(ud) };
+extern "C" fn on_touch_move(_: i32, ev: *const EmscriptenTouchEvent, ud: *mut u8) -> i32 {
+	let ctx = unsafe{ &mut *(ud as *mut MainContext) };
 	let ev = unsafe { &*ev };
 
 	if ctx.touch_id.is_none() { return 0 }
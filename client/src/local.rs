@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+
+use common::*;
+use common::world::{self, World, Region, SoilChannel, chunk_for_pos};
+use common::species;
+use common::stats::WorldStats;
+use util;
+
+static SPECIES_SRC: &str = include_str!("../../server/species.toml");
+
+const SAVE_KEY: &str = "offline_world";
+// Only as much as the stats graph asks for
+const HISTORY_LENGTH: usize = 60;
+// In seconds
+const FAUNA_SYNC_INTERVAL: f32 = 1.0;
+
+// Runs a world in the client for offline play. Takes the same packets a server would
+// and queues up the replies a server would send, so MainContext can't tell the difference
+pub struct LocalSession {
+	world: World,
+	history: VecDeque<WorldStats>,
+
+	region: Option<Region>,
+	overlay: Option<SoilChannel>,
+	time_since_fauna_sync: f32,
+
+	pub packet_queue: Vec<Packet>,
+}
+
+impl LocalSession {
+	pub fn new() -> Self {
+		let registry = species::parse_registry(SPECIES_SRC, "species.toml")
+			.expect("Built in species registry is invalid");
+
+		let world = match load_world(registry.clone()) {
+			Some(world) => world,
+			None => {
+				let terrain_seed = thread_rng().gen::<u32>();
+				println!("Starting new offline world ({})", terrain_seed);
+				World::new_random(registry, world::generate_terrain(terrain_seed, world::DEFAULT_WORLD_SIZE))
+			}
+		};
+
+		let mut session = LocalSession {
+			world,
+			history: VecDeque::with_capacity(HISTORY_LENGTH),
+
+			region: None,
+			overlay: None,
+			time_since_fauna_sync: 0.0,
+
+			packet_queue: Vec::new(),
+		};
+
+		// Anything from setting up the world goes out with the initial chunk state instead
+		session.world.dead_trees.clear();
+		session.world.new_trees.clear();
+		session.world.afflicted_trees.clear();
+		session.world.climate_changed = false;
+
+		let climate = session.world.climate;
+		session.packet_queue.push(Packet::SpeciesRegistry(session.world.registry.species.clone()));
+		session.packet_queue.extend(terrain_packets(&session.world.terrain));
+		session.packet_queue.push(Packet::ClimateUpdate(climate.season, climate.weather));
+
+		session
+	}
+
+	pub fn send(&mut self, p: &Packet) -> bool {
+		if !p.is_valid_from_client() { return false }
+
+		match *p {
+			Packet::SubscribeRegion(region) => {
+				let region = world::clamp_region(&self.world.terrain, region);
+				let prev_region = self.region;
+				self.region = Some(region);
+
+				for chunk in region.chunks() {
+					if prev_region.is_some_and(|r| r.contains(chunk)) { continue }
+
+					self.push_chunk_state(chunk);
+
+					if let Some(ch) = self.overlay {
+						self.push_chunk_soil(chunk, ch);
+					}
+				}
+			}
+
+			Packet::RequestSoilOverlay(overlay) => {
+				let prev_overlay = self.overlay;
+				self.overlay = overlay;
+
+				if let (Some(ch), Some(region)) = (overlay, self.region) {
+					if overlay != prev_overlay {
+						for chunk in region.chunks() {
+							self.push_chunk_soil(chunk, ch);
+						}
+					}
+				}
+			}
+
+			Packet::RequestPlaceTree(x, y, species) => {
+				// Announced along with naturally dropped seeds in update
+				self.world.place_tree(species, Vec2::new(x, y));
+			}
+
+			Packet::InspectTree(tree_id) => {
+				if let Some(t) = self.world.trees.iter().find(|t| t.id == tree_id) {
+					self.packet_queue.push(Packet::TreeInfo(t.id, t.species, t.generation, t.genome));
+				}
+			}
+
+			Packet::RequestStats(count) => {
+				let count = (count as usize).min(255);
+				let skip = self.history.len().saturating_sub(count);
+				let samples = self.history.iter().skip(skip).cloned().collect();
+				self.packet_queue.push(Packet::StatsHistory(samples));
+			}
+
+			_ => {}
+		}
+
+		true
+	}
+
+	pub fn update(&mut self, dt: f32) {
+		if self.world.update(dt) {
+			if self.history.len() >= HISTORY_LENGTH {
+				self.history.pop_front();
+			}
+
+			let stats = self.world.take_stats();
+			self.history.push_back(stats);
+
+			if let Some(region) = self.region {
+				for chunk in region.chunks() {
+					let tree_maturities = self.get_tree_maturities(chunk);
+					let health_state = self.world.get_chunk_health(chunk);
+
+					self.packet_queue.push(Packet::HealthUpdate(chunk.0, chunk.1, health_state));
+
+					if !tree_maturities.is_empty() {
+						self.packet_queue.push(Packet::TreeUpdate(tree_maturities));
+					}
+
+					if let Some(ch) = self.overlay {
+						self.push_chunk_soil(chunk, ch);
+					}
+				}
+			}
+
+			self.save();
+		}
+
+		self.time_since_fauna_sync += dt;
+
+		if self.time_since_fauna_sync > FAUNA_SYNC_INTERVAL {
+			let animals = self.world.fauna.animals.iter()
+				.map(|a| (a.id, a.kind, a.pos, a.vel))
+				.collect();
+
+			self.packet_queue.push(Packet::FaunaUpdate(animals));
+			self.time_since_fauna_sync = 0.0;
+		}
+
+		for &(t_id, pos) in &self.world.dead_trees {
+			if self.is_visible(pos) {
+				self.packet_queue.push(Packet::TreeDied(t_id));
+			}
+		}
+
+		for &(t_id, pos, species, natural, variation) in &self.world.new_trees {
+			if self.is_visible(pos) {
+				self.packet_queue.push(Packet::TreePlaced(t_id, pos.x, pos.y, species, natural, variation));
+			}
+		}
+
+		for &(t_id, pos, affliction, origin) in &self.world.afflicted_trees {
+			if self.is_visible(pos) {
+				self.packet_queue.push(Packet::TreeAfflicted(t_id, affliction, origin));
+			}
+		}
+
+		if self.world.climate_changed {
+			let climate = self.world.climate;
+			self.packet_queue.push(Packet::ClimateUpdate(climate.season, climate.weather));
+			self.world.climate_changed = false;
+		}
+
+		self.world.dead_trees.clear();
+		self.world.new_trees.clear();
+		self.world.afflicted_trees.clear();
+	}
+
+	fn save(&self) {
+		util::storage_set(SAVE_KEY, &util::to_hex(&self.world.save()));
+	}
+
+	fn is_visible(&self, pos: Vec2) -> bool {
+		self.region.is_some_and(|r| r.contains(chunk_for_pos(pos)))
+	}
+
+	fn get_tree_maturities(&self, chunk: world::ChunkCoord) -> Vec<(u32, u8)> {
+		self.world.trees.iter()
+			.filter(|t| !t.is_dead() && chunk_for_pos(t.pos) == chunk)
+			.map(|t| (t.id, t.get_maturity_stage(&self.world.registry[t.species])))
+			.collect()
+	}
+
+	fn push_chunk_state(&mut self, chunk: world::ChunkCoord) {
+		let health_state = self.world.get_chunk_health(chunk);
+		self.packet_queue.push(Packet::HealthUpdate(chunk.0, chunk.1, health_state));
+
+		for t in self.world.trees.iter().filter(|t| !t.is_dead() && chunk_for_pos(t.pos) == chunk) {
+			self.packet_queue.push(Packet::TreePlaced(t.id, t.pos.x, t.pos.y, t.species, t.natural, t.genome.color_variation));
+		}
+
+		let tree_maturities = self.get_tree_maturities(chunk);
+		self.packet_queue.push(Packet::TreeUpdate(tree_maturities));
+
+		for t in self.world.trees.iter().filter(|t| !t.is_dead() && chunk_for_pos(t.pos) == chunk) {
+			if let Some((affliction, _)) = t.affliction {
+				self.packet_queue.push(Packet::TreeAfflicted(t.id, affliction, false));
+			}
+		}
+	}
+
+	fn push_chunk_soil(&mut self, chunk: world::ChunkCoord, ch: SoilChannel) {
+		let soil_state = self.world.get_chunk_soil(chunk, ch);
+		self.packet_queue.push(Packet::SoilUpdate(chunk.0, chunk.1, ch, soil_state));
+	}
+}
+
+fn load_world(registry: world::SpeciesRegistry) -> Option<World> {
+	let data = util::storage_get(SAVE_KEY).and_then(|s| util::from_hex(&s))?;
+
+	match World::load(registry, &data) {
+		Ok(world) => {
+			println!("Loaded offline world ({} trees)", world.trees.len());
+			Some(world)
+		}
+
+		Err(e) => {
+			println!("Discarding offline world: {}", e);
+			None
+		}
+	}
+}
@@ -1,8 +1,5 @@
-#![feature(const_fn)]
 #![feature(link_args)]
-#![feature(ord_max_min)]
 
-#[macro_use]
 extern crate common;
 extern crate libc;
 
@@ -12,6 +9,7 @@ mod util;
 mod context;
 mod rendering;
 mod connection;
+mod local;
 
 mod ui;
 
@@ -20,8 +18,8 @@ mod boids;
 use context::*;
 
 fn main() {
-	println!("Is Hosted:      {}", cfg!(hosted));
-	println!("Public address: {}", env!("PUBLIC_ADDRESS"));
+	let (host, port) = util::websocket_address();
+	println!("Server address: {}:{}", host, port);
 
 	ems::start(Box::into_raw(Box::new(MainContext::new())));
 }
@@ -1,4 +1,5 @@
 use common::math::*;
+use common::fauna::FaunaKind;
 use boids;
 
 use rendering::gl;
@@ -6,14 +7,18 @@ use rendering::types::*;
 use rendering::shader::Shader;
 
 use std::mem::size_of;
+use std::ptr;
 
-static BOID_VERT_SRC: &'static str = include_str!("../../assets/boid.vert");
-static BOID_FRAG_SRC: &'static str = include_str!("../../assets/boid.frag");
+static BOID_VERT_SRC: &str = include_str!("../../assets/boid.vert");
+static BOID_FRAG_SRC: &str = include_str!("../../assets/boid.frag");
 
 pub struct BoidView {
 	vbo: u32,
 	shader: Shader,
-	num_points: u32,
+
+	// Pollinators are drawn first, then herbivores
+	num_pollinators: u32,
+	num_herbivores: u32,
 }
 
 impl BoidView {
@@ -25,19 +30,29 @@ impl BoidView {
 				vbo
 			},
 
-			shader: Shader::new(&BOID_VERT_SRC, &BOID_FRAG_SRC),
+			shader: Shader::new(BOID_VERT_SRC, BOID_FRAG_SRC),
 
-			num_points: 0,
+			num_pollinators: 0,
+			num_herbivores: 0,
 		}
 	}
 
 	pub fn update(&mut self, system: &boids::BoidSystem) {
-		let bs: Vec<Vec3> = system.get_boids()
-			.iter()
-			.map(|b| Vec3::new(b.pos.x, 1.0 + b.phase.cos() * 0.1, b.pos.y))
-			.collect();
+		let boids = system.get_boids();
+
+		let pollinators = boids.iter()
+			.filter(|b| b.kind == FaunaKind::Pollinator)
+			.map(|b| Vec3::new(b.pos.x, b.ground + 1.0 + b.phase.cos() * 0.1, b.pos.y));
+
+		let herbivores = boids.iter()
+			.filter(|b| b.kind == FaunaKind::Herbivore)
+			.map(|b| Vec3::new(b.pos.x, b.ground + 0.3 + b.phase.cos().abs() * 0.05, b.pos.y));
 
-		self.num_points = bs.len() as u32;
+		let mut bs: Vec<Vec3> = pollinators.collect();
+		self.num_pollinators = bs.len() as u32;
+
+		bs.extend(herbivores);
+		self.num_herbivores = bs.len() as u32 - self.num_pollinators;
 
 		unsafe {
 			gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
@@ -50,20 +65,24 @@ impl BoidView {
 	}
 
 	pub fn render(&self, view: &Mat4, point_scalar: f32) {
-		if self.num_points < 1 { return }
+		if self.num_pollinators + self.num_herbivores < 1 { return }
 
 		unsafe {
 			self.shader.use_program();
 			self.shader.set_view(view);
-			self.shader.set_uniform_vec3("color", &Color::rgb(0.22, 0.21, 0.2).to_vec3());
-			self.shader.set_uniform_f32("pointScale", 15.0 * point_scalar);
 
 			gl::EnableVertexAttribArray(0);
 
 			gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-			gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, size_of::<Vec3>() as i32, 0 as *const _);
+			gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, size_of::<Vec3>() as i32, ptr::null());
+
+			self.shader.set_uniform_vec3("color", &Color::rgb(0.22, 0.21, 0.2).to_vec3());
+			self.shader.set_uniform_f32("pointScale", 15.0 * point_scalar);
+			gl::DrawArrays(gl::POINTS, 0, self.num_pollinators as i32);
 
-			gl::DrawArrays(gl::POINTS, 0, self.num_points as i32);
+			self.shader.set_uniform_vec3("color", &Color::rgb(0.45, 0.3, 0.18).to_vec3());
+			self.shader.set_uniform_f32("pointScale", 25.0 * point_scalar);
+			gl::DrawArrays(gl::POINTS, self.num_pollinators as i32, self.num_herbivores as i32);
 			gl::BindBuffer(gl::ARRAY_BUFFER, 0);
 		}
 	}
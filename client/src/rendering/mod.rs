@@ -1,13 +1,13 @@
 use ems;
 use std::ops::Drop;
 use std::ffi::CString;
-use std::mem::uninitialized;
+use std::mem::zeroed;
 
 #[link_args = "-s FULL_ES2=1"]
-extern {}
+extern "C" {}
 
 pub mod gl {
-	#![allow(non_upper_case_globals)]
+	#![allow(non_upper_case_globals, unused_imports, clippy::all)]
 	include!(concat!(env!("OUT_DIR"), "/gl_bindings.rs"));
 }
 
@@ -22,7 +22,6 @@ mod boidview;
 mod parser_3ds;
 
 pub use self::types::*;
-pub use self::shader::*;
 
 pub struct RenderingContext {
 	ems_context_handle: ems::EmWebGLContext,
@@ -32,7 +31,7 @@ pub struct RenderingContext {
 
 impl RenderingContext {
 	pub fn new(canvas_id: &str) -> Self {
-		let mut attribs = unsafe { uninitialized() };
+		let mut attribs = unsafe { zeroed() };
 		unsafe { ems::emscripten_webgl_init_context_attributes(&mut attribs) };
 		attribs.alpha = 0;
 		attribs.stencil = 1;
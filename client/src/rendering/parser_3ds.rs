@@ -1,4 +1,3 @@
-use rendering::types::Color;
 use common::*;
 use std;
 
@@ -32,7 +31,7 @@ pub fn parse_3ds(data: &[u8]) -> Option<Mesh3DS> {
 	Some(mesh)
 }
 
-fn read_string_from_slice<'a>(data: &'a [u8]) -> &'a str {
+fn read_string_from_slice(data: &[u8]) -> &str {
 	let index = data.iter().position(|c| *c == 0).unwrap();
 
 	std::str::from_utf8(&data[..index]).unwrap()
@@ -48,46 +47,9 @@ fn read_vec3_from_slice(data: &[u8]) -> Vec3 {
 	)
 }
 
-fn parse_color_chunk(data: &[u8]) -> Color {
-	let id = read_u16_from_slice(&data[0..]);
-	let len = read_u32_from_slice(&data[2..]) as usize;
-
-	match id {
-		// rgb f32
-		0x0010 => {
-			Color::rgb(
-				read_f32_from_slice(&data[6 + 4*0..]),
-				read_f32_from_slice(&data[6 + 4*1..]),
-				read_f32_from_slice(&data[6 + 4*2..]),
-			)
-		}
-
-		// rgb u8
-		0x0011 => {
-			Color::rgb(
-				data[6 + 0] as f32 / 255.0,
-				data[6 + 1] as f32 / 255.0,
-				data[6 + 2] as f32 / 255.0,
-			)
-		}
-
-		// rgb u8 gamma
-		0x0012 => {
-			println!("TODO deal w/ gamma?");
-			Color::rgb(
-				data[6 + 0] as f32 / 255.0,
-				data[6 + 1] as f32 / 255.0,
-				data[6 + 2] as f32 / 255.0,
-			)
-		}
-
-		_ => unimplemented!()
-	}
-}
-
 // http://wayback.archive.org/web/20090404091233/http://www.jalix.org/ressources/graphics/3DS/_unofficials/3ds-info.txt
 // http://wayback.archive.org/web/20090404045225/http://www.whisqu.se/per/docs/graphics56.htm
-fn parse_chunk(mut mesh: &mut Mesh3DS, data: &[u8]) {
+fn parse_chunk(mesh: &mut Mesh3DS, data: &[u8]) {
 	if data.len() < 6 { return }
 
 	let mut idx = 0;
@@ -102,7 +64,7 @@ fn parse_chunk(mut mesh: &mut Mesh3DS, data: &[u8]) {
 			// 3D editor block
 			0x3d3d => {
 				// println!(" . [3D root]");
-				parse_chunk(&mut mesh, &header[6..len])
+				parse_chunk(mesh, &header[6..len])
 			},
 
 			// Object block
@@ -110,13 +72,13 @@ fn parse_chunk(mut mesh: &mut Mesh3DS, data: &[u8]) {
 				let name = read_string_from_slice(&header[6..len]);
 				// println!(" . . [object] '{}'", name);
 
-				parse_chunk(&mut mesh, &header[6+name.len()+1..]);
+				parse_chunk(mesh, &header[6+name.len()+1..]);
 			}
 
 			// Triangular mesh
 			0x4100 => {
 				// println!(" . . . [triangle mesh]");
-				parse_chunk(&mut mesh, &header[6..len]);
+				parse_chunk(mesh, &header[6..len]);
 			}
 
 			0x4110 => {
@@ -145,10 +107,10 @@ fn parse_chunk(mut mesh: &mut Mesh3DS, data: &[u8]) {
 				let inc = 2;
 
 				for i in 0..poly_count {
-					v.push(read_u16_from_slice(&header[8 + (i*4 + 0)*inc .. len]));
+					v.push(read_u16_from_slice(&header[8 + i*4*inc .. len]));
 					v.push(read_u16_from_slice(&header[8 + (i*4 + 1)*inc .. len]));
 					v.push(read_u16_from_slice(&header[8 + (i*4 + 2)*inc .. len]));
-					// v.push(read_u16_from_slice(&header[8 + (i*4 + 0)*inc .. len])); // Flags
+					// v.push(read_u16_from_slice(&header[8 + i*4*inc .. len])); // Flags
 				}
 
 				// println!(" . . . . . {:?}", v);
@@ -156,7 +118,7 @@ fn parse_chunk(mut mesh: &mut Mesh3DS, data: &[u8]) {
 				mesh.elements = v;
 
 				let subchunk_offset = 8 + poly_count * inc * 4;
-				parse_chunk(&mut mesh, &header[subchunk_offset .. len]);
+				parse_chunk(mesh, &header[subchunk_offset .. len]);
 			}
 
 			0x4130 => {
@@ -178,7 +140,7 @@ fn parse_chunk(mut mesh: &mut Mesh3DS, data: &[u8]) {
 			// Material block
 			0xAFFF => {
 				// println!(" . . [material block]");
-				// parse_chunk(&mut mesh, &header[6..len]);
+				// parse_chunk(mesh, &header[6..len]);
 			}
 
 			// Material name
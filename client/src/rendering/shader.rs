@@ -1,9 +1,9 @@
+#![allow(dead_code)]
+
 use std;
 use common::math::*;
 use rendering::gl;
 
-use std::mem::transmute;
-
 #[derive(Copy, Clone)]
 pub struct Shader {
 	pub gl_handle: u32,
@@ -77,7 +77,7 @@ impl Shader {
 	}
 
 	pub fn set_uniform_mat(&self, uniform: &str, mat: &Mat4) {
-		self.set_uniform_mat_raw(self.get_uniform_loc(&uniform), &mat);
+		self.set_uniform_mat_raw(self.get_uniform_loc(uniform), mat);
 	}
 	
 	pub fn set_uniform_mat_raw(&self, uniform: i32, mat: &Mat4) {
@@ -90,36 +90,36 @@ impl Shader {
 	pub fn set_uniform_vec2(&self, uniform: &str, v: &Vec2) {
 		unsafe {
 			// TODO: Make sure we're bound
-			gl::Uniform2f(self.get_uniform_loc(&uniform), v.x, v.y);
+			gl::Uniform2f(self.get_uniform_loc(uniform), v.x, v.y);
 		}
 	}
 
 	pub fn set_uniform_vec3(&self, uniform: &str, v: &Vec3) {
 		unsafe {
 			// TODO: Make sure we're bound
-			gl::Uniform3f(self.get_uniform_loc(&uniform), v.x, v.y, v.z);
+			gl::Uniform3f(self.get_uniform_loc(uniform), v.x, v.y, v.z);
 		}
 	}
 
 	pub fn set_uniform_i32(&self, uniform: &str, v: i32) {
 		unsafe {
 			// TODO: Make sure we're bound
-			gl::Uniform1i(self.get_uniform_loc(&uniform), v);
+			gl::Uniform1i(self.get_uniform_loc(uniform), v);
 		}		
 	}
 
 	pub fn set_uniform_f32(&self, uniform: &str, v: f32) {
 		unsafe {
 			// TODO: Make sure we're bound
-			gl::Uniform1f(self.get_uniform_loc(&uniform), v);
+			gl::Uniform1f(self.get_uniform_loc(uniform), v);
 		}
 	}
 
 	pub fn set_proj(&self, mat: &Mat4) {
-		self.set_uniform_mat_raw(self.proj_loc, &mat);
+		self.set_uniform_mat_raw(self.proj_loc, mat);
 	}
 
 	pub fn set_view(&self, mat: &Mat4) {
-		self.set_uniform_mat_raw(self.view_loc, &mat);
+		self.set_uniform_mat_raw(self.view_loc, mat);
 	}
 }
\ No newline at end of file
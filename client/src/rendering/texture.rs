@@ -1,3 +1,5 @@
+#![allow(dead_code)]
+
 use rendering::gl;
 use rendering::types::*;
 
@@ -25,13 +25,17 @@ impl Color {
 	pub fn white() -> Color { Color::grey(1.0) }
 	pub fn black() -> Color { Color::grey(0.0) }
 
-	pub fn to_byte_tuple(&self) -> (u8, u8, u8, u8) {
-		let Color{r,g,b,a} = *self;
+	pub fn to_byte_tuple(self) -> (u8, u8, u8, u8) {
+		let Color{r,g,b,a} = self;
 		((r*255.0) as u8, (g*255.0) as u8, (b*255.0) as u8, (a*255.0) as u8)
 	}
 
-	pub fn to_vec3(&self) -> Vec3 { Vec3::new(self.r, self.g, self.b) }
-	pub fn to_vec4(&self) -> Vec4 { Vec4::new(self.r, self.g, self.b, self.a) }
+	pub fn to_vec3(self) -> Vec3 { Vec3::new(self.r, self.g, self.b) }
+	pub fn to_vec4(self) -> Vec4 { Vec4::new(self.r, self.g, self.b, self.a) }
+
+	pub fn tint(self, o: Color) -> Color {
+		Color::rgba(self.r * o.r, self.g * o.g, self.b * o.b, self.a * o.a)
+	}
 
 	pub fn pow(self, exp: f32) -> Color {
 		Color::rgba(
@@ -5,8 +5,8 @@ use rendering::shader::Shader;
 use std::ops::Drop;
 use std::f32::consts::PI;
 
-static VERT_SRC: &'static str = include_str!("../../assets/ui.vert");
-static FRAG_SRC: &'static str = include_str!("../../assets/ui.frag");
+static VERT_SRC: &str = include_str!("../../assets/ui.vert");
+static FRAG_SRC: &str = include_str!("../../assets/ui.frag");
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -44,10 +44,10 @@ pub enum StencilFunc {
 }
 
 impl StencilOp {
-	pub fn to_gl(&self) -> u32 {
+	pub fn to_gl(self) -> u32 {
 		use self::StencilOp::*;
 
-		match *self {
+		match self {
 			Keep => gl::KEEP,
 			Zero => gl::ZERO,
 			Invert => gl::INVERT,
@@ -57,10 +57,10 @@ impl StencilOp {
 }
 
 impl StencilFunc {
-	pub fn to_gl(&self) -> u32 {
+	pub fn to_gl(self) -> u32 {
 		use self::StencilFunc::*;
 
-		match *self {
+		match self {
 			Never => gl::NEVER,
 			Less => gl::LESS,
 			LessEqual => gl::LEQUAL,
@@ -116,7 +116,7 @@ impl UIBuilder {
 		UIBuilder {
 			viewport: Viewport::new(),
 
-			shader: Shader::new(&VERT_SRC, &FRAG_SRC),
+			shader: Shader::new(VERT_SRC, FRAG_SRC),
 			commands: Vec::new(),
 
 			verts: Vec::new(),
@@ -140,7 +140,8 @@ impl UIBuilder {
 		// TODO: assert that our buffers were generated in the current webgl context
 		
 		unsafe {
-			use std::mem::{transmute, size_of, size_of_val};
+			use std::mem::{size_of, size_of_val};
+			use std::ptr;
 
 			let aspect = self.viewport.get_aspect();
 
@@ -155,14 +156,14 @@ impl UIBuilder {
 			gl::EnableVertexAttribArray(1);
 
 			gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebo);
-			gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (self.indices.len()*short_size) as isize, transmute(self.indices.as_ptr()), gl::STREAM_DRAW);
+			gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (self.indices.len()*short_size) as isize, self.indices.as_ptr() as *const _, gl::STREAM_DRAW);
 
 			gl::BindBuffer(gl::ARRAY_BUFFER, self.fs_quad);
 
 			gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-			gl::BufferData(gl::ARRAY_BUFFER, (self.verts.len()*vert_size) as isize, transmute(self.verts.as_ptr()), gl::STREAM_DRAW);
-			gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, vert_size as i32, transmute(0));
-			gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, vert_size as i32, transmute(8));
+			gl::BufferData(gl::ARRAY_BUFFER, (self.verts.len()*vert_size) as isize, self.verts.as_ptr() as *const _, gl::STREAM_DRAW);
+			gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, vert_size as i32, ptr::null());
+			gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, vert_size as i32, 8 as *const _);
 
 			let mut fs_quad_bound = false;
 
@@ -172,19 +173,19 @@ impl UIBuilder {
 						if fs_quad_bound {
 							fs_quad_bound = false;
 							gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-							gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, vert_size as i32, transmute(0));
-							gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, vert_size as i32, transmute(8));
+							gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, vert_size as i32, ptr::null());
+							gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, vert_size as i32, 8 as *const _);
 						}
 
-						gl::DrawElements(gl::TRIANGLES, count as i32, gl::UNSIGNED_SHORT, transmute(start*short_size as u32));
+						gl::DrawElements(gl::TRIANGLES, count as i32, gl::UNSIGNED_SHORT, (start as usize * short_size) as *const _);
 					}
 
 					Command::FullscreenQuad(color) => {
 						if !fs_quad_bound {
 							fs_quad_bound = true;
 							gl::BindBuffer(gl::ARRAY_BUFFER, self.fs_quad);
-							gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, vert_size as i32, transmute(0));
-							gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, vert_size as i32, transmute(8));
+							gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, vert_size as i32, ptr::null());
+							gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, vert_size as i32, 8 as *const _);
 						}
 
 						let fsquad = [
@@ -194,7 +195,7 @@ impl UIBuilder {
 							Vertex::new(self.viewport.get_bottom_right(), color),
 						];
 
-						gl::BufferData(gl::ARRAY_BUFFER, size_of_val(&fsquad) as isize, transmute(fsquad.as_ptr()), gl::STREAM_DRAW);
+						gl::BufferData(gl::ARRAY_BUFFER, size_of_val(&fsquad) as isize, fsquad.as_ptr() as *const _, gl::STREAM_DRAW);
 						gl::DrawArrays(gl::TRIANGLE_FAN, 0, 4);
 					}
 
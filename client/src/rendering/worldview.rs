@@ -6,30 +6,109 @@ use rendering::parser_3ds::*;
 use rendering::texture::*;
 
 use std::f32::consts::PI;
-use std::mem::{transmute, size_of, size_of_val};
+use std::mem::size_of;
+use std::ptr;
 
 use common::world::*;
+use common::terrain::*;
+use common::fauna::FaunaKind;
 
 use boids::BoidSystem;
 use rendering::boidview::BoidView;
 
-static TERRAIN_VERT_SRC: &'static str = include_str!("../../assets/terrain.vert");
-static TERRAIN_FRAG_SRC: &'static str = include_str!("../../assets/terrain.frag");
-static WORLD_VERT_SRC: &'static str = include_str!("../../assets/world.vert");
-static WORLD_FRAG_SRC: &'static str = include_str!("../../assets/world.frag");
-
-static TREE_MODELS: [&'static [u8]; 4] = [
-	include_bytes!("../../assets/tree0.3ds"),
-	include_bytes!("../../assets/tree1.3ds"),
-	include_bytes!("../../assets/tree2.3ds"),
-	include_bytes!("../../assets/tree3.3ds"),
+static TERRAIN_VERT_SRC: &str = include_str!("../../assets/terrain.vert");
+static TERRAIN_FRAG_SRC: &str = include_str!("../../assets/terrain.frag");
+static WORLD_VERT_SRC: &str = include_str!("../../assets/world.vert");
+static WORLD_FRAG_SRC: &str = include_str!("../../assets/world.frag");
+
+// One model per maturity stage, named so species can refer to them
+static TREE_MODEL_SETS: [(&str, [&[u8]; 4]); 1] = [
+	("broadleaf", [
+		include_bytes!("../../assets/tree0.3ds"),
+		include_bytes!("../../assets/tree1.3ds"),
+		include_bytes!("../../assets/tree2.3ds"),
+		include_bytes!("../../assets/tree3.3ds"),
+	]),
 ];
 
-pub const MAP_SIZE: u32 = 28;
-const MAP_MEM_SIZE: usize = (MAP_SIZE*MAP_SIZE) as usize;
+const CHUNK_MEM_SIZE: usize = CHUNK_SIZE*CHUNK_SIZE;
 
 pub const TILE_SIZE: f32 = 2.0;
+// Height of the highest terrain in tiles
+const ELEVATION_SCALE: f32 = 0.8;
+
+// Tree animation timings in seconds
+const TREE_POP_IN_DURATION: f32 = 0.8;
+const TREE_GROWTH_DURATION: f32 = 1.2;
+const TREE_WILT_DURATION: f32 = 1.5;
+const TREE_FALL_DURATION: f32 = 1.0;
+const TREE_FADE_DURATION: f32 = 1.0;
+const TREE_DEATH_DURATION: f32 = TREE_WILT_DURATION + TREE_FALL_DURATION + TREE_FADE_DURATION;
+
+// Terrain and foliage tints
+pub fn get_season_tint(season: Season) -> (Color, Color) {
+	match season {
+		Season::Spring => (Color::rgb(1.00, 1.05, 0.95), Color::rgb(1.00, 1.05, 1.00)),
+		Season::Summer => (Color::rgb(1.05, 1.00, 0.85), Color::rgb(1.00, 1.00, 0.90)),
+		Season::Autumn => (Color::rgb(1.10, 0.90, 0.70), Color::rgb(1.25, 0.80, 0.50)),
+		Season::Winter => (Color::rgb(0.95, 1.00, 1.10), Color::rgb(0.80, 0.85, 0.90)),
+	}
+}
+
+pub fn get_weather_tint(weather: Weather) -> Color {
+	match weather {
+		Weather::Clear => Color::white(),
+		Weather::Rainstorm => Color::rgb(0.75, 0.80, 0.90),
+		Weather::Drought => Color::rgb(1.10, 0.95, 0.75),
+	}
+}
+
+fn get_affliction_color(color: Color, affliction: Option<(Affliction, f32, bool)>, id: u32, time: f32) -> Color {
+	match affliction {
+		Some((Affliction::Disease, phase, _)) => {
+			let sick = Color::rgb(0.6, 0.55, 0.3);
+			let pulse = (time * 3.0 + id as f32).sin() * 0.1;
+
+			(phase / 4.0 + pulse).clamp(0.0, 0.8).ease_linear(color, sick)
+		}
+
+		Some((Affliction::Fire, _, _)) => {
+			let flicker = (time * 20.0 + id as f32).sin() * 0.5 + 0.5;
+			flicker.ease_linear(Color::rgb(1.0, 0.35, 0.1), Color::rgb(1.0, 0.75, 0.2))
+		}
+
+		None => color,
+	}
+}
+
+// Terrain colors for each soil overlay, from empty to full
+pub fn get_overlay_palette(ch: SoilChannel) -> [Color; 4] {
+	match ch {
+		SoilChannel::Moisture => [
+			Color::rgb(0.780, 0.690, 0.480),
+			Color::rgb(0.560, 0.680, 0.620),
+			Color::rgb(0.300, 0.550, 0.780),
+			Color::rgb(0.150, 0.350, 0.850),
+		],
+
+		SoilChannel::Nitrogen => [
+			Color::rgb(0.850, 0.820, 0.700),
+			Color::rgb(0.700, 0.600, 0.400),
+			Color::rgb(0.500, 0.380, 0.250),
+			Color::rgb(0.320, 0.220, 0.150),
+		],
+
+		SoilChannel::Shade => [
+			Color::rgb(0.950, 0.930, 0.700),
+			Color::rgb(0.650, 0.650, 0.550),
+			Color::rgb(0.400, 0.400, 0.420),
+			Color::rgb(0.200, 0.200, 0.300),
+		],
+	}
+}
 
+// Only read by GL, through the vertex buffer
+#[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
 struct TreeVertex {
 	pos: Vec3,
@@ -51,8 +130,59 @@ struct TreeInstance {
 	pos: Vec3,
 	stage: u8,
 	species: Species,
+	model_set: usize,
+	// [-1, 1] from the tree's genome
+	variation: f32,
 
 	current_health: f32,
+
+	// Affliction, time since afflicted, whether it's where it started
+	affliction: Option<(Affliction, f32, bool)>,
+
+	// Time since the tree was placed
+	age: f32,
+	// Stage being grown out of, and time since the stage changed
+	prev_stage: u8,
+	stage_phase: f32,
+	// Time since the tree died. It's removed once it has faded out
+	death_phase: Option<f32>,
+}
+
+impl TreeInstance {
+	// Which stage's model to draw and how much to scale it by
+	fn get_growth(&self) -> (u8, f32) {
+		let pop_in = (self.age / TREE_POP_IN_DURATION).ease_back_out(0.0, 1.0);
+		let half = TREE_GROWTH_DURATION / 2.0;
+
+		if self.stage_phase < half {
+			// The old model shrinks a little, then the new one springs up in its place
+			(self.prev_stage, (self.stage_phase / half).ease_quad_in(1.0, 0.8) * pop_in)
+		} else {
+			(self.stage, ((self.stage_phase - half) / half).ease_back_out(0.8, 1.0) * pop_in)
+		}
+	}
+
+	// New growth starts out lighter
+	fn get_freshness(&self) -> f32 {
+		(self.stage_phase / TREE_GROWTH_DURATION).ease_quad_out(0.4, 0.0)
+	}
+
+	// How wilted, how far fallen in radians, and opacity
+	fn get_death(&self) -> (f32, f32, f32) {
+		let phase = match self.death_phase {
+			Some(phase) => phase,
+			None => return (0.0, 0.0, 1.0),
+		};
+
+		let fall_phase = phase - TREE_WILT_DURATION;
+		let fade_phase = fall_phase - TREE_FALL_DURATION;
+
+		let wilt = (phase / TREE_WILT_DURATION).ease_quad_out(0.0, 1.0);
+		let fall = (fall_phase / TREE_FALL_DURATION).ease_quad_in(0.0, PI * 0.45);
+		let alpha = (fade_phase / TREE_FADE_DURATION).ease_quad_in(1.0, 0.0);
+
+		(wilt, fall, alpha)
+	}
 }
 
 pub struct WorldView {
@@ -63,12 +193,21 @@ pub struct WorldView {
 
 	world_scale: f32,
 
-	tree_models: [TreeMesh; 4],
-	tree_starts: [i32; 4],
+	// Indexed by model_set*4 + stage
+	tree_models: Vec<TreeMesh>,
+	tree_starts: Vec<i32>,
 	tree_vbo: u32,
 
+	species: SpeciesRegistry,
+
 	trees: Vec<TreeInstance>,
 	translation: Vec3,
+
+	region: Option<Region>,
+
+	// Current and target (terrain, foliage) tints
+	tint: (Color, Color),
+	target_tint: (Color, Color),
 }
 
 static mut TIME: f32 = 0.0;
@@ -76,23 +215,21 @@ static mut TIME: f32 = 0.0;
 impl WorldView {
 	pub fn new() -> WorldView {
 		let world_scale = 1.0 / 7.0;
-		// let world_scale = 1.0 / (MAP_SIZE as f32 - 1.0) * 2.0f32.sqrt();
+		// let world_scale = 1.0 / (DEFAULT_WORLD_SIZE as f32 - 1.0) * 2.0f32.sqrt();
 
 		let mut view = WorldView {
-			shader: Shader::new(&WORLD_VERT_SRC, &WORLD_FRAG_SRC),
+			shader: Shader::new(WORLD_VERT_SRC, WORLD_FRAG_SRC),
 			terrain: TerrainView::new(),
 
-			boids: BoidSystem::new(Vec2::splat(MAP_SIZE as f32 * TILE_SIZE)),
+			boids: BoidSystem::new(),
 			boidview: BoidView::new(),
 
-			tree_models: [
-				process_tree_mesh(parse_3ds(&TREE_MODELS[0]).unwrap()),
-				process_tree_mesh(parse_3ds(&TREE_MODELS[1]).unwrap()),
-				process_tree_mesh(parse_3ds(&TREE_MODELS[2]).unwrap()),
-				process_tree_mesh(parse_3ds(&TREE_MODELS[3]).unwrap())
-			],
+			tree_models: TREE_MODEL_SETS.iter()
+				.flat_map(|(_, models)| models.iter())
+				.map(|m| process_tree_mesh(parse_3ds(m).unwrap()))
+				.collect(),
 
-			tree_starts: [0i32; 4],
+			tree_starts: vec![0i32; TREE_MODEL_SETS.len() * 4],
 
 			tree_vbo: unsafe {
 				let mut vbo = 0u32;
@@ -100,30 +237,56 @@ impl WorldView {
 				vbo
 			},
 
-			// Center the starting view
-			translation: Vec3::new(-(MAP_SIZE as f32 - 1.0) * 2.0f32.sqrt() * TILE_SIZE * world_scale/2.0, 0.0, 0.0),
+			// Center the starting view. Worlds are usually the default size
+			translation: Vec3::new(-(DEFAULT_WORLD_SIZE as f32 - 1.0) * 2.0f32.sqrt() * TILE_SIZE * world_scale/2.0, 0.0, 0.0),
 			trees: Vec::new(),
+			region: None,
+
+			tint: (Color::white(), Color::white()),
+			target_tint: (Color::white(), Color::white()),
+
+			species: SpeciesRegistry::empty(),
 
 			world_scale,
 		};
 
 		view.build_tree_buffer();
-
-		for _ in 0..30 {
-			view.boids.update(1.0/2.0);
-		}
-
 		view
 	}
 
 	pub fn update(&mut self, dt: f32) {
+		if self.terrain.health_dirty {
+			self.terrain.build_health_vbos();
+		}
+
 		self.boids.update(dt);
+
+		for boid in self.boids.get_boids_mut().iter_mut() {
+			boid.ground = self.terrain.get_height_at(boid.pos * (1.0 / TILE_SIZE)) * TILE_SIZE;
+		}
+
 		self.boidview.update(&self.boids);
 
+		self.tint.0 = (dt/4.0).ease_linear(self.tint.0, self.target_tint.0);
+		self.tint.1 = (dt/4.0).ease_linear(self.tint.1, self.target_tint.1);
+
 		for tree in self.trees.iter_mut() {
 			let health = self.terrain.get_health_at(Vec2::new(tree.pos.x, tree.pos.z));
 			tree.current_health = (dt/16.0).ease_linear(tree.current_health, health);
+
+			if let Some((_, ref mut phase, _)) = tree.affliction {
+				*phase += dt;
+			}
+
+			if let Some(ref mut phase) = tree.death_phase {
+				*phase += dt;
+			}
+
+			tree.age += dt;
+			tree.stage_phase += dt;
 		}
+
+		self.trees.retain(|t| t.death_phase.is_none_or(|p| p < TREE_DEATH_DURATION));
 	}
 
 	pub fn render(&mut self, vp: &Viewport) {
@@ -147,12 +310,12 @@ impl WorldView {
 		let normal_mat = Mat4::yrot(-yrotph);
 
 		unsafe {
-			let clear_col = Color::rgb(0.4, 0.808, 0.58).pow(1.0/2.2);
+			let clear_col = Color::rgb(0.4, 0.808, 0.58).tint(self.tint.0).pow(1.0/2.2);
 			gl::ClearColor(clear_col.r, clear_col.g, clear_col.b, 1.0);
 			gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 		}
 
-		self.terrain.render(&world_mat);
+		self.terrain.render(&world_mat, self.tint.0);
 
 		self.shader.use_program();
 		self.shader.set_uniform_mat("normal_xform", &normal_mat);
@@ -166,15 +329,32 @@ impl WorldView {
 			let vert_size = size_of::<TreeVertex>() as isize;
 
 			gl::BindBuffer(gl::ARRAY_BUFFER, self.tree_vbo);
-			gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, vert_size as i32, transmute(0));
-			gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, vert_size as i32, transmute(size_of::<Vec3>()));
+			gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, vert_size as i32, ptr::null());
+			gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, vert_size as i32, size_of::<Vec3>() as *const _);
 
 			let trunk_color = Color::rgb(0.8, 0.411, 0.22);
 
+			let wilt_color = Color::rgb(0.45, 0.35, 0.2);
+			let fresh_color = Color::rgb(0.8, 1.0, 0.6);
+
 			for tree in &self.trees {
-				self.shader.set_view(&(world_mat * Mat4::translate(tree.pos * TILE_SIZE)));
+				let (stage, growth_scale) = tree.get_growth();
+				let (wilt, fall, alpha) = tree.get_death();
 
-				let tree_idx = tree.stage as usize;
+				// Trees where an outbreak or fire started swell briefly
+				let scale = match tree.affliction {
+					Some((_, phase, true)) if phase < 1.0 => 1.0 + (phase * PI).sin() * 0.3,
+					_ => 1.0,
+				} * growth_scale;
+
+				// Topple in a direction that varies between trees
+				let fall_dir = tree.id as f32 * 2.4;
+				let fall_mat = Mat4::yrot(fall_dir) * Mat4::xrot(fall) * Mat4::yrot(-fall_dir);
+
+				self.shader.set_view(&(world_mat * Mat4::translate(tree.pos * TILE_SIZE) * fall_mat * Mat4::uniform_scale(scale)));
+				self.shader.set_uniform_f32("alpha", alpha);
+
+				let tree_idx = tree.model_set * 4 + stage.min(3) as usize;
 
 				let trunk_count = self.tree_models[tree_idx].trunk_count;
 				let leafage_count = self.tree_models[tree_idx].leafage_count;
@@ -185,13 +365,16 @@ impl WorldView {
 
 				if trunk_count > 0 {
 					self.shader.set_uniform_vec3("color", &trunk_color.to_vec3());
-					gl::DrawArrays(gl::TRIANGLES, base_start + trunk_start as i32 * 3, trunk_count as i32 * 3);
+					gl::DrawArrays(gl::TRIANGLES, base_start + trunk_start * 3, trunk_count * 3);
 				}
 
 				if leafage_count > 0 {
-					let color = WorldView::get_tree_color(tree.current_health, tree.species);
+					let color = self.get_tree_color(tree.current_health, tree.species, tree.variation).tint(self.tint.1);
+					let color = get_affliction_color(color, tree.affliction, tree.id, ph);
+					let color = tree.get_freshness().ease_linear(color, fresh_color);
+					let color = wilt.ease_linear(color, wilt_color);
 					self.shader.set_uniform_vec3("color", &color.to_vec3());
-					gl::DrawArrays(gl::TRIANGLES, base_start + leafage_start as i32 * 3, leafage_count as i32 * 3);
+					gl::DrawArrays(gl::TRIANGLES, base_start + leafage_start * 3, leafage_count * 3);
 				}
 			}
 
@@ -214,7 +397,7 @@ impl WorldView {
 
 			gl::BindBuffer(gl::ARRAY_BUFFER, self.tree_vbo);
 			gl::BufferData(gl::ARRAY_BUFFER, verts.len() as isize * vert_size,
-				transmute(verts.as_ptr()), gl::STATIC_DRAW);
+				verts.as_ptr() as *const _, gl::STATIC_DRAW);
 
 			gl::BindBuffer(gl::ARRAY_BUFFER, 0);
 		}
@@ -237,62 +420,195 @@ impl WorldView {
 		normal_mat * ((Vec3::new(x, 0.0, y) - self.translation) * Vec3::new(1.0/sc, 1.0, 1.0/(sc*xrot.sin())))
 	}
 
-	pub fn place_tree(&mut self, id: u32, pos: Vec3, species: Species) {
+	pub fn get_visible_region(&self, vp: &Viewport) -> Region {
+		let corners = [
+			vp.get_top_left(),
+			vp.get_top_right(),
+			vp.get_bottom_left(),
+			vp.get_bottom_right(),
+		];
+
+		let (mut min, mut max) = (Vec2::splat(f32::INFINITY), Vec2::splat(-f32::INFINITY));
+
+		for &c in corners.iter() {
+			let p = self.convert_to_world_coords(c);
+			min = Vec2::new(min.x.min(p.x), min.y.min(p.z));
+			max = Vec2::new(max.x.max(p.x), max.y.max(p.z));
+		}
+
+		// Some slack so trees poking in from just outside the view don't pop
+		let margin = Vec2::splat(1.0);
+		let terrain = &self.terrain.terrain;
+		let limit = Vec2::new(terrain.width as f32 - 1.0, terrain.height as f32 - 1.0);
+		let clamp = |p: Vec2| Vec2::new(p.x.max(0.0).min(limit.x), p.y.max(0.0).min(limit.y));
+
+		Region::new(chunk_for_pos(clamp(min - margin)), chunk_for_pos(clamp(max + margin)))
+	}
+
+	// Returns whether the region changed
+	pub fn set_region(&mut self, region: Region) -> bool {
+		if self.region == Some(region) { return false }
+
+		self.region = Some(region);
+		self.terrain.retain_region(region);
+		self.trees.retain(|t| region.contains(chunk_for_pos(Vec2::new(t.pos.x, t.pos.z))));
+
+		true
+	}
+
+	pub fn clear_region(&mut self) {
+		self.region = None;
+		self.terrain.chunks.clear();
+		self.terrain.health_dirty = true;
+		self.trees.clear();
+	}
+
+	pub fn place_tree(&mut self, id: u32, pos: Vec3, species: Species, variation: f32) {
+		let in_region = self.region.is_some_and(|r| r.contains(chunk_for_pos(Vec2::new(pos.x, pos.z))));
+		if !in_region || self.trees.iter().any(|t| t.id == id) { return }
+
+		let model_set = self.get_model_set(species);
+		let pos = Vec3::new(pos.x, self.terrain.get_height_at(Vec2::new(pos.x, pos.z)), pos.z);
+
 		self.trees.push(TreeInstance {
 			id, pos,
-			stage: 0, species,
+			stage: 0, species, model_set, variation,
 			current_health: self.terrain.get_health_at(Vec2::new(pos.x, pos.z)),
+			affliction: None,
+
+			age: 0.0,
+			prev_stage: 0,
+			stage_phase: TREE_GROWTH_DURATION,
+			death_phase: None,
 		});
 	}
 
 	pub fn set_tree_stage(&mut self, id: u32, stage: u8) {
-		for tree in self.trees.iter_mut() {
-			if tree.id == id { tree.stage = stage; } 
+		for tree in self.trees.iter_mut().filter(|t| t.id == id && t.stage != stage) {
+			if tree.death_phase.is_some() { continue }
+
+			// Trees that have only just appeared are likely catching up on their stage,
+			// so hide it behind the pop in
+			if tree.age < TREE_POP_IN_DURATION {
+				tree.prev_stage = stage;
+			} else {
+				tree.prev_stage = tree.stage;
+				tree.stage_phase = 0.0;
+			}
+
+			tree.stage = stage;
+		}
+	}
+
+	pub fn afflict_tree(&mut self, id: u32, affliction: Affliction, origin: bool) {
+		for tree in self.trees.iter_mut().filter(|t| t.id == id) {
+			tree.affliction = Some((affliction, 0.0, origin));
 		}
 	}
 
+	// pos is in tiles
+	pub fn get_tree_near(&self, pos: Vec2, range: f32) -> Option<u32> {
+		self.trees.iter()
+			.filter(|t| t.death_phase.is_none())
+			.map(|t| (t.id, (Vec2::new(t.pos.x, t.pos.z) - pos).length()))
+			.filter(|&(_, d)| d < range)
+			.fold(None, |acc: Option<(u32, f32)>, (id, d)| match acc {
+				Some((_, ad)) if ad <= d => acc,
+				_ => Some((id, d)),
+			})
+			.map(|(id, _)| id)
+	}
+
+	// Trees wilt, fall and fade before they're removed
 	pub fn kill_tree(&mut self, id: u32) {
-		self.trees.retain(|tree| tree.id != id);
-	}
-
-	fn get_tree_color(health: f32, species: Species) -> Color {
-		let pal = match species {
-			Species::A => [
-				Color::rgb(0.778, 0.895, 0.241),
-				Color::rgb(0.593, 0.928, 0.257),
-				Color::rgb(0.348, 0.800, 0.185),
-				Color::rgb(0.197, 0.800, 0.202),
-			],
-
-			Species::B => [
-				Color::rgb(0.6, 0.7, 0.8),
-				Color::rgb(0.5, 0.7, 0.9),
-				Color::rgb(0.4, 0.6, 0.9),
-				Color::rgb(0.4, 0.6, 1.0),
-			],
-
-			Species::C => [
-				Color::rgb(0.8, 0.8, 0.7),
-				Color::rgb(0.9, 0.8, 0.6),
-				Color::rgb(1.0, 0.7, 0.5),
-				Color::rgb(1.0, 0.5, 0.5),
-			],
+		for tree in self.trees.iter_mut().filter(|t| t.id == id) {
+			if tree.death_phase.is_none() {
+				tree.death_phase = Some(0.0);
+			}
+		}
+	}
+
+	pub fn set_species_registry(&mut self, registry: SpeciesRegistry) {
+		for info in registry.species.iter() {
+			if !TREE_MODEL_SETS.iter().any(|&(n, _)| n == info.model) {
+				println!("Unknown tree model '{}' for species '{}', falling back to '{}'",
+					info.model, info.name, TREE_MODEL_SETS[0].0);
+			}
+		}
+
+		self.species = registry;
+
+		for i in 0..self.trees.len() {
+			let species = self.trees[i].species;
+			self.trees[i].model_set = self.get_model_set(species);
+		}
+	}
+
+	pub fn species_count(&self) -> usize {
+		self.species.len()
+	}
+
+	fn get_model_set(&self, species: Species) -> usize {
+		let name = match self.species.get(species) {
+			Some(info) => info.model.as_str(),
+			None => return 0,
+		};
+
+		TREE_MODEL_SETS.iter()
+			.position(|&(n, _)| n == name)
+			.unwrap_or(0)
+	}
+
+	fn get_tree_color(&self, health: f32, species: Species, variation: f32) -> Color {
+		let pal: Vec<Color> = match self.species.get(species) {
+			Some(info) => info.palette.iter().map(|c| Color::rgb(c[0], c[1], c[2])).collect(),
+			None => vec![Color::grey(0.5)],
 		};
 
 		let real_idx = health * pal.len() as f32;
 		let real_next = real_idx + 1.0;
 
-		let pal_idx = (real_idx as usize).max(0).min(pal.len()-1);
-		let pal_next = (real_next as usize).max(0).min(pal.len()-1);
+		let pal_idx = (real_idx as usize).min(pal.len()-1);
+		let pal_next = (real_next as usize).min(pal.len()-1);
 
 		let col_a = pal[pal_idx];
 		let col_b = pal[pal_next];
-		return real_idx.fract().ease_linear(col_a, col_b);
+		let variation = Color::rgb(1.0 + 0.15*variation, 1.0, 1.0 - 0.15*variation);
+		real_idx.fract().ease_linear(col_a, col_b).tint(variation)
 	}
 
-	pub fn update_health_state(&mut self, hs: Vec<u8>) {
-		self.boids.update_health_state(&hs);
-		self.terrain.update_health_state(hs);
+	pub fn update_health_state(&mut self, coord: ChunkCoord, hs: Vec<u8>) {
+		if !self.region.is_some_and(|r| r.contains(coord)) { return }
+		self.terrain.update_health_state(coord, hs);
+	}
+
+	pub fn set_climate(&mut self, season: Season, weather: Weather) {
+		let (terrain, foliage) = get_season_tint(season);
+		let weather = get_weather_tint(weather);
+
+		self.target_tint = (terrain.tint(weather), foliage.tint(weather));
+	}
+
+	pub fn set_terrain(&mut self, terrain: Terrain) {
+		self.terrain.set_terrain(terrain);
+
+		for tree in self.trees.iter_mut() {
+			tree.pos.y = self.terrain.get_height_at(Vec2::new(tree.pos.x, tree.pos.z));
+		}
+	}
+
+	pub fn set_overlay(&mut self, overlay: Option<SoilChannel>) {
+		self.terrain.set_overlay(overlay);
+	}
+
+	pub fn update_soil_state(&mut self, coord: ChunkCoord, ch: SoilChannel, ss: Vec<u8>) {
+		if !self.region.is_some_and(|r| r.contains(coord)) { return }
+		if self.terrain.overlay != Some(ch) { return }
+		self.terrain.update_soil_state(coord, ss);
+	}
+
+	pub fn update_fauna(&mut self, animals: Vec<(u32, FaunaKind, Vec2, Vec2)>) {
+		self.boids.sync(animals);
 	}
 
 	pub fn update_tree_maturities(&mut self, ts: Vec<(u32, u8)>) {
@@ -302,21 +618,92 @@ impl WorldView {
 	}
 }
 
+struct TerrainChunk {
+	coord: ChunkCoord,
+	health_state: Vec<u8>,
+	// Values for the current overlay, if any
+	soil_state: Vec<u8>,
+
+	vbo: u32,
+	health_vbo: u32,
+}
+
 struct TerrainView {
 	shader: Shader,
 	terrain_palette: Texture,
+	// Indexed by SoilChannel
+	overlay_palettes: Vec<Texture>,
+	overlay: Option<SoilChannel>,
+
+	terrain: Terrain,
+	chunks: Vec<TerrainChunk>,
+	health_dirty: bool,
 
-	health_state: Vec<u8>,
-	
-	health_vbo: u32,
-	vbo: u32,
 	ebo: u32,
 }
 
+impl TerrainChunk {
+	fn new(coord: ChunkCoord, terrain: &Terrain) -> TerrainChunk {
+		let mut bufs = [0u32; 2];
+		unsafe{ gl::GenBuffers(2, bufs.as_mut_ptr()); }
+
+		let mut chunk = TerrainChunk {
+			coord,
+			health_state: vec![0; CHUNK_MEM_SIZE],
+			soil_state: vec![0; CHUNK_MEM_SIZE],
+
+			vbo: bufs[0],
+			health_vbo: bufs[1],
+		};
+
+		chunk.build_main_buffer(terrain);
+		chunk
+	}
+
+	fn get_base(&self) -> (u32, u32) {
+		(self.coord.0 as u32 * CHUNK_SIZE as u32, self.coord.1 as u32 * CHUNK_SIZE as u32)
+	}
+
+	// position, then (water, rock) for each vertex
+	fn build_main_buffer(&mut self, terrain: &Terrain) {
+		let mut vs: Vec<f32> = Vec::new();
+		let (bx, by) = self.get_base();
+
+		let corners = [(0, 1), (0, 0), (1, 0), (1, 1)];
+
+		for y in by..by + CHUNK_SIZE as u32 {
+			for x in bx..bx + CHUNK_SIZE as u32 {
+				let kind = terrain.get(x as usize, y as usize).map(|t| t.kind);
+				let water = if kind == Some(TileKind::Water) { 1.0 } else { 0.0 };
+				let rock = if kind == Some(TileKind::Rock) { 1.0 } else { 0.0 };
+
+				for &(cx, cy) in corners.iter() {
+					let (cx, cy) = (x as i32 + cx, y as i32 + cy);
+					let pos = Vec3::new(cx as f32 - 0.5, get_corner_height(terrain, cx, cy), cy as f32 - 0.5) * TILE_SIZE;
+
+					vs.extend_from_slice(&[pos.x, pos.y, pos.z, water, rock]);
+				}
+			}
+		}
+
+		unsafe {
+			gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+			gl::BufferData(gl::ARRAY_BUFFER, vs.len() as isize * 4, vs.as_ptr() as *const _, gl::STATIC_DRAW);
+		}
+	}
+}
+
+impl Drop for TerrainChunk {
+	fn drop(&mut self) {
+		let bufs = [self.vbo, self.health_vbo];
+		unsafe{ gl::DeleteBuffers(2, bufs.as_ptr()); }
+	}
+}
+
 impl TerrainView {
 	fn new() -> TerrainView {
-		let mut bufs = [0u32; 3];
-		unsafe{ gl::GenBuffers(3, bufs.as_mut_ptr()); }
+		let mut ebo = 0u32;
+		unsafe{ gl::GenBuffers(1, &mut ebo); }
 
 		let pal = [
 			Color::rgb(0.633, 0.825, 0.250),
@@ -331,114 +718,228 @@ impl TerrainView {
 
 		terrain_palette.upload_1d(&pal);
 
+		let overlay_palettes = ALL_SOIL_CHANNELS.iter()
+			.map(|&ch| {
+				let mut tex = TextureBuilder::new()
+					.linear_magnify()
+					.finalize();
+
+				tex.upload_1d(&get_overlay_palette(ch));
+				tex
+			})
+			.collect();
+
 		let mut view = TerrainView {
-			shader: Shader::new(&TERRAIN_VERT_SRC, &TERRAIN_FRAG_SRC),
+			shader: Shader::new(TERRAIN_VERT_SRC, TERRAIN_FRAG_SRC),
 			terrain_palette,
+			overlay_palettes,
+			overlay: None,
 
-			health_state: vec![0; MAP_MEM_SIZE],
-			
-			health_vbo: bufs[0],
-			vbo: bufs[1],
-			ebo: bufs[2],
+			terrain: Terrain::flat(DEFAULT_WORLD_SIZE, DEFAULT_WORLD_SIZE),
+			chunks: Vec::new(),
+			health_dirty: false,
+
+			ebo,
 		};
 
-		view.build_main_buffers();
-		view.build_health_vbo();
+		view.build_index_buffer();
 		view
 	}
 
+	fn sample_chunks<F>(&self, p: Vec2, f: F) -> f32 where F: Fn(&TerrainChunk) -> &Vec<u8> {
+		let y = (p.y.max(0.0) as usize).min(self.terrain.height - 1);
+		let x = (p.x.max(0.0) as usize).min(self.terrain.width - 1);
+
+		let coord = ((x / CHUNK_SIZE) as u16, (y / CHUNK_SIZE) as u16);
+
+		match self.chunks.iter().find(|c| c.coord == coord) {
+			Some(chunk) => f(chunk)[x % CHUNK_SIZE + (y % CHUNK_SIZE) * CHUNK_SIZE] as f32 / 255.0,
+			None => 0.0,
+		}
+	}
+
+	// Interpolated from the corners of the tile p lies in
+	fn get_height_at(&self, p: Vec2) -> f32 {
+		let (u, v) = (p.x + 0.5, p.y + 0.5);
+		let (x, y) = (u.floor(), v.floor());
+		let (fx, fy) = (u - x, v - y);
+		let (x, y) = (x as i32, y as i32);
+
+		let top = fx.ease_linear(get_corner_height(&self.terrain, x, y), get_corner_height(&self.terrain, x+1, y));
+		let bottom = fx.ease_linear(get_corner_height(&self.terrain, x, y+1), get_corner_height(&self.terrain, x+1, y+1));
+
+		fy.ease_linear(top, bottom)
+	}
+
 	fn get_health_at(&self, p: Vec2) -> f32 {
-		let y = (p.y as usize).min(MAP_SIZE as usize - 1);
-		let x = (p.x as usize).min(MAP_SIZE as usize - 1);
+		self.sample_chunks(p, |c| &c.health_state)
+	}
 
-		self.health_state[x + y * MAP_SIZE as usize] as f32 / 255.0
+	// What the terrain should show at p - either health or the overlay channel
+	fn get_display_value_at(&self, p: Vec2) -> f32 {
+		if self.overlay.is_some() {
+			self.sample_chunks(p, |c| &c.soil_state)
+		} else {
+			self.get_health_at(p)
+		}
 	}
 
-	fn build_main_buffers(&mut self) {
-		let mut vs = Vec::new();
+	// All chunks share the same index layout
+	fn build_index_buffer(&mut self) {
 		let mut es: Vec<u16> = Vec::new();
 
-		for y in 0..MAP_SIZE {
-			for x in 0..MAP_SIZE {
-				let vsbase = vs.len() as u16;
-				vs.push((Vec3::new(-0.5, 0.0, 0.5) + Vec3::new(x as f32, 0.0, y as f32)) * TILE_SIZE);
-				vs.push((Vec3::new(-0.5, 0.0,-0.5) + Vec3::new(x as f32, 0.0, y as f32)) * TILE_SIZE);
-				vs.push((Vec3::new( 0.5, 0.0,-0.5) + Vec3::new(x as f32, 0.0, y as f32)) * TILE_SIZE);
-				vs.push((Vec3::new( 0.5, 0.0, 0.5) + Vec3::new(x as f32, 0.0, y as f32)) * TILE_SIZE);
-
-				es.extend(&[
-					vsbase + 0, vsbase + 1, vsbase + 2,
-					vsbase + 0, vsbase + 2, vsbase + 3
-				]);
-			}
+		for i in 0..CHUNK_MEM_SIZE as u16 {
+			let vsbase = i * 4;
+
+			es.extend(&[
+				vsbase, vsbase + 1, vsbase + 2,
+				vsbase, vsbase + 2, vsbase + 3
+			]);
 		}
 
 		unsafe {
-			let vert_size = (3*4) as isize;
-
 			gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebo);
-			gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (es.len()*2) as isize, transmute(es.as_ptr()), gl::STATIC_DRAW);
-
-			gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-			gl::BufferData(gl::ARRAY_BUFFER, vs.len() as isize * vert_size, transmute(vs.as_ptr()), gl::STATIC_DRAW);
+			gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (es.len()*2) as isize, es.as_ptr() as *const _, gl::STATIC_DRAW);
 		}
 	}
 
-	fn build_health_vbo(&mut self) {
-		assert!(self.health_state.len() == MAP_MEM_SIZE);
-
-		let mut data = Vec::with_capacity(MAP_MEM_SIZE*4);
-
+	fn build_health_vbos(&mut self) {
 		let s = 1.0/2.0;
 
-		for y in 0..MAP_SIZE {
-			for x in 0..MAP_SIZE {
-				let (x,y) = (x as f32, y as f32);
-				data.push(self.get_health_at(Vec2::new(x-s, y+s)));
-				data.push(self.get_health_at(Vec2::new(x-s, y-s)));
-				data.push(self.get_health_at(Vec2::new(x+s, y-s)));
-				data.push(self.get_health_at(Vec2::new(x+s, y+s)));
+		for chunk in self.chunks.iter() {
+			let mut data = Vec::with_capacity(CHUNK_MEM_SIZE*4);
+			let (bx, by) = chunk.get_base();
+
+			for y in by..by + CHUNK_SIZE as u32 {
+				for x in bx..bx + CHUNK_SIZE as u32 {
+					let (x,y) = (x as f32, y as f32);
+					data.push(self.get_display_value_at(Vec2::new(x-s, y+s)));
+					data.push(self.get_display_value_at(Vec2::new(x-s, y-s)));
+					data.push(self.get_display_value_at(Vec2::new(x+s, y-s)));
+					data.push(self.get_display_value_at(Vec2::new(x+s, y+s)));
+				}
 			}
-		}
 
-		unsafe {
-			gl::BindBuffer(gl::ARRAY_BUFFER, self.health_vbo);
-			gl::BufferData(gl::ARRAY_BUFFER, data.len() as isize * 4, transmute(data.as_ptr()), gl::STREAM_DRAW);
+			unsafe {
+				gl::BindBuffer(gl::ARRAY_BUFFER, chunk.health_vbo);
+				gl::BufferData(gl::ARRAY_BUFFER, data.len() as isize * 4, data.as_ptr() as *const _, gl::STREAM_DRAW);
+			}
 		}
+
+		self.health_dirty = false;
 	}
 
-	fn render(&mut self, world_mat: &Mat4) {
+	fn render(&mut self, world_mat: &Mat4, tint: Color) {
+		// Overlays should be read as-is
+		let tint = if self.overlay.is_some() { Color::white() } else { tint };
+
 		self.shader.use_program();
-		self.shader.set_view(&world_mat);
+		self.shader.set_uniform_vec3("tint", &tint.to_vec3());
+		self.shader.set_view(world_mat);
 		self.shader.set_uniform_mat("normal_xform", &Mat4::yrot(-PI/4.0));
 
-		let vert_size = size_of::<Vec3>() as i32;
+		let vert_size = 5*4;
 
 		unsafe {
 			gl::EnableVertexAttribArray(0);
 			gl::EnableVertexAttribArray(1);
+			gl::EnableVertexAttribArray(2);
+
+			match self.overlay {
+				Some(ch) => self.overlay_palettes[ch as usize].bind_to_slot(0),
+				None => self.terrain_palette.bind_to_slot(0),
+			}
 
-			self.terrain_palette.bind_to_slot(0);
 			self.shader.set_uniform_i32("health_lut", 0);
 
 			gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebo);
 
-			gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-			gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, vert_size, transmute(0));
+			for chunk in self.chunks.iter() {
+				gl::BindBuffer(gl::ARRAY_BUFFER, chunk.vbo);
+				gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, vert_size, ptr::null());
+				gl::VertexAttribPointer(2, 2, gl::FLOAT, gl::FALSE, vert_size, size_of::<Vec3>() as *const _);
 
-			gl::BindBuffer(gl::ARRAY_BUFFER, self.health_vbo);
-			gl::VertexAttribPointer(1, 1, gl::FLOAT, gl::FALSE, 4, transmute(0));
+				gl::BindBuffer(gl::ARRAY_BUFFER, chunk.health_vbo);
+				gl::VertexAttribPointer(1, 1, gl::FLOAT, gl::FALSE, 4, ptr::null());
 
-			gl::DrawElements(gl::TRIANGLES, (MAP_SIZE * MAP_SIZE) as i32 * 6, gl::UNSIGNED_SHORT, transmute(0));
+				gl::DrawElements(gl::TRIANGLES, CHUNK_MEM_SIZE as i32 * 6, gl::UNSIGNED_SHORT, ptr::null());
+			}
 
+			gl::DisableVertexAttribArray(2);
 			gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
 		}
 	}
 
-	fn update_health_state(&mut self, hs: Vec<u8>) {
-		if hs.len() != MAP_MEM_SIZE { return }
-		self.health_state = hs;
-		self.build_health_vbo();
+	fn update_health_state(&mut self, coord: ChunkCoord, hs: Vec<u8>) {
+		if hs.len() != CHUNK_MEM_SIZE { return }
+
+		if let Some(chunk) = self.chunks.iter_mut().find(|c| c.coord == coord) {
+			chunk.health_state = hs;
+			self.health_dirty = true;
+			return
+		}
+
+		let mut chunk = TerrainChunk::new(coord, &self.terrain);
+		chunk.health_state = hs;
+		self.chunks.push(chunk);
+		self.health_dirty = true;
+	}
+
+	fn update_soil_state(&mut self, coord: ChunkCoord, ss: Vec<u8>) {
+		if ss.len() != CHUNK_MEM_SIZE { return }
+
+		if let Some(chunk) = self.chunks.iter_mut().find(|c| c.coord == coord) {
+			chunk.soil_state = ss;
+			self.health_dirty = true;
+		}
+	}
+
+	fn set_terrain(&mut self, terrain: Terrain) {
+		self.terrain = terrain;
+
+		for chunk in self.chunks.iter_mut() {
+			chunk.build_main_buffer(&self.terrain);
+		}
+	}
+
+	fn set_overlay(&mut self, overlay: Option<SoilChannel>) {
+		if self.overlay == overlay { return }
+
+		self.overlay = overlay;
+
+		for chunk in self.chunks.iter_mut() {
+			chunk.soil_state = vec![0; CHUNK_MEM_SIZE];
+		}
+
+		self.health_dirty = true;
+	}
+
+	fn retain_region(&mut self, region: Region) {
+		self.chunks.retain(|c| region.contains(c.coord));
+		self.health_dirty = true;
+	}
+}
+
+// Corner (x, y) is shared by tiles (x-1, y-1) through (x, y)
+fn get_corner_height(terrain: &Terrain, x: i32, y: i32) -> f32 {
+	let mut total = 0.0;
+	let mut count = 0;
+
+	for ty in y-1 .. y+1 {
+		for tx in x-1 .. x+1 {
+			if tx < 0 || ty < 0 { continue }
+
+			if let Some(tile) = terrain.get(tx as usize, ty as usize) {
+				total += tile.elevation;
+				count += 1;
+			}
+		}
+	}
+
+	if count > 0 {
+		total / count as f32 * ELEVATION_SCALE
+	} else {
+		0.0
 	}
 }
 
@@ -456,7 +957,7 @@ fn process_tree_mesh(mesh: Mesh3DS) -> TreeMesh {
 	let overts = mesh.verts;
 	let elems = mesh.elements;
 
-	for &FaceMaterial(ref name, ref faces) in &mesh.face_materials {
+	for FaceMaterial(name, faces) in &mesh.face_materials {
 		let start = *faces.first().unwrap();
 		for (i, &f) in faces.iter().enumerate() {
 			assert!(i as u16 + start == f);
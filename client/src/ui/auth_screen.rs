@@ -49,6 +49,7 @@ struct StatusRing {
 pub enum Action {
 	RequestNewSession,
 	TryAuth(u32),
+	PlayOffline,
 	EnterGame,
 }
 
@@ -58,6 +59,7 @@ pub struct AuthScreen {
 
 	pub viewport: Viewport,
 	download_button_pos: Vec2,
+	offline_button_pos: Vec2,
 
 	action: Option<Action>,
 
@@ -72,6 +74,7 @@ impl AuthScreen {
 
 			viewport: Viewport::new(),
 			download_button_pos: Vec2::zero(),
+			offline_button_pos: Vec2::zero(),
 
 			action: None,
 
@@ -84,6 +87,12 @@ impl AuthScreen {
 		self.hide_anim_phase = Some(-0.5);
 	}
 
+	// Same as a successful auth, minus the server
+	pub fn on_offline(&mut self) {
+		self.status_ring.start_animation(StatusAnimation::Success);
+		self.hide_anim_phase = Some(-0.5);
+	}
+
 	pub fn on_auth_fail(&mut self) {
 		self.status_ring.start_animation(StatusAnimation::Fail);
 	}
@@ -108,6 +117,7 @@ impl AuthScreen {
 		self.status_ring.update(dt);
 
 		self.download_button_pos = self.viewport.get_bottom_left() + Vec2::new(0.14, 0.14);
+		self.offline_button_pos = self.viewport.get_bottom_right() + Vec2::new(-0.14, 0.14);
 
 		self.hide_anim_phase = match self.hide_anim_phase {
 			Some(phase) if phase < 1.0 => Some(phase + dt),
@@ -121,13 +131,16 @@ impl AuthScreen {
 		}
 	}
 
-	pub fn render(&self, mut builder: &mut UIBuilder) {
+	pub fn render(&self, builder: &mut UIBuilder) {
 		// TODO: some animation w/ self.hide_anim_phase
 
-		self.key_ring.render(&mut builder);
-		self.status_ring.render(&mut builder);
+		self.key_ring.render(builder);
+		self.status_ring.render(builder);
 
 		builder.build_poly(self.download_button_pos, Color::grey(0.3), 15, 0.1);
+
+		builder.build_poly(self.offline_button_pos, Color::grey(0.3), 15, 0.1);
+		builder.build_poly(self.offline_button_pos, Color::rgb(0.3, 0.8, 0.6), 3, 0.05);
 	}
 
 	pub fn download_key(&self) {
@@ -160,7 +173,7 @@ impl AuthScreen {
 
 impl InputTarget for AuthScreen {
 	fn on_drag_start(&mut self, pos: Vec2) {
-		if self.status_ring.try_start_drag(pos) { return }
+		self.status_ring.try_start_drag(pos);
 	}
 
 	fn on_drag_end(&mut self, pos: Vec2) {
@@ -189,6 +202,10 @@ impl InputTarget for AuthScreen {
 			self.download_key();
 		}
 
+		if (self.offline_button_pos - click_pos).length() < 0.1 && self.hide_anim_phase.is_none() {
+			self.action = Some(Action::PlayOffline);
+		}
+
 		// if (self.viewport.get_top_left() - click_pos).length() < 0.1 {
 		// 	use rand;
 
@@ -463,8 +480,7 @@ impl KeyRing {
 	}
 
 	fn calculate_key(&self) -> u32 {
-		use std;
-		assert!(KEY_BASE.pow(KEY_LENGTH) < std::u32::MAX);
+		assert!(KEY_BASE.pow(KEY_LENGTH) < u32::MAX);
 
 		self.tumblers.iter().enumerate().fold(0, |acc, (i, th)| {
 			assert!((th.state as u32) < KEY_BASE);
@@ -477,7 +493,7 @@ impl KeyRing {
 		let max_key = KEY_BASE.pow(KEY_LENGTH);
 		assert!(key < max_key);
 
-		for (i, mut th) in self.tumblers.iter_mut().enumerate().rev() {
+		for (i, th) in self.tumblers.iter_mut().enumerate().rev() {
 			let factor = KEY_BASE.pow(i as u32);
 			let place = key/factor;
 			key -= place * factor;
@@ -2,21 +2,27 @@ use rendering::*;
 use rendering::uibuilder::*;
 use ui::InputTarget;
 
-use std;
-
-use common::world::Species;
+use common::world::{Species, SoilChannel, ALL_SOIL_CHANNELS, Genome, GROWTH_GENE_RANGE, LIFESPAN_GENE_RANGE};
+use common::stats::WorldStats;
+use rendering::worldview::get_overlay_palette;
 
 #[derive(Copy, Clone, Debug)]
 pub enum Action {
 	Translate(Vec2),
 	ClickWorld(Vec2),
 	SetSpecies(Species),
+	SetOverlay(Option<SoilChannel>),
+	JoinWorld(u8),
 }
 
 pub struct MainScreen {
 	pub viewport: Viewport,
 
 	selector_bar: SelectorBar,
+	overlay_toggle: OverlayToggle,
+	inspect_panel: InspectPanel,
+	stats_graph: StatsGraph,
+	world_picker: WorldPicker,
 
 	actions: Vec<Action>,
 	drag_pos: Vec2,
@@ -29,6 +35,10 @@ impl MainScreen {
 			viewport: Viewport::new(),
 
 			selector_bar: SelectorBar::new(),
+			overlay_toggle: OverlayToggle::new(),
+			inspect_panel: InspectPanel::new(),
+			stats_graph: StatsGraph::new(),
+			world_picker: WorldPicker::new(),
 
 			drag_pos: Vec2::zero(),
 		}
@@ -36,10 +46,44 @@ impl MainScreen {
 
 	pub fn update(&mut self, dt: f32) {
 		self.selector_bar.update(dt);
+		self.overlay_toggle.update(dt);
+		self.inspect_panel.update(dt);
+		self.world_picker.update(dt);
+	}
+
+	pub fn render(&mut self, builder: &mut UIBuilder) {
+		self.selector_bar.render(builder);
+		self.overlay_toggle.render(builder);
+		self.inspect_panel.render(builder);
+		self.stats_graph.render(builder);
+		self.world_picker.render(builder);
+	}
+
+	pub fn set_overlay(&mut self, overlay: Option<SoilChannel>) {
+		self.overlay_toggle.overlay = overlay;
+	}
+
+	pub fn set_inspected_genome(&mut self, genome: Option<Genome>) {
+		self.inspect_panel.set_genome(genome);
+	}
+
+	pub fn set_stats_history(&mut self, samples: Vec<WorldStats>) {
+		self.stats_graph.samples = samples;
+	}
+
+	pub fn set_species_colors(&mut self, colors: Vec<Color>) {
+		self.stats_graph.colors = colors.clone();
+		self.selector_bar.set_colors(colors);
+	}
+
+	// name, players, max players
+	pub fn set_worlds(&mut self, worlds: Vec<(String, u16, u16)>) {
+		self.world_picker.worlds = worlds;
 	}
 
-	pub fn render(&mut self, mut builder: &mut UIBuilder) {
-		self.selector_bar.render(&mut builder);
+	pub fn set_current_world(&mut self, world: Option<u8>) {
+		self.world_picker.current = world;
+		self.world_picker.click_phase = 0.0;
 	}
 
 	pub fn poll_actions(&mut self) -> Option<Action> {
@@ -52,7 +96,7 @@ impl InputTarget for MainScreen {
 		self.drag_pos = pos;
 	}
 
-	fn on_drag_end(&mut self, pos: Vec2) {}
+	fn on_drag_end(&mut self, _pos: Vec2) {}
 	fn on_drag(&mut self, pos: Vec2) {
 		self.actions.push(Action::Translate(pos - self.drag_pos));
 		self.drag_pos = pos;
@@ -63,6 +107,20 @@ impl InputTarget for MainScreen {
 			self.actions.push(Action::SetSpecies(species));
 			return
 		}
+
+		if self.overlay_toggle.click(pos) {
+			self.actions.push(Action::SetOverlay(self.overlay_toggle.overlay));
+			return
+		}
+
+		if let Some(world) = self.world_picker.click(pos) {
+			if self.world_picker.current != Some(world) {
+				self.actions.push(Action::JoinWorld(world));
+			}
+
+			return
+		}
+
 		self.actions.push(Action::ClickWorld(pos));
 	}
 }
@@ -70,8 +128,9 @@ impl InputTarget for MainScreen {
 struct SelectorBar {
 	phase: f32,
 
-	selector_positions: [Vec2; 3],
-	selector_phase: [f32; 3],
+	selector_positions: Vec<Vec2>,
+	selector_phase: Vec<f32>,
+	selector_colors: Vec<Color>,
 	selector_size: f32,
 }
 
@@ -80,17 +139,24 @@ impl SelectorBar {
 		SelectorBar {
 			phase: -1.0,
 
-			selector_positions: [Vec2::zero(); 3],
-			selector_phase: [std::f32::INFINITY; 3],
+			selector_positions: Vec::new(),
+			selector_phase: Vec::new(),
+			selector_colors: Vec::new(),
 			selector_size: 0.0,
 		}
 	}
 
+	fn set_colors(&mut self, colors: Vec<Color>) {
+		self.selector_positions = vec![Vec2::zero(); colors.len()];
+		self.selector_phase = vec![f32::INFINITY; colors.len()];
+		self.selector_colors = colors;
+	}
+
 	fn click(&mut self, pos: Vec2) -> Option<Species> {
 		for (idx, &selector) in self.selector_positions.iter().enumerate() {
 			if (selector - pos).length() < self.selector_size {
 				self.selector_phase[idx] = 0.0;
-				return Species::from_byte(idx as u8)
+				return Some(Species(idx as u8))
 			}
 		}
 
@@ -116,23 +182,19 @@ impl SelectorBar {
 		let target_pos = Vec2::new(0.0, selector_size*1.1 - 1.0);
 		let center = self.phase.ease_back_out(Vec2::new(0.0,-1.0 - selector_size), target_pos);
 
-		self.selector_positions = [
-			center - separation,
-			center,
-			center + separation,
-		];
+		let count = self.selector_colors.len();
+		let first_offset = -(count as f32 - 1.0) / 2.0;
 
-		self.selector_size = selector_size;
+		for (i, pos) in self.selector_positions.iter_mut().enumerate() {
+			*pos = center + separation * (first_offset + i as f32);
+		}
 
-		let colors = [
-			Color::rgb(0.197, 0.800, 0.202).pow(1.0/2.2),
-			Color::rgb(0.400, 0.600, 1.000).pow(1.0/2.2),
-			Color::rgb(1.000, 0.500, 0.500).pow(1.0/2.2),
-		];
+		self.selector_size = selector_size;
 
 		let bg_color = Color::grey_a(0.3, 0.3);
 
-		for ((&pos, &color), &phase) in self.selector_positions.iter().zip(colors.iter()).zip(self.selector_phase.iter()) {
+		for ((&pos, &color), &phase) in self.selector_positions.iter().zip(self.selector_colors.iter()).zip(self.selector_phase.iter()) {
+			let color = color.pow(1.0/2.2);
 			let click_size = phase.ease_linear(selector_size, selector_size*2.0);
 			let click_col = Color{a: phase.ease_linear(1.0, 0.0), .. color};
 
@@ -144,4 +206,255 @@ impl SelectorBar {
 			builder.build_poly(pos, main_col, 4, selector_size);
 		}
 	}
-}
\ No newline at end of file
+}
+// Cycles the terrain through each soil channel and back to health
+struct OverlayToggle {
+	overlay: Option<SoilChannel>,
+
+	position: Vec2,
+	size: f32,
+	click_phase: f32,
+}
+
+impl OverlayToggle {
+	fn new() -> Self {
+		OverlayToggle {
+			overlay: None,
+
+			position: Vec2::zero(),
+			size: 0.0,
+			click_phase: f32::INFINITY,
+		}
+	}
+
+	fn click(&mut self, pos: Vec2) -> bool {
+		if (self.position - pos).length() >= self.size { return false }
+
+		self.overlay = match self.overlay {
+			None => Some(ALL_SOIL_CHANNELS[0]),
+			Some(ch) => SoilChannel::from_byte(ch.to_byte() + 1),
+		};
+
+		self.click_phase = 0.0;
+		true
+	}
+
+	fn update(&mut self, dt: f32) {
+		self.click_phase += dt;
+	}
+
+	fn render(&mut self, builder: &mut UIBuilder) {
+		let vp = builder.viewport;
+		let aspect = vp.get_aspect();
+		let size_aspect = if vp.size.x > vp.size.y { aspect } else { 1.0 / aspect };
+
+		// Top right corner
+		self.size = 0.03 * size_aspect;
+		self.position = Vec2::new(aspect - self.size * 2.0, 1.0 - self.size * 2.0);
+
+		let color = match self.overlay {
+			Some(ch) => get_overlay_palette(ch)[3].pow(1.0/2.2),
+			None => Color::white(),
+		};
+
+		let click_size = self.click_phase.ease_linear(self.size, self.size*2.0);
+		let click_col = Color{a: self.click_phase.ease_linear(1.0, 0.0), .. color};
+
+		builder.build_poly(self.position, Color::grey_a(0.3, 0.3), 3, self.size * 1.5);
+		builder.build_poly(self.position, click_col, 3, click_size);
+		builder.build_poly(self.position, color, 3, self.size);
+	}
+}
+
+// Shows the genome of the last tree clicked on as a set of bars
+struct InspectPanel {
+	genome: Option<Genome>,
+	phase: f32,
+}
+
+impl InspectPanel {
+	fn new() -> Self {
+		InspectPanel {
+			genome: None,
+			phase: 0.0,
+		}
+	}
+
+	fn set_genome(&mut self, genome: Option<Genome>) {
+		if genome.is_some() { self.phase = 0.0; }
+		self.genome = genome;
+	}
+
+	fn update(&mut self, dt: f32) {
+		self.phase = (self.phase + dt * 2.0).min(1.0);
+	}
+
+	fn render(&mut self, builder: &mut UIBuilder) {
+		let genome = match self.genome {
+			Some(g) => g,
+			None => return,
+		};
+
+		let normalize = |v: f32, (min, max): (f32, f32)| ((v - min) / (max - min)).clamp(0.0, 1.0);
+
+		let bars = [
+			(normalize(genome.growth, GROWTH_GENE_RANGE), Color::rgb(0.4, 0.8, 0.3)),
+			(normalize(genome.lifespan, LIFESPAN_GENE_RANGE), Color::rgb(0.8, 0.6, 0.3)),
+			(genome.drought_tolerance, Color::rgb(0.3, 0.5, 0.9)),
+			(normalize(genome.color_variation, (-1.0, 1.0)), Color::rgb(1.0 + 0.15*genome.color_variation, 1.0, 1.0 - 0.15*genome.color_variation)),
+		];
+
+		let vp = builder.viewport;
+		let aspect = vp.get_aspect();
+		let size_aspect = if vp.size.x > vp.size.y { aspect } else { 1.0 / aspect };
+
+		// Top left corner
+		let bar_height = 0.02 * size_aspect;
+		let bar_width = 0.3 * size_aspect;
+		let origin = Vec2::new(-aspect + bar_height * 2.0, 1.0 - bar_height * 2.0);
+
+		for (i, &(value, color)) in bars.iter().enumerate() {
+			let top_left = origin - Vec2::new(0.0, i as f32 * bar_height * 1.8);
+			let width = self.phase.ease_back_out(0.0, bar_width * value);
+
+			let rect = |w: f32| [
+				top_left,
+				top_left + Vec2::new(w, 0.0),
+				top_left + Vec2::new(w, -bar_height),
+				top_left + Vec2::new(0.0, -bar_height),
+			];
+
+			builder.build_from_convex(&rect(bar_width), Color::grey_a(0.3, 0.3));
+			builder.build_from_convex(&rect(width), color.pow(1.0/2.2));
+		}
+	}
+}
+
+// Population per species stacked over time, with global diversity on top
+struct StatsGraph {
+	samples: Vec<WorldStats>,
+	colors: Vec<Color>,
+}
+
+impl StatsGraph {
+	fn new() -> Self {
+		StatsGraph {
+			samples: Vec::new(),
+			colors: Vec::new(),
+		}
+	}
+
+	fn render(&mut self, builder: &mut UIBuilder) {
+		if self.samples.len() < 2 { return }
+
+		let vp = builder.viewport;
+		let aspect = vp.get_aspect();
+		let size_aspect = if vp.size.x > vp.size.y { aspect } else { 1.0 / aspect };
+
+		// Bottom left corner, clear of the selector bar
+		let size = Vec2::new(0.4, 0.2) * size_aspect;
+		let origin = Vec2::new(-aspect + 0.04 * size_aspect, -1.0 + 0.12 * size_aspect);
+
+		let column_width = size.x / self.samples.len() as f32;
+		let max_population = self.samples.iter()
+			.map(|s| s.get_total_population())
+			.max()
+			.unwrap_or(0)
+			.max(1) as f32;
+
+		let rect = |x: f32, bottom: f32, top: f32, w: f32| [
+			origin + Vec2::new(x, top),
+			origin + Vec2::new(x + w, top),
+			origin + Vec2::new(x + w, bottom),
+			origin + Vec2::new(x, bottom),
+		];
+
+		builder.build_from_convex(&rect(0.0, 0.0, size.y, size.x), Color::grey_a(0.3, 0.3));
+
+		for (i, sample) in self.samples.iter().enumerate() {
+			let x = i as f32 * column_width;
+			let mut bottom = 0.0;
+
+			for species in 0..sample.population.len() {
+				let height = sample.get_species_population(species) as f32 / max_population * size.y;
+				if height <= 0.0 { continue }
+
+				let color = self.colors.get(species).cloned().unwrap_or(Color::grey(0.5));
+				builder.build_from_convex(&rect(x, bottom, bottom + height, column_width), color.pow(1.0/2.2));
+				bottom += height;
+			}
+
+			let diversity = sample.global_diversity.clamp(0.0, 1.0) * size.y;
+			let marker = column_width.max(0.005 * size_aspect);
+			builder.build_from_convex(&rect(x, diversity - marker/2.0, diversity + marker/2.0, column_width), Color::white());
+		}
+	}
+}
+
+// One button per world down the right edge, filled by how many players are in it
+struct WorldPicker {
+	worlds: Vec<(String, u16, u16)>,
+	current: Option<u8>,
+
+	positions: Vec<Vec2>,
+	size: f32,
+	click_phase: f32,
+}
+
+impl WorldPicker {
+	fn new() -> Self {
+		WorldPicker {
+			worlds: Vec::new(),
+			current: None,
+
+			positions: Vec::new(),
+			size: 0.0,
+			click_phase: f32::INFINITY,
+		}
+	}
+
+	fn click(&mut self, pos: Vec2) -> Option<u8> {
+		self.positions.iter()
+			.position(|&p| (p - pos).length() < self.size)
+			.map(|idx| idx as u8)
+	}
+
+	fn update(&mut self, dt: f32) {
+		self.click_phase += dt;
+	}
+
+	fn render(&mut self, builder: &mut UIBuilder) {
+		// Nothing to pick between
+		if self.worlds.len() < 2 {
+			self.positions.clear();
+			return
+		}
+
+		let vp = builder.viewport;
+		let aspect = vp.get_aspect();
+		let size_aspect = if vp.size.x > vp.size.y { aspect } else { 1.0 / aspect };
+
+		// Under the overlay toggle
+		self.size = 0.025 * size_aspect;
+		let origin = Vec2::new(aspect - self.size * 2.4, 1.0 - self.size * 6.0);
+
+		self.positions = (0..self.worlds.len())
+			.map(|i| origin - Vec2::new(0.0, i as f32 * self.size * 3.0))
+			.collect();
+
+		for (i, (&pos, &(_, players, max_players))) in self.positions.iter().zip(self.worlds.iter()).enumerate() {
+			let fullness = (players as f32 / max_players.max(1) as f32).min(1.0);
+			let is_current = self.current == Some(i as u8);
+
+			let color = if is_current { Color::white() }
+				else if players >= max_players { Color::rgb(0.8, 0.4, 0.4) }
+				else { Color::grey(0.7) };
+
+			let size = if is_current { self.click_phase.ease_back_out(self.size * 0.5, self.size) } else { self.size };
+
+			builder.build_poly(pos, Color::grey_a(0.3, 0.3), 6, self.size * 1.4);
+			builder.build_poly(pos, color, 6, size);
+			builder.build_poly(pos, Color::grey_a(0.2, 0.8), 6, size * (1.0 - fullness) * 0.8);
+		}
+	}
+}
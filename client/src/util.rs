@@ -1,10 +1,65 @@
 #![allow(dead_code)]
 
 #[link_args = "--js-library libutil.js"]
-extern {
+extern "C" {
 	fn save_canvas_raw(target: *const u8, targetLen: usize);
+	fn storage_set_raw(key: *const u8, keyLen: usize, value: *const u8, valueLen: usize);
+	fn storage_get_raw(key: *const u8, keyLen: usize) -> *mut i8;
+	fn websocket_address_raw() -> *mut i8;
 }
 
 pub fn save_canvas(target: &str) {
 	unsafe{ save_canvas_raw(target.as_ptr(), target.len()) };
-}
\ No newline at end of file
+}
+
+// Persists across page loads via localStorage
+pub fn storage_set(key: &str, value: &str) {
+	unsafe{ storage_set_raw(key.as_ptr(), key.len(), value.as_ptr(), value.len()) };
+}
+
+pub fn storage_get(key: &str) -> Option<String> {
+	use std::ffi::CStr;
+	use libc;
+
+	unsafe {
+		let ptr = storage_get_raw(key.as_ptr(), key.len());
+		if ptr.is_null() { return None }
+
+		let value = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+		libc::free(ptr as *mut libc::c_void);
+		Some(value)
+	}
+}
+
+// Host and port of the server's websocket, worked out by the page at load time
+pub fn websocket_address() -> (String, String) {
+	use std::ffi::CStr;
+	use libc;
+
+	let address = unsafe {
+		let ptr = websocket_address_raw();
+		let address = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+		libc::free(ptr as *mut libc::c_void);
+		address
+	};
+
+	match address.rfind(':') {
+		Some(split) => (address[..split].to_string(), address[split+1..].to_string()),
+		None => (address, String::from("80")),
+	}
+}
+
+// localStorage only holds strings
+pub fn to_hex(data: &[u8]) -> String {
+	data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(s: &str) -> Option<Vec<u8>> {
+	if s.len() % 2 == 1 { return None }
+
+	use std::str;
+
+	s.as_bytes().chunks(2)
+		.map(|b| str::from_utf8(b).ok().and_then(|b| u8::from_str_radix(b, 16).ok()))
+		.collect()
+}
@@ -0,0 +1,132 @@
+use math::*;
+use easing::*;
+use rand::Rng;
+
+// Tiles per second
+const POLLINATOR_SPEED: f32 = 0.8;
+const HERBIVORE_SPEED: f32 = 0.3;
+// How far away an animal can notice something it wants
+const SENSE_RANGE: f32 = 6.0;
+const EDGE_MARGIN: f32 = 2.0;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum FaunaKind {
+	// Boost seed production of nearby adults
+	Pollinator,
+	// Eat seeds
+	Herbivore,
+}
+
+impl FaunaKind {
+	pub fn to_byte(self) -> u8 {
+		self as u8
+	}
+
+	pub fn from_byte(b: u8) -> Option<FaunaKind> {
+		match b {
+			0 => Some(FaunaKind::Pollinator),
+			1 => Some(FaunaKind::Herbivore),
+			_ => None,
+		}
+	}
+
+	pub fn get_speed(self) -> f32 {
+		match self {
+			FaunaKind::Pollinator => POLLINATOR_SPEED,
+			FaunaKind::Herbivore => HERBIVORE_SPEED,
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub struct Animal {
+	pub id: u32,
+	pub kind: FaunaKind,
+
+	// In tiles
+	pub pos: Vec2,
+	pub vel: Vec2,
+
+	heading: f32,
+}
+
+pub struct Fauna {
+	pub animals: Vec<Animal>,
+	bounds: Vec2,
+}
+
+impl Fauna {
+	pub fn new<R: Rng>(bounds: Vec2, pollinators: usize, herbivores: usize, rng: &mut R) -> Fauna {
+		let kinds = std::iter::repeat_n(FaunaKind::Pollinator, pollinators)
+			.chain(std::iter::repeat_n(FaunaKind::Herbivore, herbivores));
+
+		let animals = kinds.enumerate()
+			.map(|(id, kind)| {
+				let heading = rng.gen_range(0.0, 2.0*PI);
+
+				Animal {
+					id: id as u32,
+					kind,
+					pos: Vec2::new(rng.gen_range(0.0, bounds.x), rng.gen_range(0.0, bounds.y)),
+					vel: Vec2::from_angle(heading) * kind.get_speed(),
+					heading,
+				}
+			})
+			.collect();
+
+		Fauna { animals, bounds }
+	}
+
+	// attractors are the positions each kind is drawn towards
+	pub fn update<R: Rng>(&mut self, dt: f32, pollinator_attractors: &[Vec2], herbivore_attractors: &[Vec2], rng: &mut R) {
+		for animal in self.animals.iter_mut() {
+			let attractors = match animal.kind {
+				FaunaKind::Pollinator => pollinator_attractors,
+				FaunaKind::Herbivore => herbivore_attractors,
+			};
+
+			let nearest = attractors.iter()
+				.map(|&p| (p, (p - animal.pos).length()))
+				.filter(|&(_, d)| d < SENSE_RANGE)
+				.fold(None, |acc: Option<(Vec2, f32)>, (p, d)| match acc {
+					Some((_, ad)) if ad <= d => acc,
+					_ => Some((p, d)),
+				});
+
+			// Slow down to linger once close enough
+			let (attraction, speed_scale) = match nearest {
+				Some((p, d)) if d > 0.01 => ((p - animal.pos).normalize(), (d / 1.5).clamp(0.2, 1.0)),
+				_ => (Vec2::zero(), 1.0),
+			};
+
+			let centre = self.bounds * 0.5;
+			let diff_to_centre = centre - animal.pos;
+			let outside = Vec2::new(
+				(diff_to_centre.x.abs() - (centre.x - EDGE_MARGIN)).max(0.0),
+				(diff_to_centre.y.abs() - (centre.y - EDGE_MARGIN)).max(0.0),
+			);
+			let edge_avoid = if outside.length() > 0.0 { diff_to_centre.normalize() * outside } else { Vec2::zero() };
+
+			animal.heading += rng.gen_range(-1.0, 1.0) * PI * dt;
+			let wander = Vec2::from_angle(animal.heading);
+
+			let acc = wander + attraction * 1.5 + edge_avoid * 3.0;
+
+			if acc.length() > 0.01 {
+				animal.vel = (dt * 2.0).min(1.0).ease_linear(animal.vel, acc.normalize() * animal.kind.get_speed());
+			}
+
+			animal.vel = animal.vel.normalize() * animal.kind.get_speed() * speed_scale;
+			animal.pos = animal.pos + animal.vel * dt;
+
+			animal.pos.x = animal.pos.x.max(0.0).min(self.bounds.x);
+			animal.pos.y = animal.pos.y.max(0.0).min(self.bounds.y);
+		}
+	}
+
+	pub fn count_near(&self, kind: FaunaKind, p: Vec2, range: f32) -> usize {
+		self.animals.iter()
+			.filter(|a| a.kind == kind && (a.pos - p).length() < range)
+			.count()
+	}
+}
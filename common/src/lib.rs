@@ -1,9 +1,16 @@
-#![feature(slice_patterns)]
 extern crate rand;
+extern crate serde;
+extern crate toml;
+#[macro_use]
+extern crate serde_derive;
 
 pub mod easing;
 pub mod packet;
 pub mod world;
+pub mod terrain;
+pub mod fauna;
+pub mod stats;
+pub mod species;
 pub mod math;
 
 pub use packet::*;
@@ -23,27 +30,21 @@ macro_rules! match_enum {
 pub fn write_f32_to_slice(dst: &mut [u8], value: f32) {
 	assert!(dst.len() >= 4);
 
-	use std::mem::transmute;
-
-	let a: [u8; 4] = unsafe {transmute(value)};
+	let a = value.to_ne_bytes();
 	dst[..4].copy_from_slice(&a);
 }
 
 pub fn write_u32_to_slice(dst: &mut [u8], value: u32) {
 	assert!(dst.len() >= 4);
 
-	use std::mem::transmute;
-
-	let a: [u8; 4] = unsafe {transmute(value)};
+	let a = value.to_ne_bytes();
 	dst[..4].copy_from_slice(&a);
 }
 
 pub fn write_u16_to_slice(dst: &mut [u8], value: u16) {
 	assert!(dst.len() >= 2);
 
-	use std::mem::transmute;
-
-	let a: [u8; 2] = unsafe {transmute(value)};
+	let a = value.to_ne_bytes();
 	dst[..2].copy_from_slice(&a);
 }
 
@@ -53,7 +54,7 @@ pub fn read_f32_from_slice(src: &[u8]) -> f32 {
 	let mut a = [0u8; 4];
 	a.copy_from_slice(&src[..4]);
 
-	unsafe { std::mem::transmute(a) }
+	f32::from_ne_bytes(a)
 }
 
 pub fn read_u32_from_slice(src: &[u8]) -> u32 {
@@ -62,7 +63,7 @@ pub fn read_u32_from_slice(src: &[u8]) -> u32 {
 	let mut a = [0u8; 4];
 	a.copy_from_slice(&src[..4]);
 
-	unsafe { std::mem::transmute(a) }
+	u32::from_ne_bytes(a)
 }
 
 pub fn read_u16_from_slice(src: &[u8]) -> u16 {
@@ -71,7 +72,7 @@ pub fn read_u16_from_slice(src: &[u8]) -> u16 {
 	let mut a = [0u8; 2];
 	a.copy_from_slice(&src[..2]);
 
-	unsafe { std::mem::transmute(a) }
+	u16::from_ne_bytes(a)
 }
 
 pub use rand::{thread_rng, Rng};
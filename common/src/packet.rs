@@ -1,7 +1,20 @@
 use std;
 use ::*;
 
-use world::Species;
+use world::{Species, SpeciesInfo, Region, SoilChannel, Season, Weather, Affliction, Genome};
+use terrain::{Terrain, TerrainTile, TileKind};
+use fauna::FaunaKind;
+use stats::{WorldStats, NUM_STAGES};
+
+// Largest packet the server writes, anything bigger has to be refused before it gets that far
+pub const MAX_PAYLOAD_SIZE: usize = 4<<10;
+
+// Both counts go out as single bytes
+pub const MAX_SPECIES: usize = 255;
+pub const MAX_PALETTE_SIZE: usize = 255;
+
+// Type, seed, width, height and first row
+const TERRAIN_HEADER_SIZE: usize = 11;
 
 #[derive(Clone)]
 pub enum Packet {
@@ -9,20 +22,46 @@ pub enum Packet {
 	Debug(String),
 	RequestNewSession,
 	AttemptAuthSession(u32),
-	RequestDownloadWorld,
+	SubscribeRegion(Region),
+	// None turns the overlay off
+	RequestSoilOverlay(Option<SoilChannel>),
+	// Number of most recent samples wanted
+	RequestStats(u16),
+	// Index into the last WorldList
+	JoinWorld(u8),
 
 	RequestPlaceTree(f32, f32, Species),
+	InspectTree(u32),
 
 	// Server -> Client
 	AuthSuccessful(u32),
 	AuthFail,
 	NewSession(u32),
-
-	TreePlaced(u32, f32, f32, Species),
+	SpeciesRegistry(Vec<SpeciesInfo>),
+	ClimateUpdate(Season, Weather),
+	// seed, width, height, first row, tiles of whole rows from there. See terrain_packets
+	TerrainData(u32, u16, u16, u16, Vec<TerrainTile>),
+	// name, players, max players
+	WorldList(Vec<(String, u16, u16)>),
+	// Followed by the world's species, terrain and climate
+	WorldJoined(u8),
+
+	// id, x, y, species, planted naturally (as opposed to by a player), color variation
+	TreePlaced(u32, f32, f32, Species, bool, f32),
 	TreeDied(u32),
+	// id, affliction, whether it started a new outbreak/fire
+	TreeAfflicted(u32, Affliction, bool),
 
-	HealthUpdate(Vec<u8>),
+	HealthUpdate(u16, u16, Vec<u8>),
 	TreeUpdate(Vec<(u32, u8)>),
+	// chunk x, y, channel, quantized values
+	SoilUpdate(u16, u16, SoilChannel, Vec<u8>),
+	// id, kind, position, velocity
+	FaunaUpdate(Vec<(u32, FaunaKind, Vec2, Vec2)>),
+	// id, species, generation, genome
+	TreeInfo(u32, Species, u32, Genome),
+	// Oldest first
+	StatsHistory(Vec<WorldStats>),
 }
 
 impl Packet {
@@ -32,20 +71,34 @@ impl Packet {
 			Packet::Debug(_) => 0x0,
 			Packet::RequestNewSession => 0x1,
 			Packet::AttemptAuthSession(_) => 0x2,
-			Packet::RequestDownloadWorld => 0x3,
+			Packet::SubscribeRegion(_) => 0x3,
+			Packet::RequestSoilOverlay(_) => 0x4,
+			Packet::RequestStats(_) => 0x5,
+			Packet::JoinWorld(_) => 0x6,
 
 			Packet::RequestPlaceTree(..) => 0x10,
+			Packet::InspectTree(_) => 0x11,
 
 			// Server -> Client
 			Packet::AuthSuccessful(_) => 0x80,
 			Packet::AuthFail => 0x81,
 			Packet::NewSession(_) => 0x82,
+			Packet::SpeciesRegistry(_) => 0x83,
+			Packet::ClimateUpdate(..) => 0x84,
+			Packet::TerrainData(..) => 0x85,
+			Packet::WorldList(_) => 0x86,
+			Packet::WorldJoined(_) => 0x87,
 
 			Packet::TreePlaced(..) => 0x90,
 			Packet::TreeDied(..) => 0x91,
 
 			Packet::HealthUpdate(..) => 0x92,
 			Packet::TreeUpdate(..) => 0x93,
+			Packet::SoilUpdate(..) => 0x94,
+			Packet::TreeAfflicted(..) => 0x95,
+			Packet::FaunaUpdate(..) => 0x96,
+			Packet::TreeInfo(..) => 0x97,
+			Packet::StatsHistory(_) => 0x98,
 		}
 	}
 
@@ -55,32 +108,167 @@ impl Packet {
 		match ty {
 			0x0  => std::str::from_utf8(&src[1..]).ok().map(|s| Packet::Debug(String::from(s))),
 			0x1  => Some(Packet::RequestNewSession),
-			0x2  => Some(Packet::AttemptAuthSession(read_u32_from_slice(&src[1..]))),
-			0x3  => Some(Packet::RequestDownloadWorld),
+			0x2  => {
+				if src.len() < 5 { return None }
+				Some(Packet::AttemptAuthSession(read_u32_from_slice(&src[1..])))
+			}
+			0x3  => {
+				if src.len() < 9 { return None }
+
+				let min = (read_u16_from_slice(&src[1..]), read_u16_from_slice(&src[3..]));
+				let max = (read_u16_from_slice(&src[5..]), read_u16_from_slice(&src[7..]));
+				Some(Packet::SubscribeRegion(Region::new(min, max)))
+			}
+			0x4  => {
+				if src.len() < 2 { return None }
+				Some(Packet::RequestSoilOverlay(SoilChannel::from_byte(src[1])))
+			}
+			0x5  => {
+				if src.len() < 3 { return None }
+				Some(Packet::RequestStats(read_u16_from_slice(&src[1..])))
+			}
+			0x6  => {
+				if src.len() < 2 { return None }
+				Some(Packet::JoinWorld(src[1]))
+			}
 
 			0x10 => {
+				if src.len() < 10 { return None }
+
 				let (x,y) = (read_f32_from_slice(&src[1..]), read_f32_from_slice(&src[5..]));
-				let spec = Species::from_byte(src[9]).unwrap_or(Species::A);
-				Some(Packet::RequestPlaceTree(x, y, spec))
+				Some(Packet::RequestPlaceTree(x, y, Species(src[9])))
+			}
+			0x11 => {
+				if src.len() < 5 { return None }
+				Some(Packet::InspectTree(read_u32_from_slice(&src[1..])))
 			}
 
 			0x80 => Some(Packet::AuthSuccessful(read_u32_from_slice(&src[1..]))),
 			0x81 => Some(Packet::AuthFail),
 			0x82 => Some(Packet::NewSession(read_u32_from_slice(&src[1..]))),
+			0x83 => {
+				let count = src[1] as usize;
+				let mut species = Vec::with_capacity(count);
+				let mut offset = 2;
+
+				for _ in 0..count {
+					let (info, size) = read_species_info(&src[offset..])?;
+
+					species.push(info);
+					offset += size;
+				}
+
+				Some(Packet::SpeciesRegistry(species))
+			}
+			0x84 => {
+				match (Season::from_byte(src[1]), Weather::from_byte(src[2])) {
+					(Some(s), Some(w)) => Some(Packet::ClimateUpdate(s, w)),
+					_ => None
+				}
+			}
+			0x85 => {
+				if src.len() < TERRAIN_HEADER_SIZE { return None }
+
+				let seed = read_u32_from_slice(&src[1..]);
+				let (w, h) = (read_u16_from_slice(&src[5..]) as usize, read_u16_from_slice(&src[7..]) as usize);
+				let first_row = read_u16_from_slice(&src[9..]) as usize;
+				if w == 0 { return None }
+
+				let rows = (src.len() - TERRAIN_HEADER_SIZE) / (w*2);
+				if rows == 0 || first_row + rows > h { return None }
+
+				let mut tiles = Vec::with_capacity(w*rows);
+				for t in src[TERRAIN_HEADER_SIZE..TERRAIN_HEADER_SIZE + w*rows*2].chunks(2) {
+					let kind = TileKind::from_byte(t[0])?;
+
+					tiles.push(TerrainTile{ kind, elevation: t[1] as f32 / 255.0 });
+				}
+
+				Some(Packet::TerrainData(seed, w as u16, h as u16, first_row as u16, tiles))
+			}
+			0x86 => {
+				let count = src[1] as usize;
+				let mut worlds = Vec::with_capacity(count);
+				let mut offset = 2;
+
+				for _ in 0..count {
+					let (name, size) = read_string(&src[offset..])?;
+
+					offset += size;
+					if src.len() < offset + 4 { return None }
+
+					let players = read_u16_from_slice(&src[offset..]);
+					let max_players = read_u16_from_slice(&src[offset+2..]);
+					worlds.push((name, players, max_players));
+					offset += 4;
+				}
+
+				Some(Packet::WorldList(worlds))
+			}
+			0x87 => Some(Packet::WorldJoined(src[1])),
 
 			0x90 => {
 				let tree_id = read_u32_from_slice(&src[1..]);
 				let (x,y) = (read_f32_from_slice(&src[5..]), read_f32_from_slice(&src[9..]));
-				let species = Species::from_byte(src[13]).unwrap_or(Species::A);
-				Some(Packet::TreePlaced(tree_id, x, y, species))
+				let variation = src[15] as f32 / 127.5 - 1.0;
+				Some(Packet::TreePlaced(tree_id, x, y, Species(src[13]), src[14] != 0, variation))
 			}
 
 			0x91 => Some(Packet::TreeDied(read_u32_from_slice(&src[1..]))),
-			0x92 => Some(Packet::HealthUpdate(src[1..].to_vec())),
+			0x92 => {
+				let (x,y) = (read_u16_from_slice(&src[1..]), read_u16_from_slice(&src[3..]));
+				Some(Packet::HealthUpdate(x, y, src[5..].to_vec()))
+			}
 			0x93 => {
 				let v = src[1..].chunks(5).map(|c| (read_u32_from_slice(c), c[4])).collect();
 				Some(Packet::TreeUpdate(v))
 			},
+			0x94 => {
+				let (x,y) = (read_u16_from_slice(&src[1..]), read_u16_from_slice(&src[3..]));
+				SoilChannel::from_byte(src[5])
+					.map(|ch| Packet::SoilUpdate(x, y, ch, src[6..].to_vec()))
+			}
+			0x95 => {
+				let tree_id = read_u32_from_slice(&src[1..]);
+				Affliction::from_byte(src[5])
+					.map(|a| Packet::TreeAfflicted(tree_id, a, src[6] != 0))
+			}
+			0x96 => {
+				let mut animals = Vec::new();
+
+				for c in src[1..].chunks(21) {
+					if c.len() < 21 { return None }
+
+					let kind = FaunaKind::from_byte(c[4])?;
+
+					let pos = Vec2::new(read_f32_from_slice(&c[5..]), read_f32_from_slice(&c[9..]));
+					let vel = Vec2::new(read_f32_from_slice(&c[13..]), read_f32_from_slice(&c[17..]));
+					animals.push((read_u32_from_slice(c), kind, pos, vel));
+				}
+
+				Some(Packet::FaunaUpdate(animals))
+			}
+			0x97 => {
+				if src.len() < 10 + 16 { return None }
+
+				let tree_id = read_u32_from_slice(&src[1..]);
+				let generation = read_u32_from_slice(&src[6..]);
+				Some(Packet::TreeInfo(tree_id, Species(src[5]), generation, read_genome(&src[10..])))
+			}
+			0x98 => {
+				let count = src[1] as usize;
+				let mut samples = Vec::with_capacity(count);
+				let mut offset = 2;
+
+				for _ in 0..count {
+					let (stats, size) = read_world_stats(&src[offset..])?;
+
+					samples.push(stats);
+					offset += size;
+				}
+
+				Some(Packet::StatsHistory(samples))
+			}
 
 			_ => None
 		}
@@ -96,7 +284,7 @@ impl Packet {
 				let len = s.len() + 1;
 
 				assert!(dst.len() >= len);
-				dst[1..len].copy_from_slice(&s.as_bytes());
+				dst[1..len].copy_from_slice(s.as_bytes());
 
 				len
 			}
@@ -107,15 +295,41 @@ impl Packet {
 				5
 			}
 
-			Packet::RequestDownloadWorld => 1,
+			Packet::SubscribeRegion(region) => {
+				write_u16_to_slice(&mut dst[1..], region.min.0);
+				write_u16_to_slice(&mut dst[3..], region.min.1);
+				write_u16_to_slice(&mut dst[5..], region.max.0);
+				write_u16_to_slice(&mut dst[7..], region.max.1);
+				9
+			}
+
+			Packet::RequestSoilOverlay(ch) => {
+				dst[1] = ch.map(|c| c.to_byte()).unwrap_or(0xFF);
+				2
+			}
+
+			Packet::RequestStats(count) => {
+				write_u16_to_slice(&mut dst[1..], count);
+				3
+			}
+
+			Packet::JoinWorld(idx) => {
+				dst[1] = idx;
+				2
+			}
 
 			Packet::RequestPlaceTree(x, y, spec) => {
 				write_f32_to_slice(&mut dst[1..], x);
 				write_f32_to_slice(&mut dst[5..], y);
-				dst[9] = spec.to_byte();
+				dst[9] = spec.0;
 				10
 			}
 
+			Packet::InspectTree(id) => {
+				write_u32_to_slice(&mut dst[1..], id);
+				5
+			}
+
 			Packet::AuthSuccessful(tok) => {
 				write_u32_to_slice(&mut dst[1..], tok);
 				5
@@ -126,12 +340,58 @@ impl Packet {
 				5
 			}
 
-			Packet::TreePlaced(id, x, y, species) => {
+			Packet::SpeciesRegistry(ref species) => {
+				dst[1] = species.len().min(MAX_SPECIES) as u8;
+
+				species.iter().take(MAX_SPECIES).fold(2, |offset, info| {
+					offset + write_species_info(&mut dst[offset..], info)
+				})
+			}
+
+			Packet::ClimateUpdate(season, weather) => {
+				dst[1] = season.to_byte();
+				dst[2] = weather.to_byte();
+				3
+			}
+
+			Packet::TerrainData(seed, width, height, first_row, ref tiles) => {
+				write_u32_to_slice(&mut dst[1..], seed);
+				write_u16_to_slice(&mut dst[5..], width);
+				write_u16_to_slice(&mut dst[7..], height);
+				write_u16_to_slice(&mut dst[9..], first_row);
+
+				for (i, t) in tiles.iter().enumerate() {
+					dst[TERRAIN_HEADER_SIZE + i*2] = t.kind.to_byte();
+					dst[TERRAIN_HEADER_SIZE + 1 + i*2] = (t.elevation.clamp(0.0, 1.0) * 255.0) as u8;
+				}
+
+				TERRAIN_HEADER_SIZE + tiles.len() * 2
+			}
+
+			Packet::WorldList(ref worlds) => {
+				dst[1] = worlds.len() as u8;
+
+				worlds.iter().take(255).fold(2, |offset, &(ref name, players, max_players)| {
+					let offset = offset + write_string(&mut dst[offset..], name);
+					write_u16_to_slice(&mut dst[offset..], players);
+					write_u16_to_slice(&mut dst[offset+2..], max_players);
+					offset + 4
+				})
+			}
+
+			Packet::WorldJoined(idx) => {
+				dst[1] = idx;
+				2
+			}
+
+			Packet::TreePlaced(id, x, y, species, natural, variation) => {
 				write_u32_to_slice(&mut dst[1..], id);
 				write_f32_to_slice(&mut dst[5..], x);
 				write_f32_to_slice(&mut dst[9..], y);
-				dst[13] = species.to_byte();
-				14
+				dst[13] = species.0;
+				dst[14] = natural as u8;
+				dst[15] = ((variation.clamp(-1.0, 1.0) + 1.0) * 127.5) as u8;
+				16
 			}
 
 			Packet::TreeDied(id) => {
@@ -139,9 +399,18 @@ impl Packet {
 				5
 			}
 
-			Packet::HealthUpdate(ref hs) => {
-				dst[1..1+hs.len()].copy_from_slice(&hs);
-				1 + hs.len()
+			Packet::TreeAfflicted(id, affliction, origin) => {
+				write_u32_to_slice(&mut dst[1..], id);
+				dst[5] = affliction.to_byte();
+				dst[6] = origin as u8;
+				7
+			}
+
+			Packet::HealthUpdate(x, y, ref hs) => {
+				write_u16_to_slice(&mut dst[1..], x);
+				write_u16_to_slice(&mut dst[3..], y);
+				dst[5..5+hs.len()].copy_from_slice(hs);
+				5 + hs.len()
 			}
 
 			Packet::TreeUpdate(ref ts) => {
@@ -154,6 +423,44 @@ impl Packet {
 
 				1 + ts.len() * 5
 			}
+
+			Packet::FaunaUpdate(ref animals) => {
+				for (i, &(id, kind, pos, vel)) in animals.iter().enumerate() {
+					let base = 1 + i*21;
+
+					write_u32_to_slice(&mut dst[base..], id);
+					dst[base + 4] = kind.to_byte();
+					write_f32_to_slice(&mut dst[base + 5..], pos.x);
+					write_f32_to_slice(&mut dst[base + 9..], pos.y);
+					write_f32_to_slice(&mut dst[base + 13..], vel.x);
+					write_f32_to_slice(&mut dst[base + 17..], vel.y);
+				}
+
+				1 + animals.len() * 21
+			}
+
+			Packet::TreeInfo(id, species, generation, ref genome) => {
+				write_u32_to_slice(&mut dst[1..], id);
+				dst[5] = species.0;
+				write_u32_to_slice(&mut dst[6..], generation);
+				10 + write_genome(&mut dst[10..], genome)
+			}
+
+			Packet::StatsHistory(ref samples) => {
+				dst[1] = samples.len() as u8;
+
+				samples.iter().take(255).fold(2, |offset, stats| {
+					offset + write_world_stats(&mut dst[offset..], stats)
+				})
+			}
+
+			Packet::SoilUpdate(x, y, ch, ref vs) => {
+				write_u16_to_slice(&mut dst[1..], x);
+				write_u16_to_slice(&mut dst[3..], y);
+				dst[5] = ch.to_byte();
+				dst[6..6+vs.len()].copy_from_slice(vs);
+				6 + vs.len()
+			}
 		}
 	}
 
@@ -165,3 +472,288 @@ impl Packet {
 		self.get_type() >= 0x80
 	}
 }
+
+// Terrain goes out a few whole rows at a time, so worlds aren't limited to what fits in one packet
+pub fn terrain_packets(terrain: &Terrain) -> Vec<Packet> {
+	let rows_per_packet = (MAX_PAYLOAD_SIZE - TERRAIN_HEADER_SIZE) / (terrain.width * 2);
+
+	terrain.tiles.chunks(terrain.width * rows_per_packet)
+		.enumerate()
+		.map(|(i, tiles)| {
+			let first_row = (i * rows_per_packet) as u16;
+			Packet::TerrainData(terrain.seed, terrain.width as u16, terrain.height as u16, first_row, tiles.to_vec())
+		})
+		.collect()
+}
+
+fn write_string(dst: &mut [u8], s: &str) -> usize {
+	let len = s.len().min(255);
+	dst[0] = len as u8;
+	dst[1..1+len].copy_from_slice(&s.as_bytes()[..len]);
+	1 + len
+}
+
+fn read_string(src: &[u8]) -> Option<(String, usize)> {
+	if src.is_empty() || src.len() < 1 + src[0] as usize { return None }
+
+	let len = src[0] as usize;
+	std::str::from_utf8(&src[1..1+len]).ok()
+		.map(|s| (String::from(s), 1 + len))
+}
+
+fn write_genome(dst: &mut [u8], genome: &Genome) -> usize {
+	write_f32_to_slice(&mut dst[0..], genome.growth);
+	write_f32_to_slice(&mut dst[4..], genome.lifespan);
+	write_f32_to_slice(&mut dst[8..], genome.drought_tolerance);
+	write_f32_to_slice(&mut dst[12..], genome.color_variation);
+	16
+}
+
+fn read_genome(src: &[u8]) -> Genome {
+	Genome {
+		growth: read_f32_from_slice(&src[0..]),
+		lifespan: read_f32_from_slice(&src[4..]),
+		drought_tolerance: read_f32_from_slice(&src[8..]),
+		color_variation: read_f32_from_slice(&src[12..]),
+	}
+}
+
+pub fn get_world_stats_size(stats: &WorldStats) -> usize {
+	17 + stats.population.len() * NUM_STAGES * 2 + 1 + stats.regional_diversity.len()
+}
+
+fn write_world_stats(dst: &mut [u8], stats: &WorldStats) -> usize {
+	write_u32_to_slice(&mut dst[0..], stats.tick);
+	write_u16_to_slice(&mut dst[4..], stats.births);
+	write_u16_to_slice(&mut dst[6..], stats.deaths);
+	write_f32_to_slice(&mut dst[8..], stats.mean_health);
+	write_f32_to_slice(&mut dst[12..], stats.global_diversity);
+
+	let mut offset = 16;
+	dst[offset] = stats.population.len() as u8;
+	offset += 1;
+
+	for stages in stats.population.iter() {
+		for &n in stages.iter() {
+			write_u16_to_slice(&mut dst[offset..], n);
+			offset += 2;
+		}
+	}
+
+	// Regional diversity is only needed for a rough picture
+	dst[offset] = stats.regional_diversity.len() as u8;
+	offset += 1;
+
+	for &d in stats.regional_diversity.iter() {
+		dst[offset] = (d.clamp(0.0, 1.0) * 255.0) as u8;
+		offset += 1;
+	}
+
+	offset
+}
+
+fn read_world_stats(src: &[u8]) -> Option<(WorldStats, usize)> {
+	if src.len() < 17 { return None }
+
+	let tick = read_u32_from_slice(&src[0..]);
+	let births = read_u16_from_slice(&src[4..]);
+	let deaths = read_u16_from_slice(&src[6..]);
+	let mean_health = read_f32_from_slice(&src[8..]);
+	let global_diversity = read_f32_from_slice(&src[12..]);
+
+	let species_count = src[16] as usize;
+	let mut offset = 17;
+
+	if src.len() < offset + species_count * NUM_STAGES * 2 + 1 { return None }
+
+	let population = (0..species_count)
+		.map(|i| {
+			let base = offset + i * NUM_STAGES * 2;
+			[
+				read_u16_from_slice(&src[base..]),
+				read_u16_from_slice(&src[base+2..]),
+				read_u16_from_slice(&src[base+4..]),
+			]
+		})
+		.collect();
+
+	offset += species_count * NUM_STAGES * 2;
+
+	let region_count = src[offset] as usize;
+	offset += 1;
+
+	if src.len() < offset + region_count { return None }
+
+	let regional_diversity = src[offset..offset + region_count].iter()
+		.map(|&d| d as f32 / 255.0)
+		.collect();
+
+	offset += region_count;
+
+	let stats = WorldStats {
+		tick, population, mean_health,
+		global_diversity, regional_diversity,
+		births, deaths,
+	};
+
+	Some((stats, offset))
+}
+
+fn write_species_info(dst: &mut [u8], info: &SpeciesInfo) -> usize {
+	let mut offset = write_string(dst, &info.name);
+
+	write_f32_to_slice(&mut dst[offset..], info.seed_growth);
+	write_f32_to_slice(&mut dst[offset+4..], info.child_growth);
+	write_u32_to_slice(&mut dst[offset+8..], info.lifespan as u32);
+	offset += 12;
+
+	for &c in info.consumption.iter() {
+		write_f32_to_slice(&mut dst[offset..], c);
+		offset += 4;
+	}
+
+	write_f32_to_slice(&mut dst[offset..], info.moisture_use);
+	write_f32_to_slice(&mut dst[offset+4..], info.canopy_shade);
+	offset += 8;
+
+	write_f32_to_slice(&mut dst[offset..], info.diversity_weight);
+	write_f32_to_slice(&mut dst[offset+4..], info.seed_chance);
+	write_f32_to_slice(&mut dst[offset+8..], info.seed_radius);
+	offset += 12;
+
+	dst[offset] = info.palette.len().min(MAX_PALETTE_SIZE) as u8;
+	offset += 1;
+
+	for col in info.palette.iter().take(MAX_PALETTE_SIZE) {
+		for &c in col.iter() {
+			write_f32_to_slice(&mut dst[offset..], c);
+			offset += 4;
+		}
+	}
+
+	offset + write_string(&mut dst[offset..], &info.model)
+}
+
+// How many bytes a SpeciesRegistry packet takes, so registries too big to send can be refused up front
+pub fn species_registry_size(species: &[SpeciesInfo]) -> usize {
+	let string_size = |s: &str| 1 + s.len().min(255);
+
+	// 48 bytes of numbers between the name and the palette
+	2 + species.iter().take(MAX_SPECIES)
+		.map(|info| string_size(&info.name) + 48 + 1 + info.palette.len().min(MAX_PALETTE_SIZE) * 12 + string_size(&info.model))
+		.sum::<usize>()
+}
+
+fn read_species_info(src: &[u8]) -> Option<(SpeciesInfo, usize)> {
+	let (name, mut offset) = read_string(src)?;
+
+	// growth rates, lifespan, consumption, soil usage, diversity, seeding and palette length
+	if src.len() < offset + 12 + 16 + 8 + 12 + 1 { return None }
+
+	let seed_growth = read_f32_from_slice(&src[offset..]);
+	let child_growth = read_f32_from_slice(&src[offset+4..]);
+	let lifespan = read_u32_from_slice(&src[offset+8..]) as i32;
+	offset += 12;
+
+	let mut consumption = [0.0f32; 4];
+	for c in consumption.iter_mut() {
+		*c = read_f32_from_slice(&src[offset..]);
+		offset += 4;
+	}
+
+	let moisture_use = read_f32_from_slice(&src[offset..]);
+	let canopy_shade = read_f32_from_slice(&src[offset+4..]);
+	offset += 8;
+
+	let diversity_weight = read_f32_from_slice(&src[offset..]);
+	let seed_chance = read_f32_from_slice(&src[offset+4..]);
+	let seed_radius = read_f32_from_slice(&src[offset+8..]);
+	offset += 12;
+
+	let palette_len = src[offset] as usize;
+	offset += 1;
+
+	if src.len() < offset + palette_len * 12 { return None }
+
+	let palette = (0..palette_len)
+		.map(|i| {
+			let base = offset + i * 12;
+			[
+				read_f32_from_slice(&src[base..]),
+				read_f32_from_slice(&src[base+4..]),
+				read_f32_from_slice(&src[base+8..]),
+			]
+		})
+		.collect();
+
+	offset += palette_len * 12;
+
+	let (model, model_size) = read_string(&src[offset..])?;
+	offset += model_size;
+
+	let info = SpeciesInfo {
+		name, seed_growth, child_growth, lifespan,
+		consumption, moisture_use, canopy_shade,
+		diversity_weight, seed_chance, seed_radius,
+		palette, model,
+	};
+
+	Some((info, offset))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Every prefix of a valid client packet is too short, and has to be refused rather than panic
+	fn assert_prefixes_refused(packet: Packet) {
+		let mut buf = [0u8; 128];
+		let len = packet.write(&mut buf);
+
+		for i in 1..len {
+			assert!(Packet::parse(&buf[..i]).is_none(), "0x{:x} parsed from {} of {} bytes", buf[0], i, len);
+		}
+
+		assert!(Packet::parse(&buf[..len]).is_some());
+	}
+
+	#[test]
+	fn short_client_packets_are_refused() {
+		assert_prefixes_refused(Packet::AttemptAuthSession(1234));
+		assert_prefixes_refused(Packet::SubscribeRegion(Region::new((1, 2), (3, 4))));
+		assert_prefixes_refused(Packet::RequestSoilOverlay(None));
+		assert_prefixes_refused(Packet::RequestStats(60));
+		assert_prefixes_refused(Packet::JoinWorld(1));
+		assert_prefixes_refused(Packet::RequestPlaceTree(1.0, 2.0, Species(0)));
+		assert_prefixes_refused(Packet::InspectTree(1234));
+	}
+
+	#[test]
+	fn largest_terrain_is_sent_in_pieces() {
+		let terrain = ::world::generate_terrain(1, ::world::MAX_WORLD_SIZE);
+		let mut received = Terrain::flat(terrain.width, terrain.height);
+		let mut buf = vec![0u8; MAX_PAYLOAD_SIZE];
+		let mut rows = 0;
+
+		for packet in terrain_packets(&terrain) {
+			let len = packet.write(&mut buf);
+			assert!(len <= MAX_PAYLOAD_SIZE);
+
+			match Packet::parse(&buf[..len]) {
+				Some(Packet::TerrainData(seed, w, h, first_row, tiles)) => {
+					assert_eq!((seed, w as usize, h as usize, first_row as usize), (terrain.seed, terrain.width, terrain.height, rows));
+					assert!(received.set_rows(first_row as usize, &tiles));
+					rows += tiles.len() / terrain.width;
+				}
+				_ => panic!("TerrainData didn't parse"),
+			}
+		}
+
+		assert_eq!(rows, terrain.height);
+
+		for (a, b) in terrain.tiles.iter().zip(received.tiles.iter()) {
+			assert_eq!(a.kind, b.kind);
+			assert!((a.elevation - b.elevation).abs() <= 1.0 / 255.0);
+		}
+	}
+}
@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::io::Read;
+
+use toml;
+use world::{SpeciesInfo, SpeciesRegistry};
+use packet::{species_registry_size, MAX_PAYLOAD_SIZE, MAX_SPECIES, MAX_PALETTE_SIZE};
+
+#[derive(Deserialize)]
+struct SpeciesFile {
+	species: Vec<SpeciesInfo>,
+}
+
+pub fn load_registry(path: &str) -> Result<SpeciesRegistry, String> {
+	let mut data = String::new();
+
+	File::open(path)
+		.and_then(|mut f| f.read_to_string(&mut data))
+		.map_err(|e| format!("Couldn't read '{}': {}", path, e))?;
+
+	parse_registry(&data, path)
+}
+
+// name is only used in error messages
+pub fn parse_registry(data: &str, name: &str) -> Result<SpeciesRegistry, String> {
+	let file: SpeciesFile = toml::from_str(data)
+		.map_err(|e| format!("Couldn't parse '{}': {}", name, e))?;
+
+	if file.species.is_empty() {
+		return Err(format!("'{}' doesn't define any species", name));
+	}
+
+	if file.species.len() > MAX_SPECIES {
+		return Err(format!("'{}' defines too many species ({} > {})", name, file.species.len(), MAX_SPECIES));
+	}
+
+	for info in &file.species {
+		if info.lifespan <= 0 {
+			return Err(format!("Species '{}' has a non-positive lifespan", info.name));
+		}
+
+		if info.seed_chance < 0.0 || info.seed_chance > 1.0 {
+			return Err(format!("Species '{}' has a seed_chance outside of [0, 1]", info.name));
+		}
+
+		if info.canopy_shade < 0.0 {
+			return Err(format!("Species '{}' has a negative canopy_shade", info.name));
+		}
+
+		if info.palette.is_empty() {
+			return Err(format!("Species '{}' has an empty palette", info.name));
+		}
+
+		if info.palette.len() > MAX_PALETTE_SIZE {
+			return Err(format!("Species '{}' has too many palette colors ({} > {})", info.name, info.palette.len(), MAX_PALETTE_SIZE));
+		}
+	}
+
+	// Sent to every client as they join
+	let size = species_registry_size(&file.species);
+	if size > MAX_PAYLOAD_SIZE {
+		return Err(format!("'{}' is too big to send to clients ({} bytes > {}), try fewer species or shorter palettes", name, size, MAX_PAYLOAD_SIZE));
+	}
+
+	Ok(SpeciesRegistry::new(file.species))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use packet::Packet;
+
+	fn species_toml(count: usize, palette_size: usize) -> String {
+		let palette = vec!["[0.5, 0.5, 0.5]"; palette_size].join(", ");
+
+		(0..count)
+			.map(|i| format!("[[species]]\nname = \"Species {}\"\nseed_growth = 1.0\nchild_growth = 1.0\nlifespan = 50\n\
+				consumption = [1.0, 0.8, 0.4, -1.0]\nmoisture_use = 0.05\ncanopy_shade = 0.3\ndiversity_weight = 1.0\n\
+				seed_chance = 0.05\nseed_radius = 2.0\npalette = [{}]\nmodel = \"broadleaf\"\n", i, palette))
+			.collect()
+	}
+
+	// As many species as fit in one packet
+	fn largest_count() -> usize {
+		(1..MAX_SPECIES + 1)
+			.take_while(|&n| parse_registry(&species_toml(n, 4), "test").is_ok())
+			.last().unwrap()
+	}
+
+	#[test]
+	fn largest_registry_can_be_sent() {
+		let count = largest_count();
+		let registry = parse_registry(&species_toml(count, 4), "test").unwrap();
+
+		let mut buf = [0u8; MAX_PAYLOAD_SIZE];
+		let len = Packet::SpeciesRegistry(registry.species.clone()).write(&mut buf);
+		assert_eq!(len, species_registry_size(&registry.species));
+
+		match Packet::parse(&buf[..len]) {
+			Some(Packet::SpeciesRegistry(species)) => assert_eq!(species.len(), count),
+			_ => panic!("Registry didn't survive the trip"),
+		}
+
+		assert!(parse_registry(&species_toml(count + 1, 4), "test").is_err());
+	}
+
+	#[test]
+	fn oversized_registries_are_refused() {
+		assert!(parse_registry(&species_toml(1, MAX_PALETTE_SIZE), "test").is_ok());
+		assert!(parse_registry(&species_toml(1, MAX_PALETTE_SIZE + 1), "test").is_err());
+		assert!(parse_registry(&species_toml(MAX_SPECIES + 1, 1), "test").is_err());
+	}
+}
@@ -0,0 +1,77 @@
+use world::SpeciesRegistry;
+
+// Seed, child, adult
+pub const NUM_STAGES: usize = 3;
+
+// Aggregates recorded once per world tick
+#[derive(Clone, Debug)]
+pub struct WorldStats {
+	pub tick: u32,
+
+	// Indexed by species, then stage
+	pub population: Vec<[u16; NUM_STAGES]>,
+	pub mean_health: f32,
+
+	pub global_diversity: f32,
+	// Indexed by chunk, row major
+	pub regional_diversity: Vec<f32>,
+
+	// Since the previous sample
+	pub births: u16,
+	pub deaths: u16,
+}
+
+impl WorldStats {
+	pub fn get_species_population(&self, idx: usize) -> u32 {
+		self.population.get(idx)
+			.map_or(0, |p| p.iter().map(|&n| n as u32).sum())
+	}
+
+	pub fn get_total_population(&self) -> u32 {
+		(0..self.population.len())
+			.map(|i| self.get_species_population(i))
+			.sum()
+	}
+
+	pub fn csv_header(registry: &SpeciesRegistry) -> String {
+		let mut header = String::from("tick,births,deaths,mean_health,global_diversity");
+
+		for info in registry.species.iter() {
+			for stage in ["seed", "child", "adult"].iter() {
+				header.push_str(&format!(",{}_{}", info.name, stage));
+			}
+		}
+
+		header
+	}
+
+	// Regional diversity is left out to keep rows a sane width
+	pub fn to_csv_row(&self) -> String {
+		let mut row = format!("{},{},{},{:.4},{:.4}",
+			self.tick, self.births, self.deaths, self.mean_health, self.global_diversity);
+
+		for stages in self.population.iter() {
+			for n in stages.iter() {
+				row.push_str(&format!(",{}", n));
+			}
+		}
+
+		row
+	}
+
+	pub fn to_json(&self) -> String {
+		let population = self.population.iter()
+			.map(|p| format!("[{},{},{}]", p[0], p[1], p[2]))
+			.collect::<Vec<_>>()
+			.join(",");
+
+		let regional_diversity = self.regional_diversity.iter()
+			.map(|d| format!("{:.4}", d))
+			.collect::<Vec<_>>()
+			.join(",");
+
+		format!("{{\"tick\":{},\"births\":{},\"deaths\":{},\"mean_health\":{:.4},\"global_diversity\":{:.4},\"population\":[{}],\"regional_diversity\":[{}]}}",
+			self.tick, self.births, self.deaths, self.mean_health, self.global_diversity,
+			population, regional_diversity)
+	}
+}
@@ -0,0 +1,157 @@
+use math::*;
+
+// Elevation is in [0, 1]
+const WATER_LEVEL: f32 = 0.3;
+const ROCK_LEVEL: f32 = 0.74;
+// Half width of rivers in noise space
+const RIVER_WIDTH: f32 = 0.025;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum TileKind {
+	Soil,
+	Water,
+	Rock,
+}
+
+impl TileKind {
+	pub fn to_byte(self) -> u8 {
+		self as u8
+	}
+
+	pub fn from_byte(b: u8) -> Option<TileKind> {
+		match b {
+			0 => Some(TileKind::Soil),
+			1 => Some(TileKind::Water),
+			2 => Some(TileKind::Rock),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TerrainTile {
+	pub kind: TileKind,
+	pub elevation: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Terrain {
+	pub seed: u32,
+	pub width: usize,
+	pub height: usize,
+
+	pub tiles: Vec<TerrainTile>,
+}
+
+impl Terrain {
+	pub fn flat(width: usize, height: usize) -> Terrain {
+		let tile = TerrainTile{ kind: TileKind::Soil, elevation: 0.0 };
+
+		Terrain {
+			seed: 0,
+			width, height,
+			tiles: vec![tile; width * height],
+		}
+	}
+
+	pub fn generate(seed: u32, width: usize, height: usize) -> Terrain {
+		let mut tiles = Vec::with_capacity(width * height);
+
+		for y in 0..height {
+			for x in 0..width {
+				let p = Vec2::new(x as f32, y as f32);
+
+				let elevation = fbm(seed, p * (1.0/9.0), 3);
+				let river = fbm(seed.wrapping_add(1), p * (1.0/16.0), 2);
+				let rockiness = fbm(seed.wrapping_add(2), p * (1.0/4.0), 2);
+
+				let kind = if elevation < WATER_LEVEL || (river - 0.5).abs() < RIVER_WIDTH {
+					TileKind::Water
+				} else if elevation > ROCK_LEVEL || rockiness > 0.78 {
+					TileKind::Rock
+				} else {
+					TileKind::Soil
+				};
+
+				// Water sits flat
+				let elevation = match kind {
+					TileKind::Water => WATER_LEVEL.min(elevation),
+					_ => elevation,
+				};
+
+				tiles.push(TerrainTile{ kind, elevation });
+			}
+		}
+
+		Terrain { seed, width, height, tiles }
+	}
+
+	// Overwrites whole rows from first_row on, as they arrive in TerrainData packets. False if they don't fit
+	pub fn set_rows(&mut self, first_row: usize, tiles: &[TerrainTile]) -> bool {
+		let start = first_row * self.width;
+		if start + tiles.len() > self.tiles.len() { return false }
+
+		self.tiles[start .. start + tiles.len()].copy_from_slice(tiles);
+		true
+	}
+
+	pub fn get(&self, x: usize, y: usize) -> Option<TerrainTile> {
+		if x >= self.width || y >= self.height { return None }
+		Some(self.tiles[x + y * self.width])
+	}
+
+	pub fn get_at(&self, p: Vec2) -> Option<TerrainTile> {
+		if p.x < 0.0 || p.y < 0.0 { return None }
+		self.get(p.x as usize, p.y as usize)
+	}
+
+	pub fn is_plantable(&self, p: Vec2) -> bool {
+		self.get_at(p).is_some_and(|t| t.kind == TileKind::Soil)
+	}
+}
+
+fn hash(seed: u32, x: i32, y: i32) -> f32 {
+	let mut h = seed
+		.wrapping_add((x as u32).wrapping_mul(374761393))
+		.wrapping_add((y as u32).wrapping_mul(668265263));
+
+	h = (h ^ (h >> 13)).wrapping_mul(1274126177);
+	h = h ^ (h >> 16);
+
+	(h & 0xFFFF) as f32 / 65535.0
+}
+
+fn value_noise(seed: u32, p: Vec2) -> f32 {
+	let (x, y) = (p.x.floor(), p.y.floor());
+	let (fx, fy) = (p.x - x, p.y - y);
+	let (x, y) = (x as i32, y as i32);
+
+	// Smoothstep
+	let (sx, sy) = (fx * fx * (3.0 - 2.0 * fx), fy * fy * (3.0 - 2.0 * fy));
+
+	let a = hash(seed, x, y);
+	let b = hash(seed, x+1, y);
+	let c = hash(seed, x, y+1);
+	let d = hash(seed, x+1, y+1);
+
+	let top = a + (b - a) * sx;
+	let bottom = c + (d - c) * sx;
+	top + (bottom - top) * sy
+}
+
+fn fbm(seed: u32, p: Vec2, octaves: u32) -> f32 {
+	let mut total = 0.0;
+	let mut amplitude = 1.0;
+	let mut max = 0.0;
+	let mut p = p;
+
+	for i in 0..octaves {
+		total += value_noise(seed.wrapping_add(i * 7919), p) * amplitude;
+		max += amplitude;
+
+		amplitude *= 0.5;
+		p = p * 2.0;
+	}
+
+	total / max
+}
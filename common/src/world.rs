@@ -1,30 +1,463 @@
 use math::*;
+use rand::{thread_rng, Rng, SeedableRng, StdRng};
+use std::ops::Index;
 
+use {write_u32_to_slice, write_f32_to_slice, read_u32_from_slice, read_f32_from_slice};
+use terrain::{Terrain, TileKind};
+use fauna::{Fauna, FaunaKind};
+use stats::{WorldStats, NUM_STAGES};
+use packet::MAX_SPECIES;
+
+// Width/height of a chunk in tiles
+pub const CHUNK_SIZE: usize = 7;
+
+pub type ChunkCoord = (u16, u16);
+
+pub fn chunk_for_pos(p: Vec2) -> ChunkCoord {
+	let x = p.x.max(0.0) as usize / CHUNK_SIZE;
+	let y = p.y.max(0.0) as usize / CHUNK_SIZE;
+	(x as u16, y as u16)
+}
+
+// Inclusive range of chunks
 #[derive(PartialEq, Copy, Clone, Debug)]
-pub enum Species {
-	A, B, C
+pub struct Region {
+	pub min: ChunkCoord,
+	pub max: ChunkCoord,
+}
+
+impl Region {
+	pub fn new(min: ChunkCoord, max: ChunkCoord) -> Region {
+		Region{ min, max }
+	}
+
+	pub fn contains(&self, c: ChunkCoord) -> bool {
+		c.0 >= self.min.0 && c.0 <= self.max.0
+		&& c.1 >= self.min.1 && c.1 <= self.max.1
+	}
+
+	pub fn chunks(&self) -> Vec<ChunkCoord> {
+		let mut cs = Vec::new();
+
+		for y in self.min.1 .. self.max.1+1 {
+			for x in self.min.0 .. self.max.0+1 {
+				cs.push((x, y));
+			}
+		}
+
+		cs
+	}
 }
 
-pub const ALL_SPECIES: [Species; 3] = {
-	use self::Species::*; 
-	[A, B, C]
-};
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum SoilChannel {
+	Moisture,
+	Nitrogen,
+	Shade,
+}
 
-impl Species {
+pub const NUM_SOIL_CHANNELS: usize = 3;
+pub const ALL_SOIL_CHANNELS: [SoilChannel; NUM_SOIL_CHANNELS] = [
+	SoilChannel::Moisture,
+	SoilChannel::Nitrogen,
+	SoilChannel::Shade,
+];
+
+impl SoilChannel {
 	pub fn to_byte(self) -> u8 {
+		self as u8
+	}
+
+	pub fn from_byte(b: u8) -> Option<SoilChannel> {
+		ALL_SOIL_CHANNELS.get(b as usize).cloned()
+	}
+
+	pub fn get_name(self) -> &'static str {
 		match self {
-			Species::A => 0,
-			Species::B => 1,
-			Species::C => 2,
+			SoilChannel::Moisture => "moisture",
+			SoilChannel::Nitrogen => "nitrogen",
+			SoilChannel::Shade => "shade",
 		}
 	}
 
-	pub fn from_byte(b: u8) -> Option<Self> {
+	// How much of a cell is mixed with its neighbours each tick
+	pub fn get_diffusion(self) -> f32 {
+		match self {
+			SoilChannel::Moisture => 0.8,
+			SoilChannel::Nitrogen => 1.0,
+			SoilChannel::Shade => 0.3,
+		}
+	}
+
+	// Fraction lost each tick
+	pub fn get_decay(self) -> f32 {
+		match self {
+			SoilChannel::Moisture => 0.02,
+			SoilChannel::Nitrogen => 0.0, // Has its own decay curve
+			SoilChannel::Shade => 0.4,
+		}
+	}
+
+	// Value that maps to full intensity when quantized for clients
+	pub fn get_display_max(self) -> f32 {
+		match self {
+			SoilChannel::Moisture => 3.0,
+			SoilChannel::Nitrogen => 20.0,
+			SoilChannel::Shade => 1.5,
+		}
+	}
+}
+
+// Ticks per season
+pub const SEASON_LENGTH: u32 = 30;
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Season {
+	Spring,
+	Summer,
+	Autumn,
+	Winter,
+}
+
+impl Season {
+	pub fn to_byte(self) -> u8 {
+		self as u8
+	}
+
+	pub fn from_byte(b: u8) -> Option<Season> {
+		match b {
+			0 => Some(Season::Spring),
+			1 => Some(Season::Summer),
+			2 => Some(Season::Autumn),
+			3 => Some(Season::Winter),
+			_ => None,
+		}
+	}
+
+	pub fn from_tick(tick: u32) -> Season {
+		Season::from_byte((tick / SEASON_LENGTH % 4) as u8).unwrap()
+	}
+
+	// Multiplier on the rate seeds and children grow
+	pub fn get_growth_multiplier(self) -> f32 {
+		match self {
+			Season::Spring => 1.5,
+			Season::Summer => 1.0,
+			Season::Autumn => 0.6,
+			Season::Winter => 0.0, // Dormant
+		}
+	}
+
+	pub fn get_rainfall_multiplier(self) -> f32 {
+		match self {
+			Season::Spring => 1.2,
+			Season::Summer => 0.5,
+			Season::Autumn => 1.0,
+			Season::Winter => 0.8,
+		}
+	}
+
+	pub fn get_evaporation_multiplier(self) -> f32 {
+		match self {
+			Season::Spring => 1.0,
+			Season::Summer => 1.8,
+			Season::Autumn => 0.8,
+			Season::Winter => 0.4,
+		}
+	}
+
+	// Nitrogen dropped by adults each tick as leaf litter
+	pub fn get_leaf_drop(self) -> f32 {
+		match self {
+			Season::Autumn => 0.15,
+			_ => 0.0,
+		}
+	}
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Weather {
+	Clear,
+	Rainstorm,
+	Drought,
+}
+
+impl Weather {
+	pub fn to_byte(self) -> u8 {
+		self as u8
+	}
+
+	pub fn from_byte(b: u8) -> Option<Weather> {
+		match b {
+			0 => Some(Weather::Clear),
+			1 => Some(Weather::Rainstorm),
+			2 => Some(Weather::Drought),
+			_ => None,
+		}
+	}
+
+	pub fn get_growth_multiplier(self) -> f32 {
+		match self {
+			Weather::Clear => 1.0,
+			Weather::Rainstorm => 1.1,
+			Weather::Drought => 0.7,
+		}
+	}
+
+	pub fn get_rainfall_multiplier(self) -> f32 {
+		match self {
+			Weather::Clear => 1.0,
+			Weather::Rainstorm => 4.0,
+			Weather::Drought => 0.0,
+		}
+	}
+
+	pub fn get_evaporation_multiplier(self) -> f32 {
+		match self {
+			Weather::Clear => 1.0,
+			Weather::Rainstorm => 0.5,
+			Weather::Drought => 2.5,
+		}
+	}
+
+	// Chance per tick of this weather starting, given the season
+	fn get_chance(self, season: Season) -> f32 {
+		match (self, season) {
+			(Weather::Clear, _) => 0.0,
+			(Weather::Rainstorm, Season::Summer) => 0.02,
+			(Weather::Rainstorm, _) => 0.06,
+			(Weather::Drought, Season::Summer) => 0.08,
+			(Weather::Drought, Season::Winter) => 0.0,
+			(Weather::Drought, _) => 0.01,
+		}
+	}
+
+	// Range of ticks this weather lasts for
+	fn get_duration(self) -> (u32, u32) {
+		match self {
+			Weather::Clear => (0, 0),
+			Weather::Rainstorm => (2, 6),
+			Weather::Drought => (8, 20),
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Climate {
+	pub tick: u32,
+	pub season: Season,
+	pub weather: Weather,
+
+	// Ticks until the weather clears
+	weather_remaining: u32,
+}
+
+impl Default for Climate {
+	fn default() -> Self {
+		Climate::new()
+	}
+}
+
+impl Climate {
+	pub fn new() -> Self {
+		Climate {
+			tick: 0,
+			season: Season::Spring,
+			weather: Weather::Clear,
+			weather_remaining: 0,
+		}
+	}
+
+	// Returns whether the season or weather changed
+	pub fn advance<R: Rng>(&mut self, rng: &mut R) -> bool {
+		let prev = (self.season, self.weather);
+
+		self.tick += 1;
+		self.season = Season::from_tick(self.tick);
+
+		if self.weather_remaining > 0 {
+			self.weather_remaining -= 1;
+		} else {
+			self.weather = self.roll_weather(rng);
+		}
+
+		prev != (self.season, self.weather)
+	}
+
+	fn roll_weather<R: Rng>(&mut self, rng: &mut R) -> Weather {
+		for &w in [Weather::Rainstorm, Weather::Drought].iter() {
+			if rng.gen::<f32>() < w.get_chance(self.season) {
+				let (min, max) = w.get_duration();
+				self.weather_remaining = rng.gen_range(min, max + 1);
+				return w
+			}
+		}
+
+		Weather::Clear
+	}
+
+	pub fn get_growth_multiplier(&self) -> f32 {
+		self.season.get_growth_multiplier() * self.weather.get_growth_multiplier()
+	}
+
+	pub fn get_rainfall_multiplier(&self) -> f32 {
+		self.season.get_rainfall_multiplier() * self.weather.get_rainfall_multiplier()
+	}
+
+	pub fn get_evaporation_multiplier(&self) -> f32 {
+		self.season.get_evaporation_multiplier() * self.weather.get_evaporation_multiplier()
+	}
+}
+
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub struct Species(pub u8);
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SpeciesInfo {
+	pub name: String,
+
+	// Multipliers on the base tick rate while a seed/child
+	pub seed_growth: f32,
+	pub child_growth: f32,
+	// Number of ticks spent as an adult before dying
+	pub lifespan: i32,
+
+	// Nitrogen drawn per maturity stage, negative values give back to the land
+	pub consumption: [f32; 4],
+	// Moisture drawn by a full grown tree, less for younger ones
+	pub moisture_use: f32,
+	// Shade cast by adults
+	pub canopy_shade: f32,
+	pub diversity_weight: f32,
+
+	// Chance per tick that an adult drops a seed, and how far it can land
+	pub seed_chance: f32,
+	pub seed_radius: f32,
+
+	// Leafage colors from unhealthy to healthy
+	pub palette: Vec<[f32; 3]>,
+	pub model: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct SpeciesRegistry {
+	pub species: Vec<SpeciesInfo>,
+}
+
+impl SpeciesRegistry {
+	pub fn new(species: Vec<SpeciesInfo>) -> Self {
+		assert!(species.len() <= MAX_SPECIES);
+		SpeciesRegistry{ species }
+	}
+
+	pub fn empty() -> Self {
+		SpeciesRegistry::new(Vec::new())
+	}
+
+	pub fn len(&self) -> usize {
+		self.species.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.species.is_empty()
+	}
+
+	pub fn is_valid(&self, s: Species) -> bool {
+		(s.0 as usize) < self.species.len()
+	}
+
+	pub fn get(&self, s: Species) -> Option<&SpeciesInfo> {
+		self.species.get(s.0 as usize)
+	}
+
+	pub fn all_species(&self) -> Vec<Species> {
+		(0..self.species.len()).map(|i| Species(i as u8)).collect()
+	}
+}
+
+impl Index<Species> for SpeciesRegistry {
+	type Output = SpeciesInfo;
+
+	fn index(&self, s: Species) -> &SpeciesInfo {
+		&self.species[s.0 as usize]
+	}
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Affliction {
+	// Spreads between nearby trees of the same species
+	Disease,
+	// Spreads through nearby adults of any species
+	Fire,
+}
+
+impl Affliction {
+	pub fn to_byte(self) -> u8 {
+		self as u8
+	}
+
+	pub fn from_byte(b: u8) -> Option<Affliction> {
 		match b {
-			0 => Some(Species::A),
-			1 => Some(Species::B),
-			2 => Some(Species::C),
-			_ => None
+			0 => Some(Affliction::Disease),
+			1 => Some(Affliction::Fire),
+			_ => None,
+		}
+	}
+}
+
+// Largest change to any trait between parent and seed
+const MUTATION_RATE: f32 = 0.08;
+
+pub const GROWTH_GENE_RANGE: (f32, f32) = (0.5, 2.0);
+pub const LIFESPAN_GENE_RANGE: (f32, f32) = (0.5, 2.0);
+
+// Per tree variation on top of its species
+#[derive(Copy, Clone, Debug)]
+pub struct Genome {
+	// Multiplier on growth rate. Fast growers die younger
+	pub growth: f32,
+	// Multiplier on the species' lifespan
+	pub lifespan: f32,
+	// [0, 1] - less thirsty and less affected by dry soil, but grows slower
+	pub drought_tolerance: f32,
+	// [-1, 1] - shifts leafage color
+	pub color_variation: f32,
+}
+
+impl Default for Genome {
+	fn default() -> Self {
+		Genome::new()
+	}
+}
+
+impl Genome {
+	pub fn new() -> Genome {
+		Genome {
+			growth: 1.0,
+			lifespan: 1.0,
+			drought_tolerance: 0.0,
+			color_variation: 0.0,
+		}
+	}
+
+	// For trees planted by players, so there's something to select from
+	pub fn wild<R: Rng>(rng: &mut R) -> Genome {
+		let mut g = Genome::new();
+		for _ in 0..3 { g = g.mutate(rng); }
+		g
+	}
+
+	pub fn mutate<R: Rng>(&self, rng: &mut R) -> Genome {
+		let mut vary = |v: f32, (min, max): (f32, f32)| {
+			let delta = rng.gen_range(-MUTATION_RATE, MUTATION_RATE) * (max - min);
+			(v + delta).max(min).min(max)
+		};
+
+		Genome {
+			growth: vary(self.growth, GROWTH_GENE_RANGE),
+			lifespan: vary(self.lifespan, LIFESPAN_GENE_RANGE),
+			drought_tolerance: vary(self.drought_tolerance, (0.0, 1.0)),
+			color_variation: vary(self.color_variation, (-1.0, 1.0)),
 		}
 	}
 }
@@ -35,7 +468,7 @@ pub enum Maturity {
 	Seed(i32),
 	// [0, 1000) - affected by tick rate
 	Child(i32),
-	// [0, 50)
+	// [0, lifespan)
 	Adult(i32),
 	Dead,
 }
@@ -46,64 +479,1076 @@ pub struct Tree {
 	pub maturity: Maturity,
 	pub pos: Vec2,
 	pub id: u32,
+
+	// Dropped by another tree rather than planted by a player
+	pub natural: bool,
+
+	// Ticks since the tree was afflicted
+	pub affliction: Option<(Affliction, i32)>,
+
+	pub genome: Genome,
+	// Number of ancestors since a player planted the line
+	pub generation: u32,
 }
 
 impl Tree {
 	pub fn is_dead(&self) -> bool {
-		match self.maturity {
-			Maturity::Dead => true,
-			_ => false,
-		}
+		matches!(self.maturity, Maturity::Dead)
 	}
 
 	pub fn is_mature(&self) -> bool {
-		match self.maturity {
-			Maturity::Adult(_) => true,
-			_ => false,
-		}
+		matches!(self.maturity, Maturity::Adult(_))
+	}
+
+	pub fn is_seed(&self) -> bool {
+		matches!(self.maturity, Maturity::Seed(_))
+	}
+
+	pub fn is_afflicted(&self) -> bool {
+		self.affliction.is_some()
 	}
 
 	pub fn is_growing(&self) -> bool {
-		match self.maturity {
-			Maturity::Seed(_) => true,
-			Maturity::Child(_) => true,
-			_ => false
-		}
+		matches!(self.maturity, Maturity::Seed(_) | Maturity::Child(_))
 	}
 
-	pub fn get_diversity_contribution(&self) -> f32 {
-		use self::Maturity::*;
+	pub fn get_diversity_contribution(&self, info: &SpeciesInfo) -> f32 {
+		let contribution = match self.get_maturity_stage(info) {
+			0 => 0.25,
+			1 => 0.5,
+			2 => 0.6,
+			3 => 1.0,
+			_ => 0.0,
+		};
 
-		match self.maturity {
-			Seed(_) => 0.25,
-			Child(_) => 0.5,
-			Adult(x) if x < 25 => 0.6,
-			Adult(_) => 1.0,
-			Dead => 0.0,
+		contribution * info.diversity_weight
+	}
+
+	pub fn get_consumption_rate(&self, info: &SpeciesInfo) -> f32 {
+		match self.get_maturity_stage(info) {
+			stage @ 0..=3 => info.consumption[stage as usize],
+			_ => 0.0,
 		}
 	}
 
-	pub fn get_consumption_rate(&self) -> f32 {
-		use self::Maturity::*;
+	pub fn get_moisture_use(&self, info: &SpeciesInfo) -> f32 {
+		match self.get_maturity_stage(info) {
+			stage @ 0..=3 => info.moisture_use * (stage as f32 + 1.0) / 4.0 * (1.0 - 0.5 * self.genome.drought_tolerance),
+			_ => 0.0,
+		}
+	}
 
-		match self.maturity {
-			Seed(_) => 1.0,
-			Child(_) => 0.8,
-			Adult(x) if x < 25 => 0.4,
-			Adult(_) => -1.0,
-			Dead => 0.0,
-		}		
+	pub fn get_lifespan(&self, info: &SpeciesInfo) -> i32 {
+		(info.lifespan as f32 * self.genome.lifespan / self.genome.growth) as i32
 	}
 
-	pub fn get_maturity_stage(&self) -> u8 {
+	// Multiplier on the species' growth rate given how wet the soil is
+	pub fn get_growth_rate(&self, moisture: f32) -> f32 {
+		let dryness = 1.0 - moisture.clamp(0.0, 1.0);
+		let drought_penalty = 1.0 - dryness * (1.0 - self.genome.drought_tolerance);
+
+		self.genome.growth * (1.0 - 0.3 * self.genome.drought_tolerance) * drought_penalty
+	}
+
+	pub fn get_maturity_stage(&self, info: &SpeciesInfo) -> u8 {
 		use self::Maturity::*;
 
 		match self.maturity {
 			Seed(_) => 0,
 			Child(_) => 1,
-			Adult(x) if x < 25 => 2,
+			Adult(x) if x < self.get_lifespan(info)/2 => 2,
 			Adult(_) => 3,
 			Dead => 4,
 		}		
 	}
-}
\ No newline at end of file
+}
+
+// Width/height of the world in tiles
+pub const DEFAULT_WORLD_SIZE: usize = 28;
+pub const MIN_WORLD_SIZE: usize = CHUNK_SIZE;
+// Bigger worlds get slow to simulate long before terrain rows stop fitting in a packet
+pub const MAX_WORLD_SIZE: usize = 256;
+const DIVERSITY_RANGE: f32 = 1.3;
+const DEATH_AFFECT_RANGE: f32 = 2.5;
+const GROWTH_AFFECT_RANGE: f32 = 2.3;
+const TREE_RADIUS: f32 = 0.3;
+const SEED_MIN_HEALTH: f32 = 0.2;
+const NITROGEN_DECAY: f32 = 0.03;
+const NITROGEN_SATURATION: f32 = 15.0;
+
+// Per tick chance of a random tree falling ill
+const OUTBREAK_CHANCE: f32 = 0.02;
+const DISEASE_RANGE: f32 = 1.5;
+const DISEASE_SPREAD: f32 = 0.35;
+const DISEASE_DURATION: i32 = 6;
+
+// Per tick chance of a random adult catching fire, before weather
+const IGNITION_CHANCE: f32 = 0.004;
+const FIRE_RANGE: f32 = 1.2;
+const FIRE_SPREAD: f32 = 0.6;
+const FIRE_DURATION: i32 = 1;
+
+const NUM_POLLINATORS: usize = 24;
+const NUM_HERBIVORES: usize = 8;
+// Each nearby pollinator adds this much to an adult's seed chance, up to three
+const POLLINATION_RANGE: f32 = 1.5;
+const POLLINATION_BOOST: f32 = 0.5;
+// Chance per tick that a herbivore eats a seed it's standing on
+const GRAZE_RANGE: f32 = 0.6;
+const GRAZE_CHANCE: f32 = 0.3;
+
+const MAX_TREES: usize = 2000;
+
+// Moisture added to every cell each tick
+const RAINFALL: f32 = 0.06;
+// Moisture held by water tiles
+const WATER_MOISTURE: f32 = 3.0;
+// Moisture above which trees stop benefiting
+const MOISTURE_COMFORT: f32 = 1.0;
+// Shade above which growth starts to suffer
+const SHADE_TOLERANCE: f32 = 0.5;
+
+// Seconds between ticks, unless overridden
+const DEFAULT_TICK_INTERVAL: f32 = 2.0;
+
+pub struct World {
+	pub registry: SpeciesRegistry,
+	pub climate: Climate,
+	pub terrain: Terrain,
+	pub fauna: Fauna,
+
+	pub trees: Vec<Tree>,
+	// Indexed by SoilChannel, then cell
+	pub land: [Vec<f32>; NUM_SOIL_CHANNELS],
+	// Derived from land each tick
+	pub land_health: Vec<f32>,
+
+	next_tree_id: u32,
+
+	// In seconds
+	pub tick_interval: f32,
+	time_since_tick: f32,
+
+	// Since stats were last taken
+	births: u16,
+	deaths: u16,
+
+	pub dead_trees: Vec<(u32, Vec2)>,
+	// id, pos, species, planted naturally, color variation
+	pub new_trees: Vec<(u32, Vec2, Species, bool, f32)>,
+	// id, pos, affliction, whether it started a new outbreak/fire
+	pub afflicted_trees: Vec<(u32, Vec2, Affliction, bool)>,
+	pub climate_changed: bool,
+
+	pub tuning: Tuning,
+
+	// Everything random in the simulation draws from this, so a seed reproduces a run
+	rng: StdRng,
+}
+
+// Balancing knobs, so they can be varied without recompiling
+#[derive(Copy, Clone, Debug)]
+pub struct Tuning {
+	pub diversity_range: f32,
+	pub death_affect_range: f32,
+	pub growth_affect_range: f32,
+	pub tree_radius: f32,
+	pub seed_min_health: f32,
+	pub rainfall: f32,
+	// Nitrogen lost from every cell each tick
+	pub nitrogen_decay: f32,
+	// Nitrogen above which a cell starts losing it quickly
+	pub nitrogen_saturation: f32,
+}
+
+pub const TUNING_NAMES: [&str; 8] = [
+	"diversity_range",
+	"death_affect_range",
+	"growth_affect_range",
+	"tree_radius",
+	"seed_min_health",
+	"rainfall",
+	"nitrogen_decay",
+	"nitrogen_saturation",
+];
+
+impl Default for Tuning {
+	fn default() -> Self {
+		Tuning::new()
+	}
+}
+
+impl Tuning {
+	pub fn new() -> Tuning {
+		Tuning {
+			diversity_range: DIVERSITY_RANGE,
+			death_affect_range: DEATH_AFFECT_RANGE,
+			growth_affect_range: GROWTH_AFFECT_RANGE,
+			tree_radius: TREE_RADIUS,
+			seed_min_health: SEED_MIN_HEALTH,
+			rainfall: RAINFALL,
+			nitrogen_decay: NITROGEN_DECAY,
+			nitrogen_saturation: NITROGEN_SATURATION,
+		}
+	}
+
+	pub fn get_mut(&mut self, name: &str) -> Option<&mut f32> {
+		match name {
+			"diversity_range" => Some(&mut self.diversity_range),
+			"death_affect_range" => Some(&mut self.death_affect_range),
+			"growth_affect_range" => Some(&mut self.growth_affect_range),
+			"tree_radius" => Some(&mut self.tree_radius),
+			"seed_min_health" => Some(&mut self.seed_min_health),
+			"rainfall" => Some(&mut self.rainfall),
+			"nitrogen_decay" => Some(&mut self.nitrogen_decay),
+			"nitrogen_saturation" => Some(&mut self.nitrogen_saturation),
+			_ => None,
+		}
+	}
+}
+
+impl World {
+	// The world takes its size from the terrain
+	pub fn new(registry: SpeciesRegistry, terrain: Terrain) -> Self {
+		World::with_seed(registry, terrain, thread_rng().gen())
+	}
+
+	pub fn with_seed(registry: SpeciesRegistry, terrain: Terrain, seed: u32) -> Self {
+		let size = Vec2::new(terrain.width as f32, terrain.height as f32);
+		let cells = terrain.width * terrain.height;
+
+		let mut rng = StdRng::from_seed(&[seed as usize][..]);
+		let fauna = Fauna::new(size, NUM_POLLINATORS, NUM_HERBIVORES, &mut rng);
+
+		World {
+			registry,
+			climate: Climate::new(),
+			terrain,
+			fauna,
+
+			trees: Vec::new(),
+			land: [vec![0.0; cells], vec![0.0; cells], vec![0.0; cells]],
+			land_health: vec![0.0; cells],
+			next_tree_id: 0,
+
+			tick_interval: DEFAULT_TICK_INTERVAL,
+			time_since_tick: 0.0,
+
+			births: 0,
+			deaths: 0,
+
+			dead_trees: Vec::new(),
+			new_trees: Vec::new(),
+			afflicted_trees: Vec::new(),
+			climate_changed: true,
+
+			tuning: Tuning::new(),
+
+			rng,
+		}
+	}
+
+	pub fn new_random(registry: SpeciesRegistry, terrain: Terrain) -> Self {
+		let mut world = World::new(registry, terrain);
+
+		for _ in 0..50 {
+			let pos = world.random_pos();
+			world.place_tree(Species(0), pos);
+		}
+
+		world.seed_soil();
+
+		for _ in 0..10 {
+			world.tick();
+		}
+
+		world
+	}
+
+	// Scatters a few nitrogen hotspots and waters everything
+	pub fn seed_soil(&mut self) {
+		for _ in 0..10 {
+			let idx = self.rng.gen_range(0, self.land_health.len());
+			self.land[SoilChannel::Nitrogen as usize][idx] = 100.0;
+		}
+
+		for m in self.land[SoilChannel::Moisture as usize].iter_mut() {
+			*m = MOISTURE_COMFORT;
+		}
+	}
+
+	// In tiles
+	pub fn get_size(&self) -> Vec2 {
+		Vec2::new(self.terrain.width as f32, self.terrain.height as f32)
+	}
+
+	// Anywhere on the map, plantable or not
+	pub fn random_pos(&mut self) -> Vec2 {
+		let size = self.get_size();
+		Vec2::new(self.rng.gen_range(0.0, size.x), self.rng.gen_range(0.0, size.y))
+	}
+
+	// Positions come from clients and save files, so NaN has to be caught too
+	pub fn is_on_map(&self, pos: Vec2) -> bool {
+		pos.x.is_finite() && pos.y.is_finite()
+			&& pos.x >= -0.5
+			&& pos.y >= -0.5
+			&& pos.x <= self.terrain.width as f32 - 0.5
+			&& pos.y <= self.terrain.height as f32 - 0.5
+	}
+
+	pub fn place_tree(&mut self, s: Species, pos: Vec2) -> Option<u32> {
+		self.plant(s, pos, None)
+	}
+
+	// parent is the genome and generation of the tree that dropped the seed, if any
+	fn plant(&mut self, s: Species, pos: Vec2, parent: Option<(Genome, u32)>) -> Option<u32> {
+		if !self.registry.is_valid(s) || self.trees.len() >= MAX_TREES {
+			return None
+		}
+
+		if !self.is_on_map(pos) || !self.terrain.is_plantable(pos) {
+			return None
+		}
+
+		let tree_radius = self.tuning.tree_radius;
+		let pos_available = self.trees.iter()
+			.all(|x| (x.pos - pos).length() > tree_radius);
+
+		if pos_available {
+			let id = self.next_tree_id;
+
+			let (genome, generation) = match parent {
+				Some((genome, generation)) => (genome.mutate(&mut self.rng), generation + 1),
+				None => (Genome::wild(&mut self.rng), 0),
+			};
+
+			let natural = parent.is_some();
+
+			self.trees.push(Tree {
+				species: s,
+				maturity: Maturity::Seed(0),
+				pos, id, natural,
+				affliction: None,
+				genome, generation,
+			});
+
+			self.new_trees.push((id, pos, s, natural, genome.color_variation));
+			self.next_tree_id += 1;
+			self.births = self.births.saturating_add(1);
+
+			Some(id)
+		} else {
+			None
+		}
+	}
+
+	// Moves fauna along by dt seconds, and ticks if tick_interval has passed.
+	// Returns whether it ticked
+	pub fn update(&mut self, dt: f32) -> bool {
+		self.update_fauna(dt);
+
+		self.time_since_tick += dt;
+		if self.time_since_tick < self.tick_interval { return false }
+		self.time_since_tick = 0.0;
+
+		self.tick();
+
+		true
+	}
+
+	pub fn tick(&mut self) {
+		use self::Maturity::*;
+
+		let tuning = self.tuning;
+
+		let climate_changed = self.climate.advance(&mut self.rng);
+		self.climate_changed = self.climate_changed || climate_changed;
+
+		let growth_multiplier = self.climate.get_growth_multiplier();
+		let rainfall = tuning.rainfall * self.climate.get_rainfall_multiplier();
+		let evaporation = SoilChannel::Moisture.get_decay() * self.climate.get_evaporation_multiplier();
+		let leaf_drop = self.climate.season.get_leaf_drop();
+
+		self.spread_afflictions();
+		self.graze();
+
+		let width = self.terrain.width;
+
+		for t in &mut self.trees {
+			let p = t.pos;
+			let (x,y) = (p.x as usize, p.y as usize);
+			let health = self.land_health[x + y*width];
+
+			let moisture = self.land[SoilChannel::Moisture as usize][x + y*width] / MOISTURE_COMFORT;
+
+			let tick_rate = (100.0 + 200.0*(1.0 - health)) * growth_multiplier * t.get_growth_rate(moisture);
+			let info = &self.registry[t.species];
+			let lifespan = t.get_lifespan(info);
+
+			t.maturity = match t.maturity {
+				Dead => Dead,
+				Adult(t) if t > lifespan => Dead,
+				Child(t) if t > 1000 => Adult(0),
+				Seed(t) if t > 1000 => Child(0),
+
+				Adult(i) => Adult(i + 1),
+				Child(t) => Child(t + (tick_rate * info.child_growth) as i32),
+				Seed(t) => Seed(t + (tick_rate * info.seed_growth) as i32),
+			};
+		}
+
+		// Water bodies keep the ground around them wet
+		for (m, tile) in self.land[SoilChannel::Moisture as usize].iter_mut().zip(self.terrain.tiles.iter()) {
+			if tile.kind == TileKind::Water {
+				*m = WATER_MOISTURE;
+			}
+		}
+
+		for &ch in ALL_SOIL_CHANNELS.iter() {
+			self.diffuse(ch);
+		}
+
+		let moisture_idx = SoilChannel::Moisture as usize;
+		let nitrogen_idx = SoilChannel::Nitrogen as usize;
+		let shade_idx = SoilChannel::Shade as usize;
+
+		for y in 0..self.terrain.height {
+			for x in 0..width {
+				let idx = x + y*width;
+
+				let pos = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+				let falloff = |t: &Tree, range: f32| 1.0 - (t.pos-pos).length() / range;
+
+				let nearby_dead: f32 = self.trees.iter()
+					.filter(|&t| t.is_dead())
+					.map(|t| falloff(t, tuning.death_affect_range))
+					.filter(|&d| d > 0.0)
+					.sum();
+
+				let nearby_growing: f32 = self.trees.iter()
+					.filter(|&t| t.is_growing())
+					.map(|t| t.get_consumption_rate(&self.registry[t.species]) * falloff(t, tuning.growth_affect_range))
+					.filter(|&d| d > 0.0)
+					.sum();
+
+				let nearby_mature: f32 = self.trees.iter()
+					.filter(|&t| t.is_mature())
+					.map(|t| falloff(t, tuning.growth_affect_range).max(0.0))
+					.sum();
+
+				let nearby_thirst: f32 = self.trees.iter()
+					.filter(|&t| !t.is_dead())
+					.map(|t| t.get_moisture_use(&self.registry[t.species]) * falloff(t, tuning.growth_affect_range).max(0.0))
+					.sum();
+
+				let nearby_canopy: f32 = self.trees.iter()
+					.filter(|&t| t.is_mature())
+					.map(|t| self.registry[t.species].canopy_shade * falloff(t, tuning.growth_affect_range).max(0.0))
+					.sum();
+
+				let local_diversity = self.get_diversity_at(pos, tuning.diversity_range);
+
+				let mut n = self.land[nitrogen_idx][idx];
+				n -= tuning.nitrogen_decay + ((n-tuning.nitrogen_saturation)/3.0).max(0.0); // decay
+				n += local_diversity * nearby_mature * 0.2;
+				n += nearby_dead * 3.0;
+				n += nearby_mature * leaf_drop;
+				n -= nearby_growing * 0.2;
+
+				// Shade from last tick slows evaporation
+				let mut s = self.land[shade_idx][idx];
+				let mut m = self.land[moisture_idx][idx];
+				m += rainfall;
+				// Higher ground drains faster
+				let elevation = self.terrain.tiles[idx].elevation;
+				m -= m * evaporation * (1.0 - s.min(1.0) * 0.5) * (0.5 + elevation);
+				m -= nearby_thirst;
+
+				s -= s * SoilChannel::Shade.get_decay();
+				s += nearby_canopy;
+
+				self.land[nitrogen_idx][idx] = n.max(0.0);
+				self.land[moisture_idx][idx] = m.max(0.0);
+				self.land[shade_idx][idx] = s.max(0.0);
+
+				self.land_health[idx] = self.get_soil_health(idx);
+			}
+		}
+
+		let num_dead = self.dead_trees.len();
+		self.dead_trees.extend(self.trees.iter().filter(|x| x.is_dead()).map(|x| (x.id, x.pos)));
+		self.deaths = self.deaths.saturating_add((self.dead_trees.len() - num_dead) as u16);
+
+		self.trees.retain(|x| !x.is_dead());
+
+		self.disperse_seeds();
+	}
+
+	pub fn update_fauna(&mut self, dt: f32) {
+		let flowering = self.trees.iter()
+			.filter(|t| t.is_mature() && !t.is_afflicted())
+			.map(|t| t.pos)
+			.collect::<Vec<_>>();
+
+		let seeds = self.trees.iter()
+			.filter(|t| t.is_seed())
+			.map(|t| t.pos)
+			.collect::<Vec<_>>();
+
+		self.fauna.update(dt, &flowering, &seeds, &mut self.rng);
+	}
+
+	fn graze(&mut self) {
+		for t in self.trees.iter_mut() {
+			if !t.is_seed() { continue }
+
+			let grazers = self.fauna.count_near(FaunaKind::Herbivore, t.pos, GRAZE_RANGE);
+
+			for _ in 0..grazers {
+				if self.rng.gen::<f32>() < GRAZE_CHANCE {
+					t.maturity = Maturity::Dead;
+					break;
+				}
+			}
+		}
+	}
+
+	fn spread_afflictions(&mut self) {
+		let mut new_afflictions = Vec::new();
+
+		for t in self.trees.iter().filter(|t| !t.is_dead()) {
+			let kind = match t.affliction {
+				Some((a, _)) => a,
+				None => continue,
+			};
+
+			let (range, spread) = match kind {
+				Affliction::Disease => (DISEASE_RANGE, DISEASE_SPREAD),
+				Affliction::Fire => (FIRE_RANGE, FIRE_SPREAD),
+			};
+
+			let candidates = self.trees.iter()
+				.filter(|o| !o.is_dead() && !o.is_afflicted())
+				.filter(|o| (o.pos - t.pos).length() < range)
+				.filter(|o| match kind {
+					Affliction::Disease => o.species == t.species,
+					Affliction::Fire => o.is_mature(),
+				});
+
+			for o in candidates {
+				// Mixed stands are harder to spread through
+				let resistance = self.get_diversity_at(o.pos, self.tuning.diversity_range).min(1.0);
+
+				if self.rng.gen::<f32>() < spread * (1.0 - resistance) {
+					new_afflictions.push((o.id, kind, false));
+				}
+			}
+		}
+
+		let ignition_chance = IGNITION_CHANCE * match (self.climate.season, self.climate.weather) {
+			(_, Weather::Rainstorm) => 0.0,
+			(Season::Winter, _) => 0.0,
+			(_, Weather::Drought) => 8.0,
+			(Season::Summer, _) => 2.0,
+			_ => 1.0,
+		};
+
+		if !self.trees.is_empty() && self.rng.gen::<f32>() < OUTBREAK_CHANCE {
+			let idx = self.rng.gen_range(0, self.trees.len());
+			let t = &self.trees[idx];
+			new_afflictions.push((t.id, Affliction::Disease, true));
+		}
+
+		if !self.trees.is_empty() && self.rng.gen::<f32>() < ignition_chance {
+			let idx = self.rng.gen_range(0, self.trees.len());
+			let t = &self.trees[idx];
+			if t.is_mature() {
+				new_afflictions.push((t.id, Affliction::Fire, true));
+			}
+		}
+
+		// Afflictions run their course
+		for t in self.trees.iter_mut() {
+			if let Some((kind, ticks)) = t.affliction {
+				let duration = match kind {
+					Affliction::Disease => DISEASE_DURATION,
+					Affliction::Fire => FIRE_DURATION,
+				};
+
+				if ticks + 1 >= duration {
+					t.maturity = Maturity::Dead;
+				}
+
+				t.affliction = Some((kind, ticks + 1));
+			}
+		}
+
+		for (id, kind, origin) in new_afflictions {
+			let tree = self.trees.iter_mut()
+				.find(|t| t.id == id && !t.is_dead() && !t.is_afflicted());
+
+			if let Some(t) = tree {
+				t.affliction = Some((kind, 0));
+				self.afflicted_trees.push((id, t.pos, kind, origin));
+			}
+		}
+	}
+
+	fn diffuse(&mut self, ch: SoilChannel) {
+		let mut blur_buf = vec![0.0f32; self.land_health.len()];
+
+		let ww = self.terrain.width as i32;
+		let wh = self.terrain.height as i32;
+
+		{
+			let land = &self.land[ch as usize];
+			let diffusion = ch.get_diffusion();
+
+			for y in 0..wh {
+				for x in 0..ww {
+					let sample = |x: i32, y: i32| {
+						// Clamp to edge
+						let x = x.max(0).min(ww-1);
+						let y = y.max(0).min(wh-1);
+
+						let idx = x + y*ww;
+						land[idx as usize]
+					};
+
+					let c: f32 = sample(x, y);
+					let o: f32 = [
+						sample(x+1, y),
+						sample(x, y+1),
+						sample(x-1, y),
+						sample(x, y-1),
+					].iter().sum();
+
+					let d: f32 = [
+						sample(x+1, y+1),
+						sample(x+1, y-1),
+						sample(x-1, y+1),
+						sample(x-1, y-1),
+					].iter().sum();
+
+					let blurred = c * 0.3 + o * 0.15 + d * 0.025;
+					blur_buf[(x + y*ww) as usize] = c + (blurred - c) * diffusion;
+				}
+			}
+		}
+
+		self.land[ch as usize].copy_from_slice(&blur_buf);
+	}
+
+	fn get_soil_health(&self, idx: usize) -> f32 {
+		let m = self.land[SoilChannel::Moisture as usize][idx];
+		let n = self.land[SoilChannel::Nitrogen as usize][idx];
+		let s = self.land[SoilChannel::Shade as usize][idx];
+
+		let nitrogen = 1.0 - (n + 1.0).powf(1.0/3.0) / (n + 1.0);
+		let moisture = (m / MOISTURE_COMFORT).min(1.0);
+		let light = 1.0 - (s - SHADE_TOLERANCE).clamp(0.0, 1.0) * 0.5;
+
+		nitrogen * (0.5 + 0.5 * moisture) * light
+	}
+
+	fn disperse_seeds(&mut self) {
+		if self.climate.season == Season::Winter { return }
+
+		let tree_radius = self.tuning.tree_radius;
+		let (registry, fauna, rng) = (&self.registry, &self.fauna, &mut self.rng);
+
+		let seeds = self.trees.iter()
+			.filter(|t| t.is_mature() && !t.is_afflicted())
+			.filter_map(|t| {
+				let info = &registry[t.species];
+
+				let pollinators = fauna.count_near(FaunaKind::Pollinator, t.pos, POLLINATION_RANGE).min(3);
+				let seed_chance = info.seed_chance * (1.0 + POLLINATION_BOOST * pollinators as f32);
+
+				if rng.gen::<f32>() >= seed_chance { return None }
+
+				let dir = Vec2::from_angle(rng.gen_range(0.0, 2.0*PI));
+				let dist = rng.gen_range(tree_radius, info.seed_radius.max(tree_radius * 2.0));
+
+				Some((t.species, t.pos + dir * dist, t.genome, t.generation))
+			})
+			.collect::<Vec<_>>();
+
+		for (species, pos, genome, generation) in seeds {
+			if pos.x < 0.0 || pos.y < 0.0 { continue }
+
+			let (x, y) = (pos.x as usize, pos.y as usize);
+			if x >= self.terrain.width || y >= self.terrain.height { continue }
+
+			let health = self.land_health[x + y*self.terrain.width];
+			if health < self.tuning.seed_min_health { continue }
+
+			self.plant(species, pos, Some((genome, generation)));
+		}
+	}
+
+	pub fn get_chunk_health(&self, c: ChunkCoord) -> Vec<u8> {
+		quantize_chunk(&self.land_health, &self.terrain, c, 1.0)
+	}
+
+	pub fn get_chunk_soil(&self, c: ChunkCoord, ch: SoilChannel) -> Vec<u8> {
+		quantize_chunk(&self.land[ch as usize], &self.terrain, c, ch.get_display_max())
+	}
+
+	// Resets births and deaths
+	pub fn take_stats(&mut self) -> WorldStats {
+		let mut population = vec![[0u16; NUM_STAGES]; self.registry.len()];
+
+		for t in self.trees.iter() {
+			let stage = match t.maturity {
+				Maturity::Seed(_) => 0,
+				Maturity::Child(_) => 1,
+				Maturity::Adult(_) => 2,
+				Maturity::Dead => continue,
+			};
+
+			let count = &mut population[t.species.0 as usize][stage];
+			*count = count.saturating_add(1);
+		}
+
+		let plantable = self.terrain.tiles.iter()
+			.zip(self.land_health.iter())
+			.filter(|&(t, _)| t.kind == TileKind::Soil)
+			.map(|(_, &h)| h)
+			.collect::<Vec<_>>();
+
+		let mean_health = plantable.iter().sum::<f32>() / plantable.len().max(1) as f32;
+
+		let world_size = self.get_size();
+		let global_diversity = self.get_diversity_at(world_size * 0.5, world_size.length());
+
+		let chunk_size = CHUNK_SIZE as f32;
+		let regional_diversity = all_chunks(&self.terrain).chunks().into_iter()
+			.map(|c| {
+				let centre = Vec2::new(c.0 as f32 + 0.5, c.1 as f32 + 0.5) * chunk_size;
+				self.get_diversity_at(centre, chunk_size * 0.75)
+			})
+			.collect();
+
+		let stats = WorldStats {
+			tick: self.climate.tick,
+			population, mean_health,
+			global_diversity, regional_diversity,
+			births: self.births,
+			deaths: self.deaths,
+		};
+
+		self.births = 0;
+		self.deaths = 0;
+
+		stats
+	}
+
+	pub fn get_diversity_at(&self, p: Vec2, r: f32) -> f32 {
+		let q = 2.0;
+
+		let trees_in_range = self.trees.iter()
+			.map(|t| (t, (t.pos-p).length()))
+			.filter(|&(_, d)| d < r)
+			.collect::<Vec<_>>();
+
+		let contribution = |t: &Tree, d: f32| t.get_diversity_contribution(&self.registry[t.species]) * 4.0 / d;
+
+		let total_potential_diversity = trees_in_range.iter()
+			.fold(0.0, |a, &(t, d)| a + contribution(t, d));
+
+		let abundances: Vec<_> = self.registry.all_species().iter()
+			.map(|x| trees_in_range.iter().filter(|&tp| tp.0.species == *x)
+				.fold(0.0, |a, &(t, d)| a + contribution(t, d)))
+			.filter(|&x| x > 0.0)
+			.map(|x| x / total_potential_diversity)
+			.collect();
+
+		let diversity = abundances.iter()
+			.map(|p| p * p.powf(q - 1.0))
+			.sum::<f32>();
+
+		if diversity > 0.0 {
+			let diversity = diversity.powf(-1.0 / (q - 1.0));
+			(diversity - 1.0) / (self.registry.len().max(2) - 1) as f32
+		} else {
+			0.0
+		}
+	}
+
+	// Everything needed to pick a world back up later. Terrain is regenerated from its seed
+	// and fauna are started afresh, so neither is stored
+	pub fn save(&self) -> Vec<u8> {
+		let mut data = vec![SAVE_VERSION];
+
+		push_u32(&mut data, self.terrain.seed);
+		// Terrain is always square, see generate_terrain
+		push_u32(&mut data, self.terrain.width as u32);
+		push_u32(&mut data, self.climate.tick);
+		data.push(self.climate.season.to_byte());
+		data.push(self.climate.weather.to_byte());
+		push_u32(&mut data, self.climate.weather_remaining);
+		push_u32(&mut data, self.next_tree_id);
+
+		for channel in self.land.iter() {
+			for &v in channel.iter() {
+				push_f32(&mut data, v);
+			}
+		}
+
+		push_u32(&mut data, self.trees.len() as u32);
+
+		for t in self.trees.iter() {
+			let (stage, age) = match t.maturity {
+				Maturity::Seed(a) => (0, a),
+				Maturity::Child(a) => (1, a),
+				Maturity::Adult(a) => (2, a),
+				Maturity::Dead => (3, 0),
+			};
+
+			data.push(t.species.0);
+			push_u32(&mut data, t.id);
+			push_f32(&mut data, t.pos.x);
+			push_f32(&mut data, t.pos.y);
+			data.push(t.natural as u8);
+			data.push(stage);
+			push_u32(&mut data, age as u32);
+
+			match t.affliction {
+				Some((a, ticks)) => {
+					data.push(a.to_byte());
+					push_u32(&mut data, ticks as u32);
+				}
+
+				None => data.push(0xFF),
+			}
+
+			push_f32(&mut data, t.genome.growth);
+			push_f32(&mut data, t.genome.lifespan);
+			push_f32(&mut data, t.genome.drought_tolerance);
+			push_f32(&mut data, t.genome.color_variation);
+			push_u32(&mut data, t.generation);
+		}
+
+		data
+	}
+
+	// Trees of species no longer in the registry are dropped
+	pub fn load(registry: SpeciesRegistry, data: &[u8]) -> Result<World, String> {
+		let mut r = SaveReader{ data, offset: 0 };
+
+		let version = r.u8()?;
+		if version == 0 || version > SAVE_VERSION {
+			return Err(format!("Unsupported save version {}", version))
+		}
+
+		let seed = r.u32()?;
+
+		// Worlds were all the same size before version 2
+		let size = if version >= 2 { r.u32()? as usize } else { DEFAULT_WORLD_SIZE };
+		if !(MIN_WORLD_SIZE..=MAX_WORLD_SIZE).contains(&size) {
+			return Err(format!("Invalid world size {} in save", size))
+		}
+
+		let mut world = World::new(registry, generate_terrain(seed, size));
+
+		world.climate.tick = r.u32()?;
+		world.climate.season = Season::from_byte(r.u8()?).ok_or("Invalid season in save")?;
+		world.climate.weather = Weather::from_byte(r.u8()?).ok_or("Invalid weather in save")?;
+		world.climate.weather_remaining = r.u32()?;
+		world.next_tree_id = r.u32()?;
+
+		for channel in world.land.iter_mut() {
+			for v in channel.iter_mut() {
+				*v = r.f32()?;
+			}
+		}
+
+		let num_trees = r.u32()?;
+
+		for _ in 0..num_trees {
+			let species = Species(r.u8()?);
+			let id = r.u32()?;
+			let pos = Vec2::new(r.f32()?, r.f32()?);
+			let natural = r.u8()? != 0;
+			let stage = r.u8()?;
+			let age = r.u32()? as i32;
+
+			let maturity = match stage {
+				0 => Maturity::Seed(age),
+				1 => Maturity::Child(age),
+				2 => Maturity::Adult(age),
+				3 => Maturity::Dead,
+				_ => return Err(format!("Invalid maturity {} in save", stage)),
+			};
+
+			let affliction = match r.u8()? {
+				0xFF => None,
+				b => {
+					let a = Affliction::from_byte(b).ok_or("Invalid affliction in save")?;
+					Some((a, r.u32()? as i32))
+				}
+			};
+
+			let genome = Genome {
+				growth: r.f32()?,
+				lifespan: r.f32()?,
+				drought_tolerance: r.f32()?,
+				color_variation: r.f32()?,
+			};
+
+			let generation = r.u32()?;
+
+			// Dropped rather than failing the whole load, same as species that have since been removed
+			if !world.registry.is_valid(species) || !world.is_on_map(pos) {
+				continue
+			}
+
+			world.trees.push(Tree {
+				species, maturity, pos, id, natural,
+				affliction, genome, generation,
+			});
+		}
+
+		for idx in 0..world.land_health.len() {
+			world.land_health[idx] = world.get_soil_health(idx);
+		}
+
+		Ok(world)
+	}
+}
+
+// Bumped whenever the save layout changes
+const SAVE_VERSION: u8 = 2;
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+	let mut bytes = [0u8; 4];
+	write_u32_to_slice(&mut bytes, value);
+	data.extend_from_slice(&bytes);
+}
+
+fn push_f32(data: &mut Vec<u8>, value: f32) {
+	let mut bytes = [0u8; 4];
+	write_f32_to_slice(&mut bytes, value);
+	data.extend_from_slice(&bytes);
+}
+
+struct SaveReader<'a> {
+	data: &'a [u8],
+	offset: usize,
+}
+
+impl<'a> SaveReader<'a> {
+	fn take(&mut self, size: usize) -> Result<&'a [u8], String> {
+		if self.offset + size > self.data.len() {
+			return Err("Save is truncated".to_string())
+		}
+
+		let bytes = &self.data[self.offset .. self.offset + size];
+		self.offset += size;
+		Ok(bytes)
+	}
+
+	fn u8(&mut self) -> Result<u8, String> {
+		self.take(1).map(|b| b[0])
+	}
+
+	fn u32(&mut self) -> Result<u32, String> {
+		self.take(4).map(read_u32_from_slice)
+	}
+
+	fn f32(&mut self) -> Result<f32, String> {
+		self.take(4).map(read_f32_from_slice)
+	}
+}
+
+
+fn quantize_chunk(values: &[f32], terrain: &Terrain, c: ChunkCoord, max: f32) -> Vec<u8> {
+	let mut qs = vec![0u8; CHUNK_SIZE * CHUNK_SIZE];
+
+	let base_x = c.0 as usize * CHUNK_SIZE;
+	let base_y = c.1 as usize * CHUNK_SIZE;
+
+	for y in 0..CHUNK_SIZE {
+		for x in 0..CHUNK_SIZE {
+			let (wx, wy) = (base_x + x, base_y + y);
+			if wx >= terrain.width || wy >= terrain.height { continue }
+
+			let v = values[wx + wy*terrain.width] / max;
+			qs[x + y*CHUNK_SIZE] = (v.clamp(0.0, 1.0) * 255.0) as u8;
+		}
+	}
+
+	qs
+}
+
+// Square, size tiles across
+pub fn generate_terrain(seed: u32, size: usize) -> Terrain {
+	Terrain::generate(seed, size, size)
+}
+
+pub fn all_chunks(terrain: &Terrain) -> Region {
+	let chunks_x = terrain.width.div_ceil(CHUNK_SIZE);
+	let chunks_y = terrain.height.div_ceil(CHUNK_SIZE);
+	Region::new((0, 0), (chunks_x as u16 - 1, chunks_y as u16 - 1))
+}
+
+pub fn clamp_region(terrain: &Terrain, r: Region) -> Region {
+	let bounds = all_chunks(terrain);
+
+	let min = (r.min.0.min(bounds.max.0), r.min.1.min(bounds.max.1));
+	let max = (r.max.0.min(bounds.max.0).max(min.0), r.max.1.min(bounds.max.1).max(min.1));
+
+	Region::new(min, max)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use species;
+
+	fn world() -> World {
+		let registry = species::parse_registry(include_str!("../../server/species.toml"), "species.toml").unwrap();
+		World::new(registry, generate_terrain(1, MIN_WORLD_SIZE))
+	}
+
+	fn plantable_pos(world: &World) -> Vec2 {
+		(0..MIN_WORLD_SIZE * MIN_WORLD_SIZE)
+			.map(|i| Vec2::new((i % MIN_WORLD_SIZE) as f32 + 0.25, (i / MIN_WORLD_SIZE) as f32 + 0.25))
+			.find(|&pos| world.terrain.is_plantable(pos))
+			.unwrap()
+	}
+
+	#[test]
+	fn trees_off_the_map_are_refused() {
+		let mut world = world();
+		let pos = plantable_pos(&world);
+
+		assert!(world.place_tree(Species(0), Vec2::new(f32::NAN, pos.y)).is_none());
+		assert!(world.place_tree(Species(0), Vec2::new(pos.x, f32::INFINITY)).is_none());
+		assert!(world.place_tree(Species(0), Vec2::new(-1.0, pos.y)).is_none());
+		assert!(world.place_tree(Species(0), Vec2::new(pos.x, MIN_WORLD_SIZE as f32)).is_none());
+		assert!(world.place_tree(Species(0), pos).is_some());
+	}
+
+	#[test]
+	fn trees_off_the_map_are_dropped_from_saves() {
+		let mut world = world();
+		let pos = plantable_pos(&world);
+		world.place_tree(Species(0), pos).unwrap();
+
+		let mut save = world.save();
+		assert_eq!(World::load(world.registry.clone(), &save).unwrap().trees.len(), 1);
+
+		// The tree's x, as a hand edited or damaged save might have it
+		let mut x = [0u8; 4];
+		write_f32_to_slice(&mut x, pos.x);
+		let offset = save.windows(4).rposition(|w| w == x).unwrap();
+
+		for &bad_x in [f32::NAN, -10.0, 1000.0].iter() {
+			write_f32_to_slice(&mut save[offset..], bad_x);
+			assert_eq!(World::load(world.registry.clone(), &save).unwrap().trees.len(), 0);
+		}
+	}
+}
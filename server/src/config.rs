@@ -0,0 +1,374 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+
+use toml;
+use fileserver;
+use log;
+use tls::CertSource;
+use world::{TICK_DURATION, DEFAULT_WORLD_SIZE, MIN_WORLD_SIZE, MAX_WORLD_SIZE};
+
+// Read if it exists and no other file is given
+pub const DEFAULT_CONFIG_PATH: &str = "server.toml";
+
+// Everything in the config file is optional, anything left out keeps its default
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	pub http: HttpConfig,
+	pub worlds: WorldsConfig,
+	pub limits: LimitsConfig,
+	pub compression: CompressionConfig,
+	pub log: LogConfig,
+	pub tls: TlsConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+	// Serves files and takes websocket upgrades
+	pub listen: String,
+	// Separate websocket only address, for clients built to expect one
+	pub ws_listen: Option<String>,
+	// Given to clients in /config.json. They connect back to the page's origin if not set
+	pub websocket_url: Option<String>,
+	// Pages that can open websockets besides our own, e.g. "https://example.com", or "*" for any
+	pub allowed_origins: Vec<String>,
+
+	pub doc_root: PathBuf,
+	pub build_dir: PathBuf,
+	pub workers: usize,
+	pub max_queued: usize,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldsConfig {
+	// List of hosted worlds, a single default world if it doesn't exist
+	pub file: String,
+	// Where world saves and stats logs go
+	pub save_dir: String,
+
+	// Defaults for worlds that don't set their own
+	pub species: String,
+	pub max_players: u16,
+	pub tick_duration: u64,
+	pub size: usize,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+	// Per connection. Anything over is dropped, and connections that keep it up are closed
+	pub max_packets_per_second: u32,
+	// Websocket connections open at once, in total and from any one address
+	pub max_connections: usize,
+	pub max_connections_per_ip: usize,
+}
+
+// permessage-deflate for websocket messages, when the client offers it
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+	pub enabled: bool,
+	// Lets messages refer back to earlier ones, at the cost of a few hundred KB per connection
+	pub context_takeover: bool,
+	// In bytes. Shorter messages are sent as they are
+	pub min_size: usize,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+	pub level: String,
+}
+
+// Off unless a certificate and key are given, or self_signed is set
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+	// PEM files, reread on SIGHUP
+	pub cert: Option<String>,
+	pub key: Option<String>,
+	// Generates a certificate for localhost at startup, for testing
+	pub self_signed: bool,
+}
+
+impl Default for HttpConfig {
+	fn default() -> Self {
+		let files = fileserver::Config::default();
+
+		HttpConfig {
+			listen: String::from("0.0.0.0:8000"),
+			ws_listen: None,
+			websocket_url: None,
+			allowed_origins: Vec::new(),
+
+			doc_root: files.doc_root,
+			build_dir: files.build_dir,
+			workers: files.workers,
+			max_queued: files.max_queued,
+		}
+	}
+}
+
+impl Default for WorldsConfig {
+	fn default() -> Self {
+		WorldsConfig {
+			file: String::from("worlds.toml"),
+			save_dir: String::from("saves"),
+
+			species: String::from("species.toml"),
+			max_players: 32,
+			tick_duration: TICK_DURATION,
+			size: DEFAULT_WORLD_SIZE,
+		}
+	}
+}
+
+impl Default for LimitsConfig {
+	fn default() -> Self {
+		LimitsConfig {
+			max_packets_per_second: 50,
+			max_connections: 256,
+			max_connections_per_ip: 8,
+		}
+	}
+}
+
+impl Default for CompressionConfig {
+	fn default() -> Self {
+		CompressionConfig {
+			enabled: true,
+			context_takeover: true,
+			min_size: 64,
+		}
+	}
+}
+
+impl Default for LogConfig {
+	fn default() -> Self {
+		LogConfig {
+			level: String::from("info"),
+		}
+	}
+}
+
+impl HttpConfig {
+	pub fn get_fileserver_config(&self) -> fileserver::Config {
+		fileserver::Config {
+			doc_root: self.doc_root.clone(),
+			build_dir: self.build_dir.clone(),
+			workers: self.workers,
+			max_queued: self.max_queued,
+			websocket_url: self.websocket_url.clone(),
+		}
+	}
+}
+
+impl TlsConfig {
+	pub fn get_cert_source(&self) -> Option<CertSource> {
+		if self.self_signed {
+			return Some(CertSource::SelfSigned);
+		}
+
+		match (&self.cert, &self.key) {
+			(Some(cert), Some(key)) => Some(CertSource::Files(cert.clone(), key.clone())),
+			_ => None,
+		}
+	}
+}
+
+impl Config {
+	// A missing file is only an error if it was asked for
+	pub fn load(path: &str, required: bool) -> Result<Config, String> {
+		let mut data = String::new();
+
+		match File::open(path).and_then(|mut f| f.read_to_string(&mut data)) {
+			Ok(_) => {},
+			Err(ref e) if !required && e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+			Err(e) => return Err(format!("Couldn't read '{}': {}", path, e)),
+		}
+
+		toml::from_str(&data)
+			.map_err(|e| format!("Couldn't parse '{}': {}", path, e))
+	}
+
+	// For command line overrides. Returns false if the option isn't a config option
+	pub fn set(&mut self, option: &str, value: &str) -> Result<bool, String> {
+		match option {
+			"--listen" => self.http.listen = value.to_string(),
+			"--ws-listen" => self.http.ws_listen = Some(value.to_string()),
+			"--websocket-url" => self.http.websocket_url = Some(value.to_string()),
+			"--allowed-origin" => self.http.allowed_origins.push(value.to_string()),
+			"--doc-root" => self.http.doc_root = PathBuf::from(value),
+			"--build-dir" => self.http.build_dir = PathBuf::from(value),
+			"--http-workers" => self.http.workers = parse_value(option, value)?,
+			"--worlds" => self.worlds.file = value.to_string(),
+			"--save-dir" => self.worlds.save_dir = value.to_string(),
+			"--tick-duration" => self.worlds.tick_duration = parse_value(option, value)?,
+			"--world-size" => self.worlds.size = parse_value(option, value)?,
+			"--max-packets-per-second" => self.limits.max_packets_per_second = parse_value(option, value)?,
+			"--max-connections" => self.limits.max_connections = parse_value(option, value)?,
+			"--max-connections-per-ip" => self.limits.max_connections_per_ip = parse_value(option, value)?,
+			"--compression" => self.compression.enabled = parse_value(option, value)?,
+			"--compression-context-takeover" => self.compression.context_takeover = parse_value(option, value)?,
+			"--compression-min-size" => self.compression.min_size = parse_value(option, value)?,
+			"--log-level" => self.log.level = value.to_string(),
+			"--tls-cert" => self.tls.cert = Some(value.to_string()),
+			"--tls-key" => self.tls.key = Some(value.to_string()),
+			"--tls-self-signed" => self.tls.self_signed = parse_value(option, value)?,
+			_ => return Ok(false),
+		}
+
+		Ok(true)
+	}
+
+	pub fn validate(&self) -> Result<(), String> {
+		check_address("http.listen", &self.http.listen)?;
+
+		if let Some(ref ws_listen) = self.http.ws_listen {
+			check_address("http.ws_listen", ws_listen)?;
+
+			if *ws_listen == self.http.listen {
+				return Err(format!("http.ws_listen can't be the same as http.listen ({})", ws_listen));
+			}
+		}
+
+		if let Some(ref url) = self.http.websocket_url {
+			check_websocket_url(url)?;
+		}
+
+		for origin in self.http.allowed_origins.iter() {
+			check_origin(origin)?;
+		}
+
+		if !self.http.doc_root.is_dir() {
+			return Err(format!("http.doc_root '{}' isn't a directory", self.http.doc_root.display()));
+		}
+
+		check_nonzero("http.workers", self.http.workers as u64)?;
+		check_nonzero("http.max_queued", self.http.max_queued as u64)?;
+		check_nonzero("worlds.max_players", self.worlds.max_players as u64)?;
+		check_nonzero("worlds.tick_duration", self.worlds.tick_duration)?;
+		check_nonzero("limits.max_packets_per_second", self.limits.max_packets_per_second as u64)?;
+		check_nonzero("limits.max_connections", self.limits.max_connections as u64)?;
+		check_nonzero("limits.max_connections_per_ip", self.limits.max_connections_per_ip as u64)?;
+
+		if self.worlds.size < MIN_WORLD_SIZE || self.worlds.size > MAX_WORLD_SIZE {
+			return Err(format!("worlds.size must be between {} and {} (got {})", MIN_WORLD_SIZE, MAX_WORLD_SIZE, self.worlds.size));
+		}
+
+		if self.worlds.save_dir.is_empty() {
+			return Err(String::from("worlds.save_dir can't be empty"));
+		}
+
+		self.get_log_level()?;
+
+		if self.tls.cert.is_some() != self.tls.key.is_some() {
+			return Err(String::from("tls.cert and tls.key have to be given together"));
+		}
+
+		if self.tls.self_signed && self.tls.cert.is_some() {
+			return Err(String::from("tls.self_signed can't be used along with tls.cert and tls.key"));
+		}
+
+		Ok(())
+	}
+
+	pub fn get_log_level(&self) -> Result<log::Level, String> {
+		log::Level::from_name(&self.log.level)
+			.map_err(|e| format!("log.level: {}", e))
+	}
+
+	pub fn print(&self) {
+		let ws_listen = self.http.ws_listen.as_ref().map_or("none", |s| s.as_str());
+		let websocket_url = self.http.websocket_url.as_ref().map_or("page origin", |s| s.as_str());
+		let allowed_origins = if self.http.allowed_origins.is_empty() {
+			String::from("same origin only")
+		} else {
+			self.http.allowed_origins.join(", ")
+		};
+
+		let tls_cert = self.tls.cert.as_ref().map_or("none", |s| s.as_str());
+		let tls_key = self.tls.key.as_ref().map_or("none", |s| s.as_str());
+
+		println!("{:<32} {}", "http.listen", self.http.listen);
+		println!("{:<32} {}", "http.ws_listen", ws_listen);
+		println!("{:<32} {}", "http.websocket_url", websocket_url);
+		println!("{:<32} {}", "http.allowed_origins", allowed_origins);
+		println!("{:<32} {}", "http.doc_root", self.http.doc_root.display());
+		println!("{:<32} {}", "http.build_dir", self.http.build_dir.display());
+		println!("{:<32} {}", "http.workers", self.http.workers);
+		println!("{:<32} {}", "http.max_queued", self.http.max_queued);
+		println!("{:<32} {}", "worlds.file", self.worlds.file);
+		println!("{:<32} {}", "worlds.save_dir", self.worlds.save_dir);
+		println!("{:<32} {}", "worlds.species", self.worlds.species);
+		println!("{:<32} {}", "worlds.max_players", self.worlds.max_players);
+		println!("{:<32} {}ms", "worlds.tick_duration", self.worlds.tick_duration);
+		println!("{:<32} {}", "worlds.size", self.worlds.size);
+		println!("{:<32} {}", "limits.max_packets_per_second", self.limits.max_packets_per_second);
+		println!("{:<32} {}", "limits.max_connections", self.limits.max_connections);
+		println!("{:<32} {}", "limits.max_connections_per_ip", self.limits.max_connections_per_ip);
+		println!("{:<32} {}", "compression.enabled", self.compression.enabled);
+		println!("{:<32} {}", "compression.context_takeover", self.compression.context_takeover);
+		println!("{:<32} {} bytes", "compression.min_size", self.compression.min_size);
+		println!("{:<32} {}", "log.level", self.log.level);
+		println!("{:<32} {}", "tls.cert", tls_cert);
+		println!("{:<32} {}", "tls.key", tls_key);
+		println!("{:<32} {}", "tls.self_signed", self.tls.self_signed);
+	}
+}
+
+fn parse_value<T: ::std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+	value.parse().map_err(|_| format!("Invalid value '{}' for {}", value, option))
+}
+
+fn check_address(name: &str, address: &str) -> Result<(), String> {
+	if let Ok(mut addrs) = address.to_socket_addrs() {
+		if addrs.next().is_some() { return Ok(()) }
+	}
+
+	Err(format!("{} '{}' isn't a valid address (expected host:port, e.g. 0.0.0.0:8000)", name, address))
+}
+
+// Ends up in json as is, so anything that would need escaping is refused
+fn check_websocket_url(url: &str) -> Result<(), String> {
+	let has_scheme = url.starts_with("ws://") || url.starts_with("wss://");
+	let has_host = url.split_once("://").map(|(_, rest)| rest).is_some_and(|rest| !rest.is_empty() && !rest.starts_with('/'));
+	let printable = url.chars().all(|c| c > ' ' && c <= '~' && c != '"' && c != '\\');
+
+	if !has_scheme || !has_host || !printable {
+		return Err(format!("http.websocket_url '{}' isn't a valid websocket url (expected e.g. wss://example.com:8000/)", url));
+	}
+
+	Ok(())
+}
+
+// Compared against Origin headers as is, which never have a path
+fn check_origin(origin: &str) -> Result<(), String> {
+	if origin == "*" {
+		return Ok(())
+	}
+
+	let host = if origin.starts_with("http://") || origin.starts_with("https://") {
+		origin.split_once("://").map(|(_, rest)| rest).unwrap_or("")
+	} else {
+		""
+	};
+
+	if host.is_empty() || host.contains('/') {
+		return Err(format!("http.allowed_origins entry '{}' isn't an origin (expected e.g. https://example.com or *)", origin));
+	}
+
+	Ok(())
+}
+
+fn check_nonzero(name: &str, value: u64) -> Result<(), String> {
+	if value == 0 {
+		return Err(format!("{} must be greater than 0", name));
+	}
+
+	Ok(())
+}
@@ -1,12 +1,23 @@
-use std::net::TcpStream;
 use std::io::{Write, Read};
-use common::Packet;
+use std::net::IpAddr;
+use std::time::Instant;
+use common::{Packet, MAX_PAYLOAD_SIZE};
+use common::world::{Region, ChunkCoord, SoilChannel};
+use world::WorldID;
+use config::LimitsConfig;
+use tls::Stream;
 use ws;
 
 pub type ConnectionID = u32;
 
+// Room for the frame header, and the few bytes deflate adds to payloads it can't shrink
+const PACKET_BUFFER_SIZE: usize = MAX_PAYLOAD_SIZE + 64;
+
 pub const MAX_FAILED_AUTH_ATTEMPTS: i32 = 100;
 
+// Keys are nine base 3 digits, the way the auth screen shows them
+pub const MAX_SESSION_KEY: u32 = 19683;
+
 #[derive(Debug)]
 pub enum ConnectionState {
 	NoAuth,
@@ -18,12 +29,28 @@ pub enum ConnectionState {
 }
 
 pub struct Connection {
-	pub stream: TcpStream,
+	pub stream: Stream,
 	pub state: ConnectionState,
 	pub failed_auth_attempts: i32,
 
 	pub session_id: Option<u32>,
 	pub id: ConnectionID,
+	pub address: Option<IpAddr>,
+
+	pub world: Option<WorldID>,
+	pub region: Option<Region>,
+	pub overlay: Option<SoilChannel>,
+
+	// Set if the client agreed to permessage-deflate
+	deflate: Option<ws::Deflate>,
+
+	// Packets that can be read before hitting the rate limit, topped up over time
+	packet_allowance: f32,
+	allowance_updated: Instant,
+	// So hitting the limit is only logged once until the connection backs off
+	rate_limited: bool,
+	// Since the connection last backed off
+	dropped_packets: u32,
 }
 
 impl Connection {
@@ -39,9 +66,58 @@ impl Connection {
 		match_enum!(self.state, ConnectionState::Ready)
 	}
 
-	pub fn send_payload(&mut self, mut packet_buffer: &mut [u8], payload: &[u8]) {
-		let packet = ws::encode_ws_packet(&mut packet_buffer, &payload);
-		let _ = self.stream.write_all(&packet);
+	pub fn is_in_world(&self, world: WorldID) -> bool {
+		self.is_ready() && self.world == Some(world)
+	}
+
+	pub fn is_subscribed_to(&self, chunk: ChunkCoord) -> bool {
+		self.region.is_some_and(|r| r.contains(chunk))
+	}
+
+	pub fn is_viewing_soil(&self, chunk: ChunkCoord, ch: SoilChannel) -> bool {
+		self.overlay == Some(ch) && self.is_subscribed_to(chunk)
+	}
+
+	// Lets a second's worth of packets through in a burst
+	fn take_packet_allowance(&mut self, max_packets_per_second: u32) -> bool {
+		let max = max_packets_per_second as f32;
+		let now = Instant::now();
+		let elapsed = now - self.allowance_updated;
+		let elapsed = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1_000_000_000.0;
+
+		self.packet_allowance = (self.packet_allowance + elapsed * max).min(max);
+		self.allowance_updated = now;
+
+		// Backed off for long enough to be worth mentioning again
+		if self.packet_allowance >= max {
+			self.rate_limited = false;
+			self.dropped_packets = 0;
+		}
+
+		if self.packet_allowance < 1.0 {
+			if !self.rate_limited {
+				warn!("Connection ({}) is sending more than {} packets a second, dropping some", self.id, max_packets_per_second);
+				self.rate_limited = true;
+			}
+
+			self.dropped_packets += 1;
+			return false
+		}
+
+		self.packet_allowance -= 1.0;
+		true
+	}
+
+	// A message that can't be framed would leave the client out of sync, so the connection is closed
+	pub fn send_payload(&mut self, packet_buffer: &mut [u8], payload: &[u8]) {
+		match ws::encode_ws_packet(packet_buffer, payload, self.deflate.as_mut()) {
+			Ok(packet) => { let _ = self.stream.write_all(packet); },
+			Err(e) => {
+				error!("Closing connection ({}): couldn't send packet: {}", self.id, e);
+				ws::send_close(&mut self.stream, ws::CLOSE_INTERNAL_ERROR, "Couldn't send packet");
+				self.state = ConnectionState::AwaitingDeletion;
+			}
+		}
 	}
 }
 
@@ -49,21 +125,51 @@ pub struct ConnectionManager {
 	pub connections: Vec<Connection>,
 
 	next_id: ConnectionID,
+	limits: LimitsConfig,
 }
 
 impl ConnectionManager {
-	pub fn new() -> Self {
+	pub fn new(limits: LimitsConfig) -> Self {
 		ConnectionManager{
 			connections: Vec::new(),
 
 			next_id: 1,
+			limits,
 		}
 	}
 
-	pub fn register_connection(&mut self, stream: TcpStream) {
-		stream.set_nonblocking(true).expect("Set nonblock failed");
+	// Connections over either limit are closed with a code saying which
+	pub fn register_connection(&mut self, mut stream: Stream, deflate: Option<ws::DeflateParams>) -> Option<ConnectionID> {
+		let address = stream.get_ref().peer_addr().ok().map(|a| a.ip());
+		let address_name = address.map_or(String::from("unknown address"), |a| a.to_string());
 
-		println!("Connection ({})", self.next_id);
+		let open_connections = self.connections.iter()
+			.filter(|c| !c.is_awaiting_deletion())
+			.count();
+
+		if open_connections >= self.limits.max_connections {
+			warn!("Refusing connection from {}: server is full ({} connections)", address_name, self.limits.max_connections);
+			ws::send_close(&mut stream, ws::CLOSE_SERVER_FULL, "Server full");
+			return None
+		}
+
+		let from_address = self.connections.iter()
+			.filter(|c| !c.is_awaiting_deletion() && address.is_some() && c.address == address)
+			.count();
+
+		if from_address >= self.limits.max_connections_per_ip {
+			warn!("Refusing connection from {}: already {} connections from there", address_name, from_address);
+			ws::send_close(&mut stream, ws::CLOSE_TOO_MANY_FROM_ADDRESS, "Too many connections from your address");
+			return None
+		}
+
+		stream.get_ref().set_nonblocking(true).expect("Set nonblock failed");
+
+		info!("Connection ({}) from {}", self.next_id, address_name);
+
+		if let Some(params) = deflate {
+			debug!("Connection ({}) agreed to {}", self.next_id, params.to_header());
+		}
 
 		self.connections.push(Connection {
 			stream,
@@ -72,9 +178,22 @@ impl ConnectionManager {
 
 			session_id: None,
 			id: self.next_id,
+			address,
+
+			world: None,
+			region: None,
+			overlay: None,
+
+			deflate: deflate.map(ws::Deflate::new),
+
+			packet_allowance: self.limits.max_packets_per_second as f32,
+			allowance_updated: Instant::now(),
+			rate_limited: false,
+			dropped_packets: 0,
 		});
 
 		self.next_id += 1;
+		Some(self.next_id - 1)
 	}
 
 	pub fn imbue_session(&mut self, id: ConnectionID, token: u32) -> bool {
@@ -120,21 +239,86 @@ impl ConnectionManager {
 					else { NoAuth },
 
 				_ => {
-					println!("notify_auth_fail called on connection not waiting for auth - closing...");
+					warn!("notify_auth_fail called on connection not waiting for auth - closing...");
 					AwaitingDeletion
 				},
 			};
 		}
 	}
 
+	pub fn get_session(&self, id: ConnectionID) -> Option<u32> {
+		self.connections.iter()
+			.find(|c| c.id == id)
+			.and_then(|c| c.session_id)
+	}
+
+	pub fn get_world(&self, id: ConnectionID) -> Option<WorldID> {
+		self.connections.iter()
+			.find(|c| c.id == id)
+			.and_then(|c| c.world)
+	}
+
+	// Region and overlay are reset, as they refer to the old world
+	pub fn set_world(&mut self, id: ConnectionID, world: WorldID) -> bool {
+		if let Some(ref mut con) = self.connections.iter_mut().find(|c| c.id == id) {
+			con.world = Some(world);
+			con.region = None;
+			con.overlay = None;
+			true
+		} else {
+			false
+		}
+	}
+
+	pub fn count_in_world(&self, world: WorldID) -> usize {
+		self.connections.iter()
+			.filter(|c| c.is_in_world(world))
+			.count()
+	}
+
+	// Returns chunks that weren't in the previous region
+	pub fn set_region(&mut self, id: ConnectionID, region: Region) -> Vec<ChunkCoord> {
+		if let Some(ref mut con) = self.connections.iter_mut().find(|c| c.id == id) {
+			let prev_region = con.region;
+			con.region = Some(region);
+
+			region.chunks().into_iter()
+				.filter(|&c| !prev_region.is_some_and(|r| r.contains(c)))
+				.collect()
+		} else {
+			Vec::new()
+		}
+	}
+
+	pub fn get_overlay(&self, id: ConnectionID) -> Option<SoilChannel> {
+		self.connections.iter()
+			.find(|c| c.id == id)
+			.and_then(|c| c.overlay)
+	}
+
+	// Returns chunks that need soil state for the new overlay
+	pub fn set_overlay(&mut self, id: ConnectionID, overlay: Option<SoilChannel>) -> Vec<ChunkCoord> {
+		if let Some(ref mut con) = self.connections.iter_mut().find(|c| c.id == id) {
+			let prev_overlay = con.overlay;
+			con.overlay = overlay;
+
+			if overlay.is_none() || overlay == prev_overlay {
+				return Vec::new();
+			}
+
+			con.region.map_or(Vec::new(), |r| r.chunks())
+		} else {
+			Vec::new()
+		}
+	}
+
 	pub fn flush(&mut self) {
 		self.connections.retain(|x| !x.is_awaiting_deletion());
 	}
 
 	pub fn poll_new_sessions(&mut self) -> Option<ConnectionID> {
 		self.connections.iter_mut()
-			.filter(|c| c.is_awaiting_new_session())
-			.next().as_mut()
+			.find(|c| c.is_awaiting_new_session())
 			.map(|con| {
 				con.state = ConnectionState::NewSessionRequested;
 				con.id
@@ -143,8 +327,7 @@ impl ConnectionManager {
 
 	pub fn poll_auth_attempts(&mut self) -> Option<(ConnectionID, u32)> {
 		self.connections.iter_mut()
-			.filter(|c| match_enum!(c.state, ConnectionState::AttemptingAuth{waiting: false, ..}))
-			.next().as_mut()
+			.find(|c| match_enum!(c.state, ConnectionState::AttemptingAuth{waiting: false, ..}))
 			.and_then(|con| {
 				if let ConnectionState::AttemptingAuth{token, ..} = con.state {
 					con.state = ConnectionState::AttemptingAuth{waiting: true, 	token};
@@ -159,8 +342,8 @@ impl ConnectionManager {
 		if let Some(ref mut con) = self.connections.iter_mut().find(|c| c.id == id) {
 			if !p.is_valid_from_server() { return false }
 
-			let mut payload = [0u8; 4<<10];
-			let mut packet_buffer = [0u8; 4<<10];
+			let mut payload = [0u8; MAX_PAYLOAD_SIZE];
+			let mut packet_buffer = [0u8; PACKET_BUFFER_SIZE];
 			let len = p.write(&mut payload);
 
 			con.send_payload(&mut packet_buffer, &payload[..len]);
@@ -171,49 +354,98 @@ impl ConnectionManager {
 		}
 	}
 
-	pub fn broadcast_to_authed(&mut self, p: &Packet) {
-		let mut payload = [0u8; 4<<10];
-		let mut packet_buffer = [0u8; 4<<10];
+	pub fn broadcast_to_world(&mut self, world: WorldID, p: &Packet) {
+		let mut payload = [0u8; MAX_PAYLOAD_SIZE];
+		let mut packet_buffer = [0u8; PACKET_BUFFER_SIZE];
 		let len = p.write(&mut payload);
 
-		for con in self.connections.iter_mut().filter(|c| c.is_ready()) {
+		for con in self.connections.iter_mut().filter(|c| c.is_in_world(world)) {
 			con.send_payload(&mut packet_buffer, &payload[..len]);
 		}
 	}
 
-	pub fn try_read(&mut self, mut read_buffer: &mut [u8]) -> Option<(ConnectionID, Packet)> {
-		for mut con in &mut self.connections {
-			let res = con.stream.read(&mut read_buffer);
+	pub fn broadcast_to_chunk(&mut self, world: WorldID, chunk: ChunkCoord, p: &Packet) {
+		let mut payload = [0u8; MAX_PAYLOAD_SIZE];
+		let mut packet_buffer = [0u8; PACKET_BUFFER_SIZE];
+		let len = p.write(&mut payload);
+
+		for con in self.connections.iter_mut().filter(|c| c.is_in_world(world) && c.is_subscribed_to(chunk)) {
+			con.send_payload(&mut packet_buffer, &payload[..len]);
+		}
+	}
+
+	pub fn broadcast_soil_to_chunk(&mut self, world: WorldID, chunk: ChunkCoord, ch: SoilChannel, p: &Packet) {
+		let mut payload = [0u8; MAX_PAYLOAD_SIZE];
+		let mut packet_buffer = [0u8; PACKET_BUFFER_SIZE];
+		let len = p.write(&mut payload);
+
+		for con in self.connections.iter_mut().filter(|c| c.is_in_world(world) && c.is_viewing_soil(chunk, ch)) {
+			con.send_payload(&mut packet_buffer, &payload[..len]);
+		}
+	}
+
+	pub fn try_read(&mut self, read_buffer: &mut [u8]) -> Option<(ConnectionID, Packet)> {
+		let max_packets_per_second = self.limits.max_packets_per_second;
+
+		for con in &mut self.connections {
+			let res = con.stream.read(read_buffer);
 			let length = match res {
 				Ok(length) => length,
 				Err(_) => continue,
 			};
 
 			if length == 0 {
-				println!("Zero length packet ({})", con.id);
+				info!("Zero length packet ({})", con.id);
 				con.state = ConnectionState::AwaitingDeletion;
 				continue;
 			}
 
-			let payload = ws::decode_ws_packet(&mut read_buffer[..length]);
-			if payload.len() == 0 {
-				println!("Disconnection ({})", con.id);
-				con.state = ConnectionState::AwaitingDeletion;
-				continue;
-			}
+			let payload = match ws::decode_ws_packet(&mut read_buffer[..length], con.deflate.as_mut()) {
+				Ok(ws::Frame::Binary(payload)) => payload,
+
+				Ok(ws::Frame::Ping(data)) => {
+					ws::send_pong(&mut con.stream, data);
+					continue;
+				},
+
+				Ok(ws::Frame::Pong) => continue,
+
+				Ok(ws::Frame::Close) => {
+					info!("Disconnection ({})", con.id);
+					con.state = ConnectionState::AwaitingDeletion;
+					continue;
+				},
+
+				Err((code, reason)) => {
+					warn!("Closing connection ({}): {}", con.id, reason);
+					ws::send_close(&mut con.stream, code, reason);
+					con.state = ConnectionState::AwaitingDeletion;
+					continue;
+				},
+			};
 
 			if let Some(packet) = Packet::parse(&payload) {
 				if !packet.is_valid_from_client() { continue }
+				if !con.take_packet_allowance(max_packets_per_second) {
+					// A second's worth dropped and still going
+					if con.dropped_packets > max_packets_per_second {
+						warn!("Closing connection ({}): kept sending more than {} packets a second", con.id, max_packets_per_second);
+						ws::send_close(&mut con.stream, ws::CLOSE_RATE_LIMITED, "Too many packets");
+						con.state = ConnectionState::AwaitingDeletion;
+					}
+
+					continue
+				}
 
 				if con.session_id.is_none() {
-					ConnectionManager::process_unauthed_packet(&mut con, &packet);
+					ConnectionManager::process_unauthed_packet(con, &packet);
 				} else {
 					return Some((con.id, packet))
 				}
 
 			} else {
 				con.state = ConnectionState::AwaitingDeletion;
-				println!("Invalid payload ({})", con.id);
+				warn!("Invalid payload ({})", con.id);
 			}
 		}
 
@@ -228,7 +460,7 @@ impl ConnectionManager {
 				},
 
 				Packet::AttemptAuthSession(token) => {
-					println!("Client {} tried to auth with key '{}'", con.id, token);
+					info!("Client {} tried to auth with key '{}'", con.id, token);
 					// TODO: if token doesn't exist potentially terminate connection
 					// con.session_id = Some(token);
 					// con.state = ConnectionState::Ready;
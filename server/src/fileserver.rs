@@ -1,144 +1,983 @@
+use std::collections::HashMap;
 use std::net::{TcpStream, TcpListener};
-use std::io::{Write, Read};
-use std::time;
+use std::io::{self, Write, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::fs;
+use std::thread;
+use std::time::{self, SystemTime};
 use std::str;
 
+use flate2::Compression;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use sha1;
+
 use http;
+use tls::{self, Stream};
 
-pub fn start(listener: TcpListener) {
-	let mut buf = [0u8; 8<<10];
+// Tried in order when a request names a directory
+const INDEX_FILES: &[&str] = &["index.html", "index.htm"];
 
-	for stream in listener.incoming() {
-		if cfg!(debug_requests) {
-			println!("[fsrv] New connection...");
+// Nothing served has a fingerprinted name - wsclient.js keeps its name across rebuilds -
+// so browsers always revalidate, and get a 304 if nothing's changed
+const CACHE_CONTROL: &str = "no-cache";
+
+// Seconds a kept-alive connection can sit idle before it's closed
+const KEEP_ALIVE_TIMEOUT: u64 = 5;
+// Seconds a client has to get a whole request header through, so trickling it in can't hold a worker
+const HEADER_TIMEOUT: u64 = 10;
+// Seconds a client can stall a response before giving up on it
+const WRITE_TIMEOUT: u64 = 30;
+
+const DEFAULT_WORKERS: usize = 16;
+const DEFAULT_MAX_QUEUED: usize = 64;
+
+// Generated rather than read from doc_root, see get_client_config
+const CLIENT_CONFIG_PATH: &str = "/config.json";
+
+#[derive(Clone, Debug)]
+pub struct Config {
+	// Everything beneath this is served
+	pub doc_root: PathBuf,
+	// The client's build output, searched after doc_root so /wsclient.js and friends resolve
+	pub build_dir: PathBuf,
+
+	// Connections handled at once
+	pub workers: usize,
+	// Connections left waiting for a worker before new ones are turned away with a 503
+	pub max_queued: usize,
+
+	// Where clients should open their websocket, if not back at the page's origin
+	pub websocket_url: Option<String>,
+}
+
+impl Default for Config {
+	// Relative to the server crate rather than the working directory
+	fn default() -> Self {
+		let client_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../client");
+
+		Config {
+			build_dir: client_dir.join("target/asmjs-unknown-emscripten/debug"),
+			doc_root: client_dir,
+
+			workers: DEFAULT_WORKERS,
+			max_queued: DEFAULT_MAX_QUEUED,
+
+			websocket_url: None,
 		}
+	}
+}
 
-		if !stream.is_ok() {
-			println!("[fsrv] Connection failed {}", stream.err().unwrap());
-			continue
+// Takes over connections asking to upgrade to a websocket
+pub type WebSocketHandler = Box<dyn Fn(Stream, &http::Request) + Send + Sync>;
+
+enum Lookup {
+	Found(PathBuf),
+	// Directory requested without a trailing slash
+	Redirect(String),
+	Forbidden,
+	NotFound,
+}
+
+// State shared between workers
+struct Shared {
+	config: Config,
+	cache: FileCache,
+	client_config: String,
+	// Connections accepted but not yet picked up by a worker
+	queued: AtomicUsize,
+
+	tls: Option<Arc<tls::Acceptor>>,
+
+	on_websocket: Option<WebSocketHandler>,
+}
+
+// Where a response to the current request goes, and how
+struct Responder<'a> {
+	stream: &'a mut Stream,
+	head: bool,
+	keep_alive: bool,
+}
+
+impl<'a> Responder<'a> {
+	fn send(&mut self, mut res: http::Response) -> io::Result<()> {
+		res.set("Connection", if self.keep_alive { "keep-alive" } else { "close" });
+
+		if self.head {
+			res.omit_body();
 		}
 
-		let mut stream = stream.unwrap();
+		res.write_to_stream(self.stream)
+	}
+}
+
+// Serves https if given an acceptor. Without a websocket handler, upgrade requests are treated like any other
+pub fn start(listener: TcpListener, config: Config, tls: Option<Arc<tls::Acceptor>>, on_websocket: Option<WebSocketHandler>) {
+	let (tx, rx) = mpsc::sync_channel::<TcpStream>(config.max_queued);
+	let rx = Arc::new(Mutex::new(rx));
+
+	let shared = Arc::new(Shared {
+		cache: FileCache::new(),
+		client_config: get_client_config(&config),
+		queued: AtomicUsize::new(0),
+		config,
+		tls,
+		on_websocket,
+	});
+
+	for _ in 0..shared.config.workers.max(1) {
+		let rx = rx.clone();
+		let shared = shared.clone();
 
-		// TODO: poll or async instead of block until timeout
-		match stream.set_read_timeout(Some(time::Duration::from_millis(500))) {
-			Ok(()) => {}, Err(e) => {
-				println!("[fsrv] set_read_timeout failed: {}", e);
+		thread::spawn(move || loop {
+			let stream = match rx.lock().unwrap().recv() {
+				Ok(stream) => stream,
+				Err(_) => return,
+			};
+
+			shared.queued.fetch_sub(1, Ordering::SeqCst);
+			handle_connection(stream, &shared);
+		});
+	}
+
+	for stream in listener.incoming() {
+		debug!("[fsrv] New connection...");
+
+		let stream = match stream {
+			Ok(s) => s,
+			Err(e) => {
+				warn!("[fsrv] Connection failed {}", e);
 				continue
 			}
+		};
+
+		let timeouts = stream.set_read_timeout(Some(time::Duration::from_secs(KEEP_ALIVE_TIMEOUT)))
+			.and_then(|_| stream.set_write_timeout(Some(time::Duration::from_secs(WRITE_TIMEOUT))));
+
+		if let Err(e) = timeouts {
+			warn!("[fsrv] Setting timeouts failed: {}", e);
+			continue
 		}
 
-		let size = match stream.read(&mut buf) {
-			Ok(0) => {
-				println!("[fsrv] Zero length read");
-				continue
-			},
-			
+		shared.queued.fetch_add(1, Ordering::SeqCst);
+
+		if let Err(mpsc::TrySendError::Full(stream)) = tx.try_send(stream) {
+			shared.queued.fetch_sub(1, Ordering::SeqCst);
+			warn!("[fsrv] Too many connections, turning one away");
+
+			// Answering over TLS would mean a handshake here, holding up everyone else
+			if shared.tls.is_some() { continue }
+
+			let mut res = error_response("HTTP/1.1 503 Service Unavailable");
+			res.set("Retry-After", "1");
+			let _ = Responder { stream: &mut Stream::Plain(stream), head: false, keep_alive: false }.send(res);
+		}
+	}
+}
+
+fn handle_connection(stream: TcpStream, shared: &Shared) {
+	let mut stream = Stream::accept(stream, &shared.tls);
+	let mut reader = http::RequestReader::new();
+	reader.set_header_timeout(time::Duration::from_secs(HEADER_TIMEOUT));
+
+	loop {
+		let request = match reader.read_request(&mut stream) {
+			Ok(Some(r)) => r,
+			// Closed or idle
+			Ok(None) => return,
+
 			Err(e) => {
-				println!("[fsrv] Error reading: {}", e);
-				continue
-			},
+				if let Some(status) = e.get_status_line() {
+					info!("[fsrv] Bad request: {}", e);
+					let _ = Responder { stream: &mut stream, head: false, keep_alive: false }
+						.send(error_response(status));
+				}
 
-			Ok(len) => len,
+				return
+			}
 		};
 
-		let reqstr = match str::from_utf8(&buf[0..size]) {
-			Ok(string) => string,
+		debug!("{:?}", request);
+
+		if let Some(ref on_websocket) = shared.on_websocket {
+			if request.method() == "GET" && request.has_token("Upgrade", "websocket") {
+				// From here on it's a long lived connection, not ours to time out
+				let timeouts = stream.get_ref().set_read_timeout(None)
+					.and_then(|_| stream.get_ref().set_write_timeout(None));
+
+				match timeouts {
+					Ok(()) => on_websocket(stream, &request),
+					Err(e) => warn!("[fsrv] Clearing timeouts failed: {}", e),
+				}
+
+				return
+			}
+		}
+
+		// An idle kept-alive connection would tie up a worker others are waiting on
+		let keep_alive = request.wants_keep_alive() && shared.queued.load(Ordering::SeqCst) == 0;
+
+		let result = handle_request(&mut Responder {
+			stream: &mut stream,
+			head: request.method() == "HEAD",
+			keep_alive,
+		}, &request, shared);
+
+		if result.is_err() || !keep_alive {
+			return
+		}
+	}
+}
+
+fn handle_request(out: &mut Responder, request: &http::Request, shared: &Shared) -> io::Result<()> {
+	if request.method() != "GET" && request.method() != "HEAD" {
+		let mut res = error_response("HTTP/1.1 405 Method Not Allowed");
+		res.set("Allow", "GET, HEAD");
+		return out.send(res)
+	}
+
+	let path = match decode_uri_path(request.uri()) {
+		Ok(path) => path,
+		Err(e) => {
+			info!("[fsrv] Bad request path '{}': {}", request.uri(), e);
+			return out.send(error_response("HTTP/1.1 400 Bad Request"))
+		}
+	};
+
+	if path == CLIENT_CONFIG_PATH {
+		let mut res = http::Response::new("HTTP/1.1 200 OK");
+		res.set("Content-Type", "application/json");
+		res.set("Cache-Control", "no-store");
+		res.set_body(shared.client_config.as_bytes());
+		return out.send(res)
+	}
+
+	match lookup(&shared.config, &path) {
+		Lookup::Found(filepath) => send_file(out, request, &shared.cache, &filepath),
+
+		Lookup::Redirect(location) => {
+			let mut res = http::Response::new("HTTP/1.1 301 Moved Permanently");
+			res.set("Location", &location);
+			out.send(res)
+		}
+
+		Lookup::Forbidden => {
+			info!("[fsrv] Refusing request for '{}'", request.uri());
+			out.send(error_response("HTTP/1.1 403 Forbidden"))
+		}
+
+		Lookup::NotFound => out.send(error_response("HTTP/1.1 404 File not found")),
+	}
+}
+
+// Read by index.html before the client starts, so it can find the server without any addresses built in.
+// websocket_url is null when the client should connect back to wherever the page came from
+fn get_client_config(config: &Config) -> String {
+	let websocket_url = match config.websocket_url {
+		Some(ref url) => format!("\"{}\"", url),
+		None => String::from("null"),
+	};
+
+	format!("{{\"websocket_url\": {}}}\n", websocket_url)
+}
+
+// Strips the query and decodes %XX escapes. The result always starts with '/'
+fn decode_uri_path(uri: &str) -> Result<String, String> {
+	let path = uri.split(['?', '#']).next().unwrap_or("");
+
+	if !path.starts_with('/') {
+		return Err(String::from("Path must start with '/'"));
+	}
+
+	let bytes = path.as_bytes();
+	let mut decoded = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+
+	while i < bytes.len() {
+		if bytes[i] != b'%' {
+			decoded.push(bytes[i]);
+			i += 1;
+			continue
+		}
+
+		let hex = bytes.get(i+1..i+3)
+			.and_then(|h| str::from_utf8(h).ok())
+			.and_then(|h| u8::from_str_radix(h, 16).ok());
+
+		match hex {
+			Some(b) => decoded.push(b),
+			None => return Err(String::from("Malformed percent escape")),
+		}
+
+		i += 3;
+	}
+
+	let decoded = String::from_utf8(decoded)
+		.map_err(|_| String::from("Path isn't valid utf8"))?;
+
+	if decoded.contains('\0') || decoded.contains('\\') {
+		return Err(String::from("Path contains invalid characters"));
+	}
+
+	Ok(decoded)
+}
+
+fn lookup(config: &Config, path: &str) -> Lookup {
+	let mut relative = PathBuf::new();
+
+	for component in path.split('/') {
+		match component {
+			"" | "." => {}
+			// Anything trying to climb out is refused outright, canonicalising below catches the rest
+			".." => return Lookup::Forbidden,
+			// Keeps things like .cargo or .git out of reach
+			_ if component.starts_with('.') => return Lookup::Forbidden,
+			_ => relative.push(component),
+		}
+	}
+
+	for root in [&config.doc_root, &config.build_dir].iter() {
+		// A root that doesn't exist (e.g. the client isn't built yet) has nothing to serve
+		let root = match root.canonicalize() {
+			Ok(root) => root,
+			Err(_) => continue,
+		};
+
+		let filepath = match root.join(&relative).canonicalize() {
+			Ok(filepath) => filepath,
+			Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => return Lookup::Forbidden,
 			Err(_) => continue,
 		};
 
-		if cfg!(debug_requests) {
-			println!("{}", reqstr);
+		// Symlinks pointing outside the root
+		if !filepath.starts_with(&root) {
+			return Lookup::Forbidden
 		}
 
-		let request = match http::Request::parse(&reqstr) {
-			Ok(r) => r,
-			Err(e) => {
-				println!("Parsing request: {}", e);
-				let _ = http::Response::new("HTTP/1.1 400 Bad Request").write_to_stream(&mut stream);
-				continue;
-			}
+		if !filepath.is_dir() {
+			return Lookup::Found(filepath)
+		}
+
+		// So relative links in the index resolve against the directory
+		if !path.ends_with('/') {
+			return Lookup::Redirect(format!("{}/", path))
+		}
+
+		return match INDEX_FILES.iter().map(|f| filepath.join(f)).find(|f| f.is_file()) {
+			Some(index) => Lookup::Found(index),
+			// No directory listings
+			None => Lookup::Forbidden,
+		}
+	}
+
+	Lookup::NotFound
+}
+
+pub fn get_mime_type(path: &Path) -> &'static str {
+	let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+
+	// emscripten's memory initialiser
+	if name.ends_with(".js.mem") {
+		return "application/octet-stream"
+	}
+
+	let extension = path.extension()
+		.and_then(|e| e.to_str())
+		.map(|e| e.to_lowercase())
+		.unwrap_or_default();
+
+	match extension.as_str() {
+		"html" | "htm" => "text/html; charset=utf-8",
+		"js" => "application/javascript; charset=utf-8",
+		"css" => "text/css; charset=utf-8",
+		"json" | "map" => "application/json; charset=utf-8",
+		"txt" | "toml" => "text/plain; charset=utf-8",
+		"glsl" | "vert" | "frag" => "text/plain; charset=utf-8",
+		"wasm" => "application/wasm",
+
+		"png" => "image/png",
+		"jpg" | "jpeg" => "image/jpeg",
+		"gif" => "image/gif",
+		"svg" => "image/svg+xml",
+		"ico" => "image/x-icon",
+		"webp" => "image/webp",
+
+		"woff" => "font/woff",
+		"woff2" => "font/woff2",
+		"ttf" => "font/ttf",
+		"otf" => "font/otf",
+
+		"wav" => "audio/wav",
+		"ogg" => "audio/ogg",
+		"mp3" => "audio/mpeg",
+
+		_ => "application/octet-stream",
+	}
+}
+
+fn error_response(status: &str) -> http::Response<'_> {
+	// "HTTP/1.1 404 File not found" -> "404 File not found"
+	let body = status.split_once(' ').map(|(_, rest)| rest).unwrap_or(status);
+
+	let mut res = http::Response::new(status);
+	res.set("Content-Type", "text/plain; charset=utf-8");
+	res.set_body(body.as_bytes());
+	res
+}
+
+// A file's contents along with everything needed to answer conditional requests for it
+pub struct CachedFile {
+	modified: SystemTime,
+	size: u64,
+
+	content_type: &'static str,
+	hash: String,
+	last_modified: String,
+
+	identity: Vec<u8>,
+	// Only kept when compressing actually made them smaller
+	gzip: Option<Vec<u8>>,
+	deflate: Option<Vec<u8>>,
+}
+
+impl CachedFile {
+	fn load(path: &Path, modified: SystemTime) -> io::Result<CachedFile> {
+		let mut identity = Vec::new();
+		fs::File::open(path)?.read_to_end(&mut identity)?;
+
+		let content_type = get_mime_type(path);
+		let (gzip, deflate) = if is_compressible(content_type) {
+			let mut gz = GzEncoder::new(Vec::new(), Compression::Default);
+			gz.write_all(&identity)?;
+			// 'deflate' means zlib framing over http, not a raw deflate stream
+			let mut zlib = ZlibEncoder::new(Vec::new(), Compression::Default);
+			zlib.write_all(&identity)?;
+
+			let smaller = |v: Vec<u8>| if v.len() < identity.len() { Some(v) } else { None };
+			(smaller(gz.finish()?), smaller(zlib.finish()?))
+		} else {
+			(None, None)
 		};
 
-		let encodings = match request.get("Accept-Encoding") {
-			Some(s) =>
-				s.split_terminator(',').map(|s| s.trim()).collect(),
+		let mut sha = sha1::Sha1::new();
+		sha.update(&identity);
+
+		Ok(CachedFile {
+			modified,
+			size: identity.len() as u64,
+
+			content_type,
+			hash: sha.digest().to_string()[..16].to_string(),
+			last_modified: http::format_http_date(modified),
 
-			None => Vec::new()
+			identity,
+			gzip,
+			deflate,
+		})
+	}
+
+	fn get_body(&self, encoding: Option<&str>) -> &[u8] {
+		match encoding {
+			Some("gzip") => self.gzip.as_ref(),
+			Some("deflate") => self.deflate.as_ref(),
+			_ => None,
+		}.unwrap_or(&self.identity)
+	}
+
+	// Each encoding is a different representation, so needs its own tag
+	fn get_etag(&self, encoding: Option<&str>) -> String {
+		match encoding {
+			Some(encoding) => format!("\"{}-{}\"", self.hash, encoding),
+			None => format!("\"{}\"", self.hash),
+		}
+	}
+
+	// gzip if the client accepts it and we have a variant, then deflate. q-values only rule encodings out
+	fn choose_encoding(&self, accept_encoding: Option<&str>) -> Option<&'static str> {
+		// e.g. "gzip, deflate;q=0.5, br;q=0"
+		let accepted = accept_encoding.unwrap_or("").split(',')
+			.filter_map(|enc| {
+				let mut params = enc.split(';').map(|p| p.trim());
+				let name = params.next().unwrap_or("");
+				let q = params.filter(|p| p.starts_with("q="))
+					.filter_map(|p| p[2..].parse::<f32>().ok())
+					.next().unwrap_or(1.0);
+
+				if q > 0.0 { Some(name) } else { None }
+			})
+			.collect::<Vec<_>>();
+
+		if self.gzip.is_some() && accepted.contains(&"gzip") {
+			Some("gzip")
+		} else if self.deflate.is_some() && accepted.contains(&"deflate") {
+			Some("deflate")
+		} else {
+			None
+		}
+	}
+
+	fn is_not_modified(&self, request: &http::Request, etag: &str) -> bool {
+		// If-None-Match takes precedence when both are sent
+		if let Some(tags) = request.get("If-None-Match") {
+			return tags.split(',')
+				.map(|t| t.trim())
+				.any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+		}
+
+		match request.get("If-Modified-Since").and_then(http::parse_http_date) {
+			// Last-Modified only has second precision
+			Some(since) => self.modified.duration_since(since).map_or(true, |d| d.as_secs() == 0),
+			None => false,
+		}
+	}
+}
+
+// Served files, reloaded whenever their mtime or size changes
+pub struct FileCache {
+	files: Mutex<HashMap<PathBuf, Arc<CachedFile>>>,
+}
+
+impl FileCache {
+	pub fn new() -> Self {
+		FileCache { files: Mutex::new(HashMap::new()) }
+	}
+
+	// Files are loaded and compressed without the lock held, so one doesn't hold up requests for the rest.
+	// Workers missing the same file at once each load it, and whichever finishes last is kept
+	pub fn get(&self, path: &Path) -> io::Result<Arc<CachedFile>> {
+		let metadata = match fs::metadata(path) {
+			Ok(m) => m,
+			Err(e) => {
+				self.files.lock().unwrap().remove(path);
+				return Err(e)
+			}
 		};
 
-		let encoding = encodings.iter()
-			.find(|&&enc| enc == "deflate" || enc == "gzip")
-			.map(|s| *s);
+		let modified = metadata.modified()?;
 
-		match request.uri() {
-			"/" => send_file(&mut stream, "../client/index.html", encoding),
-			"/wsclient.js" => {
-				if cfg!(hosted) {
-					send_file(&mut stream, "../client/target/asmjs-unknown-emscripten/release/wsclient.js", encoding)
-				} else {
-					send_file(&mut stream, "../client/target/asmjs-unknown-emscripten/debug/wsclient.js", encoding)
-				}
-			},
-			_ => {
-				let _ = http::Response::new("HTTP/1.1 404 File not found")
-					.write_to_stream(&mut stream);
+		if let Some(file) = self.files.lock().unwrap().get(path) {
+			if file.modified == modified && file.size == metadata.len() {
+				return Ok(file.clone())
 			}
 		}
+
+		let file = Arc::new(CachedFile::load(path, modified)?);
+		self.files.lock().unwrap().insert(path.to_path_buf(), file.clone());
+		Ok(file)
 	}
 }
 
-fn send_file(mut stream: &mut TcpStream, filepath: &str, encoding: Option<&str>) {
-	use std::fs::File;
-	use flate2::Compression;
-	use flate2::write::{GzEncoder, DeflateEncoder};
+// Not worth compressing formats that already are
+fn is_compressible(content_type: &str) -> bool {
+	match content_type {
+		"image/png" | "image/jpeg" | "image/gif" | "image/webp" => false,
+		"font/woff" | "font/woff2" => false,
+		_ => !content_type.starts_with("audio/"),
+	}
+}
 
-	// TODO: cache
-	let mut f = match File::open(filepath) {
-		Ok(f) => f,
+fn send_file(out: &mut Responder, request: &http::Request, cache: &FileCache, filepath: &Path) -> io::Result<()> {
+	let file = match cache.get(filepath) {
+		Ok(file) => file,
 		Err(e) => {
-			println!("Couldn't open requested file '{}': {}", filepath, e);
-			let _ = http::Response::new("HTTP/1.1 500 Internal Server Error").write_to_stream(&mut stream);
-			return;
+			error!("Couldn't read requested file '{}': {}", filepath.display(), e);
+			return out.send(error_response("HTTP/1.1 500 Internal Server Error"))
 		}
 	};
 
-	let mut body_buffer = Vec::new();
-	if let Err(e) = f.read_to_end(&mut body_buffer) {
-		println!("Couldn't read requested file '{}': {}", filepath, e);
-		let _ = http::Response::new("HTTP/1.1 500 Internal Server Error").write_to_stream(&mut stream);
-		return;
+	let encoding = file.choose_encoding(request.get("Accept-Encoding"));
+	let etag = file.get_etag(encoding);
+
+	let not_modified = file.is_not_modified(request, &etag);
+
+	let mut res = if not_modified {
+		http::Response::new("HTTP/1.1 304 Not Modified")
+	} else {
+		http::Response::new("HTTP/1.1 200 OK")
 	};
 
-	let mut res = http::Response::new("HTTP/1.1 200 OK");
+	res.set("ETag", &etag);
+	res.set("Last-Modified", &file.last_modified);
+	res.set("Cache-Control", CACHE_CONTROL);
 
-	if let Some(encoding) = encoding {
-		let mut encoded_buffer = Vec::new();
+	if is_compressible(file.content_type) {
+		res.set("Vary", "Accept-Encoding");
+	}
 
-		let write_result = match encoding {
-			"gzip" =>
-				GzEncoder::new(&mut encoded_buffer, Compression::Default)
-					.write_all(&body_buffer),
+	if !not_modified {
+		res.set("Content-Type", file.content_type);
 
-			"deflate" =>
-				DeflateEncoder::new(&mut encoded_buffer, Compression::Default)
-					.write_all(&body_buffer),
+		if let Some(encoding) = encoding {
+			res.set("Content-Encoding", encoding);
+		}
 
-			_ => {
-				println!("Couldn't encode requested file '{}': Unknown encoding '{}'", filepath, encoding);
-				let _ = http::Response::new("HTTP/1.1 500 Internal Server Error").write_to_stream(&mut stream);
-				return;
-			}
+		res.set_body(file.get_body(encoding));
+	}
+
+	out.send(res)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::net::SocketAddr;
+	use std::time::{Duration, Instant};
+
+	// Removes the doc root once the test is done with it
+	struct TestServer {
+		addr: SocketAddr,
+		doc_root: PathBuf,
+	}
+
+	impl Drop for TestServer {
+		fn drop(&mut self) {
+			let _ = fs::remove_dir_all(&self.doc_root);
+		}
+	}
+
+	fn serve(workers: usize, max_queued: usize) -> TestServer {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+
+		let doc_root = ::std::env::temp_dir().join(format!("wsrs-fileserver-{}", addr.port()));
+		fs::create_dir_all(&doc_root).unwrap();
+		fs::File::create(doc_root.join("index.html")).unwrap()
+			.write_all(&vec![b'x'; 256<<10]).unwrap();
+
+		let config = Config {
+			build_dir: doc_root.join("build"),
+			doc_root: doc_root.clone(),
+			workers,
+			max_queued,
+			websocket_url: None,
 		};
 
-		if write_result.is_ok() {
-			body_buffer = encoded_buffer;
-			res.set("Content-Encoding", encoding);
-		} else {
-			println!("Couldn't encode requested file '{}': {}", filepath, write_result.err().unwrap());
+		thread::spawn(move || start(listener, config, None, None));
+		TestServer { addr, doc_root }
+	}
+
+	// Sends half a request and leaves it hanging, like a slow or stalled client
+	fn stall(addr: SocketAddr) -> TcpStream {
+		let mut stream = TcpStream::connect(addr).unwrap();
+		stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+		thread::sleep(Duration::from_millis(50));
+		stream
+	}
+
+	// Returns the status line and whether the connection was kept alive
+	fn read_response(stream: &mut TcpStream) -> (String, bool) {
+		let mut received = Vec::new();
+		let mut chunk = [0u8; 16<<10];
+		let mut header_end = None;
+
+		while header_end.is_none() {
+			let len = stream.read(&mut chunk).unwrap();
+			assert!(len > 0, "Connection closed before response");
+			received.extend_from_slice(&chunk[..len]);
+
+			header_end = received.windows(4).position(|w| w == b"\r\n\r\n").map(|pos| pos + 4);
+		}
+
+		let header_end = header_end.unwrap();
+		let header = str::from_utf8(&received[..header_end]).unwrap().to_string();
+
+		let content_length = header.lines()
+			.filter_map(|l| l.strip_prefix("Content-Length: ").and_then(|v| v.parse().ok()))
+			.next().unwrap_or(0);
+
+		let mut body_received = received.len() - header_end;
+		while body_received < content_length {
+			body_received += stream.read(&mut chunk).unwrap();
+		}
+
+		let status = header.lines().next().unwrap().to_string();
+		(status, header.contains("Connection: keep-alive"))
+	}
+
+	fn get(addr: SocketAddr) -> String {
+		let mut stream = TcpStream::connect(addr).unwrap();
+		stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+		stream.write_all(b"GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").unwrap();
+		read_response(&mut stream).0
+	}
+
+	// Sends a whole request on its own connection. Returns the response header, and everything after it until the server closes
+	fn fetch(addr: SocketAddr, request: &str) -> (String, Vec<u8>) {
+		let mut stream = TcpStream::connect(addr).unwrap();
+		stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+		stream.write_all(request.as_bytes()).unwrap();
+
+		let mut response = Vec::new();
+		stream.read_to_end(&mut response).unwrap();
+
+		let header_end = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+		let body = response.split_off(header_end);
+		(String::from_utf8(response).unwrap(), body)
+	}
+
+	fn get_field(header: &str, name: &str) -> Option<String> {
+		header.lines()
+			.filter_map(|l| {
+				let mut parts = l.splitn(2, ": ");
+				match (parts.next(), parts.next()) {
+					(Some(field), Some(value)) if field.eq_ignore_ascii_case(name) => Some(value.to_string()),
+					_ => None,
+				}
+			})
+			.next()
+	}
+
+	fn conditional_get(addr: SocketAddr, fields: &str) -> String {
+		let (header, body) = fetch(addr, &format!("GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n{}\r\n", fields));
+		let status = header.lines().next().unwrap().to_string();
+
+		if status == "HTTP/1.1 304 Not Modified" {
+			assert!(body.is_empty());
+			assert_eq!(get_field(&header, "Content-Length"), None);
+		}
+
+		status
+	}
+
+	#[test]
+	fn stalled_clients_dont_block_others() {
+		let server = serve(8, 64);
+		let addr = server.addr;
+		let _stalled = (0..4).map(|_| stall(addr)).collect::<Vec<_>>();
+
+		let start_time = Instant::now();
+		let clients = (0..32)
+			.map(|_| thread::spawn(move || get(addr)))
+			.collect::<Vec<_>>();
+
+		for client in clients {
+			assert_eq!(client.join().unwrap(), "HTTP/1.1 200 OK");
+		}
+
+		// Handled one at a time, each stalled client would hold everyone up for KEEP_ALIVE_TIMEOUT
+		assert!(start_time.elapsed() < Duration::from_secs(KEEP_ALIVE_TIMEOUT));
+	}
+
+	#[test]
+	fn connections_over_the_limit_are_turned_away() {
+		let server = serve(1, 1);
+		let addr = server.addr;
+
+		// One with the only worker, one waiting for it
+		let _busy = stall(addr);
+		let _queued = stall(addr);
+
+		assert_eq!(get(addr), "HTTP/1.1 503 Service Unavailable");
+	}
+
+	#[test]
+	fn unchanged_files_are_not_sent_again() {
+		let server = serve(1, 1);
+		let addr = server.addr;
+
+		let (header, body) = fetch(addr, "GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n");
+		assert!(header.starts_with("HTTP/1.1 200 OK\r\n"));
+		assert_eq!(body.len(), 256<<10);
+		assert_eq!(get_field(&header, "Cache-Control").unwrap(), CACHE_CONTROL);
+
+		let etag = get_field(&header, "ETag").unwrap();
+		let last_modified = get_field(&header, "Last-Modified").unwrap();
+
+		assert_eq!(conditional_get(addr, &format!("If-None-Match: {}\r\n", etag)), "HTTP/1.1 304 Not Modified");
+		assert_eq!(conditional_get(addr, &format!("If-None-Match: \"other\", W/{}\r\n", etag)), "HTTP/1.1 304 Not Modified");
+		assert_eq!(conditional_get(addr, "If-None-Match: *\r\n"), "HTTP/1.1 304 Not Modified");
+		assert_eq!(conditional_get(addr, "If-None-Match: \"other\"\r\n"), "HTTP/1.1 200 OK");
+
+		assert_eq!(conditional_get(addr, &format!("If-Modified-Since: {}\r\n", last_modified)), "HTTP/1.1 304 Not Modified");
+		assert_eq!(conditional_get(addr, "If-Modified-Since: Thu, 01 Jan 1970 00:00:00 GMT\r\n"), "HTTP/1.1 200 OK");
+
+		// If-None-Match wins when both are sent
+		let both = format!("If-None-Match: \"other\"\r\nIf-Modified-Since: {}\r\n", last_modified);
+		assert_eq!(conditional_get(addr, &both), "HTTP/1.1 200 OK");
+	}
+
+	#[test]
+	fn compressed_variants_have_their_own_etags() {
+		let server = serve(1, 1);
+		let addr = server.addr;
+
+		let (identity, _) = fetch(addr, "GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n");
+		assert_eq!(get_field(&identity, "Content-Encoding"), None);
+		assert_eq!(get_field(&identity, "Vary").unwrap(), "Accept-Encoding");
+
+		// gzip is picked over deflate whatever order they come in
+		let (gzip, body) = fetch(addr, "GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\nAccept-Encoding: deflate, gzip\r\n\r\n");
+		assert_eq!(get_field(&gzip, "Content-Encoding").unwrap(), "gzip");
+		assert!(body.len() < 256<<10);
+
+		let (deflate, _) = fetch(addr, "GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\nAccept-Encoding: gzip;q=0, deflate\r\n\r\n");
+		assert_eq!(get_field(&deflate, "Content-Encoding").unwrap(), "deflate");
+
+		let etags = [&identity, &gzip, &deflate].iter().map(|h| get_field(h, "ETag").unwrap()).collect::<Vec<_>>();
+		assert!(etags[0] != etags[1] && etags[1] != etags[2] && etags[0] != etags[2]);
+
+		// A tag for one encoding doesn't match another
+		assert_eq!(conditional_get(addr, &format!("Accept-Encoding: gzip\r\nIf-None-Match: {}\r\n", etags[1])), "HTTP/1.1 304 Not Modified");
+		assert_eq!(conditional_get(addr, &format!("If-None-Match: {}\r\n", etags[1])), "HTTP/1.1 200 OK");
+	}
+
+	#[test]
+	fn head_gets_the_header_without_a_body() {
+		let server = serve(1, 1);
+		let addr = server.addr;
+
+		let (header, body) = fetch(addr, "GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n");
+		assert_eq!(get_field(&header, "Content-Length").unwrap(), body.len().to_string());
+
+		let (head, body) = fetch(addr, "HEAD / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n");
+		assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+		assert_eq!(get_field(&head, "Content-Length"), get_field(&header, "Content-Length"));
+		assert_eq!(get_field(&head, "ETag"), get_field(&header, "ETag"));
+		assert!(body.is_empty());
+	}
+
+	#[test]
+	fn kept_alive_responses_line_up() {
+		let server = serve(1, 1);
+		let mut stream = TcpStream::connect(server.addr).unwrap();
+		stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+
+		// Only right if each Content-Length was
+		for _ in 0..3 {
+			stream.write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+			assert_eq!(read_response(&mut stream), (String::from("HTTP/1.1 200 OK"), true));
+		}
+
+		stream.write_all(b"HEAD / HTTP/1.1\r\nHost: test\r\n\r\nGET /missing HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+
+		let mut response = Vec::new();
+		while !response.ends_with(b"404 File not found") {
+			let mut chunk = [0u8; 1024];
+			let len = stream.read(&mut chunk).unwrap();
+			assert!(len > 0, "Connection closed before response");
+			response.extend_from_slice(&chunk[..len]);
 		}
+
+		let response = String::from_utf8(response).unwrap();
+		assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+		assert_eq!(response.matches("HTTP/1.1 404 File not found\r\n").count(), 1);
+		assert!(!response.contains("xxxx"));
+	}
+
+	#[test]
+	fn bad_requests_are_refused() {
+		let server = serve(1, 1);
+		let addr = server.addr;
+		let status = |request: &str| fetch(addr, request).0.lines().next().unwrap().to_string();
+
+		let (header, _) = fetch(addr, "POST / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n");
+		assert!(header.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+		assert_eq!(get_field(&header, "Allow").unwrap(), "GET, HEAD");
+
+		assert_eq!(status("GET /%zz HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n"), "HTTP/1.1 400 Bad Request");
+		assert_eq!(status("GET\r\n\r\n"), "HTTP/1.1 400 Bad Request");
+		assert_eq!(status("GET / HTTP/1.1\r\nHost: test\r\nContent-Length: lots\r\n\r\n"), "HTTP/1.1 400 Bad Request");
+
+		assert_eq!(status("POST / HTTP/1.1\r\nHost: test\r\nContent-Length: 1000000\r\n\r\n"), "HTTP/1.1 413 Payload Too Large");
+
+		// Never finished, but already past the limit
+		let header = format!("GET / HTTP/1.1\r\nHost: test\r\nX-Padding: {}", "x".repeat(http::MAX_HEADER_SIZE));
+		assert_eq!(status(&header), "HTTP/1.1 413 Payload Too Large");
 	}
 
-	res.set_body(&body_buffer);
-	let _ = res.write_to_stream(&mut stream);
-}
\ No newline at end of file
+	#[test]
+	fn trickled_headers_time_out() {
+		let server = serve(1, 1);
+		let addr = server.addr;
+		let mut stream = TcpStream::connect(addr).unwrap();
+		stream.set_read_timeout(Some(Duration::from_secs(HEADER_TIMEOUT * 2))).unwrap();
+
+		// A byte at a time, never quite hitting KEEP_ALIVE_TIMEOUT between them
+		let mut writer = stream.try_clone().unwrap();
+		thread::spawn(move || {
+			for &b in b"GET / HTTP/1.1\r\nHost: test\r\nX-Padding: ".iter().cycle() {
+				if writer.write_all(&[b]).is_err() { break }
+				thread::sleep(Duration::from_millis(KEEP_ALIVE_TIMEOUT * 1000 / 2));
+			}
+		});
+
+		let mut response = Vec::new();
+		let _ = stream.read_to_end(&mut response);
+
+		assert!(response.starts_with(b"HTTP/1.1 408 Request Timeout"));
+	}
+
+	// Not run by default. Prints throughput and latency for many kept-alive clients at once:
+	//   cargo test load_test -- --ignored --nocapture
+	#[test]
+	#[ignore]
+	fn load_test() {
+		const CLIENTS: usize = 64;
+		const REQUESTS_PER_CLIENT: usize = 50;
+		const STALLED_CLIENTS: usize = 4;
+
+		let server = serve(DEFAULT_WORKERS, DEFAULT_MAX_QUEUED);
+		let addr = server.addr;
+		let _stalled = (0..STALLED_CLIENTS).map(|_| stall(addr)).collect::<Vec<_>>();
+
+		let start_time = Instant::now();
+
+		let clients = (0..CLIENTS).map(|_| thread::spawn(move || {
+			let mut latencies = Vec::new();
+			let mut rejected = 0;
+			let mut stream = None;
+
+			for _ in 0..REQUESTS_PER_CLIENT {
+				let request_start = Instant::now();
+
+				// The server stops keeping connections alive while others are waiting
+				if stream.is_none() {
+					let s = TcpStream::connect(addr).unwrap();
+					s.set_read_timeout(Some(Duration::from_secs(30))).unwrap();
+					stream = Some(s);
+				}
+
+				let (status, kept_alive) = {
+					let s = stream.as_mut().unwrap();
+					s.write_all(b"GET / HTTP/1.1\r\nHost: test\r\nAccept-Encoding: gzip\r\n\r\n").unwrap();
+					read_response(s)
+				};
+
+				if status == "HTTP/1.1 503 Service Unavailable" {
+					rejected += 1;
+				} else {
+					assert_eq!(status, "HTTP/1.1 200 OK");
+				}
+
+				if !kept_alive { stream = None }
+				latencies.push(request_start.elapsed());
+			}
+
+			(latencies, rejected)
+		})).collect::<Vec<_>>();
+
+		let mut latencies = Vec::new();
+		let mut rejected = 0;
+
+		for client in clients {
+			let (client_latencies, client_rejected) = client.join().unwrap();
+			latencies.extend(client_latencies);
+			rejected += client_rejected;
+		}
+
+		let elapsed = start_time.elapsed();
+		latencies.sort();
+
+		let to_ms = |d: Duration| d.as_secs() as f64 * 1000.0 + d.subsec_nanos() as f64 / 1.0e6;
+		let total_secs = to_ms(elapsed) / 1000.0;
+
+		println!();
+		println!("{} clients x {} requests, {} stalled clients, {} workers", CLIENTS, REQUESTS_PER_CLIENT, STALLED_CLIENTS, DEFAULT_WORKERS);
+		println!("  {:.0} requests/s over {:.2}s, {} turned away", latencies.len() as f64 / total_secs, total_secs, rejected);
+		println!("  latency p50 {:.1}ms, p99 {:.1}ms, max {:.1}ms",
+			to_ms(latencies[latencies.len() / 2]),
+			to_ms(latencies[latencies.len() * 99 / 100]),
+			to_ms(*latencies.last().unwrap()));
+	}
+}
@@ -1,14 +1,22 @@
 extern crate std;
 
-use std::net::TcpStream;
-use std::io;
+use std::io::{self, Read, Write};
 use std::option::Option;
 use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Anything bigger is answered with a 413
+pub const MAX_HEADER_SIZE: usize = 16<<10;
+// Bodies aren't used for anything, they're only read so the next request on the connection lines up
+const MAX_BODY_SIZE: usize = 64<<10;
 
 #[derive(Debug)]
-pub struct Request<'a> {
-	uri: &'a str,
-	fields: HashMap<&'a str, &'a str>,
+pub struct Request {
+	method: String,
+	uri: String,
+	version: String,
+	// Keyed by lowercase field name
+	fields: HashMap<String, String>,
 }
 
 #[derive(Debug)]
@@ -16,71 +24,248 @@ pub struct Response<'a> {
 	status_line: &'a str,
 	fields: HashMap<&'a str, &'a str>,
 	body: Option<&'a [u8]>,
+	omit_body: bool,
+}
+
+#[derive(Debug)]
+pub enum RequestError {
+	// Connection dropped or timed out partway through a request
+	Io(io::Error),
+	BadRequest(String),
+	TooLarge,
+	// Header took too long to arrive
+	Timeout,
+}
+
+impl RequestError {
+	// What to tell the client before closing the connection, if anything
+	pub fn get_status_line(&self) -> Option<&'static str> {
+		match *self {
+			RequestError::Io(_) => None,
+			RequestError::BadRequest(_) => Some("HTTP/1.1 400 Bad Request"),
+			RequestError::TooLarge => Some("HTTP/1.1 413 Payload Too Large"),
+			RequestError::Timeout => Some("HTTP/1.1 408 Request Timeout"),
+		}
+	}
+}
+
+impl std::fmt::Display for RequestError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			RequestError::Io(ref e) => write!(f, "{}", e),
+			RequestError::BadRequest(ref e) => write!(f, "{}", e),
+			RequestError::TooLarge => write!(f, "Request too large"),
+			RequestError::Timeout => write!(f, "Timed out waiting for request"),
+		}
+	}
+}
+
+// Reads requests off a connection one at a time. Anything read past the end of one request
+// is kept for the next, so pipelined requests on a kept-alive connection aren't lost
+pub struct RequestReader {
+	buffer: Vec<u8>,
+	header_timeout: Option<Duration>,
 }
 
-impl<'a> Request<'a> {
-	pub fn parse(data: &'a str) -> Result<Request<'a>, &str> {
+impl RequestReader {
+	pub fn new() -> Self {
+		RequestReader {
+			buffer: Vec::new(),
+			header_timeout: None,
+		}
+	}
+
+	// Limits the time between a call to read_request and the end of the header arriving.
+	// The stream's own read timeout still applies to each read
+	pub fn set_header_timeout(&mut self, timeout: Duration) {
+		self.header_timeout = Some(timeout);
+	}
+
+	// Ok(None) means the connection was closed, or timed out, between requests
+	pub fn read_request<R: Read>(&mut self, stream: &mut R) -> Result<Option<Request>, RequestError> {
+		let mut chunk = [0u8; 4<<10];
+		let start_time = Instant::now();
+
+		let mut header_end = find_header_end(&self.buffer);
+
+		while header_end.is_none() {
+			if self.buffer.len() > MAX_HEADER_SIZE {
+				return Err(RequestError::TooLarge)
+			}
+
+			if self.header_timeout.is_some_and(|t| !self.buffer.is_empty() && start_time.elapsed() > t) {
+				return Err(RequestError::Timeout)
+			}
+
+			match stream.read(&mut chunk) {
+				Ok(0) if self.buffer.is_empty() => return Ok(None),
+				Ok(0) => return Err(RequestError::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed mid request"))),
+				Ok(len) => self.buffer.extend_from_slice(&chunk[..len]),
+
+				Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+				Err(ref e) if self.buffer.is_empty() && is_timeout(e) => return Ok(None),
+				Err(ref e) if is_timeout(e) => return Err(RequestError::Timeout),
+				Err(e) => return Err(RequestError::Io(e)),
+			}
+
+			header_end = find_header_end(&self.buffer);
+		}
+
+		let header_end = header_end.unwrap();
+
+		if header_end > MAX_HEADER_SIZE {
+			return Err(RequestError::TooLarge)
+		}
+
+		let request = {
+			let header = std::str::from_utf8(&self.buffer[..header_end])
+				.map_err(|_| RequestError::BadRequest(String::from("Header isn't valid utf8")))?;
+
+			Request::parse(header).map_err(RequestError::BadRequest)?
+		};
+
+		self.buffer.drain(..header_end);
+
+		if request.get("Transfer-Encoding").is_some() {
+			return Err(RequestError::BadRequest(String::from("Chunked request bodies aren't supported")))
+		}
+
+		let body_size = match request.get("Content-Length") {
+			Some(len) => len.parse::<usize>()
+				.map_err(|_| RequestError::BadRequest(format!("Invalid Content-Length '{}'", len)))?,
+			None => 0,
+		};
+
+		if body_size > MAX_BODY_SIZE {
+			return Err(RequestError::TooLarge)
+		}
+
+		while self.buffer.len() < body_size {
+			match stream.read(&mut chunk) {
+				Ok(0) => return Err(RequestError::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed mid body"))),
+				Ok(len) => self.buffer.extend_from_slice(&chunk[..len]),
+				Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+				Err(e) => return Err(RequestError::Io(e)),
+			}
+		}
+
+		self.buffer.drain(..body_size);
+
+		Ok(Some(request))
+	}
+}
+
+// Index just past the blank line ending the header
+fn find_header_end(data: &[u8]) -> Option<usize> {
+	data.windows(4).position(|w| w == b"\r\n\r\n").map(|pos| pos + 4)
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+	e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+impl Request {
+	pub fn parse(data: &str) -> Result<Request, String> {
+		// Stray line breaks before a request are allowed
+		let data = data.trim_start_matches("\r\n");
 		let header_end = data.split("\r\n\r\n").next().unwrap();
 		let mut lines = header_end.split_terminator("\r\n");
 		let reqline = lines.next().unwrap_or("");
 
-		let mut reqlineels = reqline.split_whitespace();
+		let mut reqlineels = reqline.split(' ');
 
-		if reqlineels.next().unwrap_or("") != "GET" {
-			if cfg!(debug_requests) {
-				println!("{}", data);
+		let (method, uri, version) = match (reqlineels.next(), reqlineels.next(), reqlineels.next(), reqlineels.next()) {
+			(Some(m), Some(u), Some(v), None) if !m.is_empty() && !u.is_empty() => (m, u, v),
+			_ => {
+				debug!("{}", data);
+				return Err(format!("Malformed request line '{}'", reqline));
 			}
-			return Err("Non-GET requests not supported");
-		}
-
-		let requri = reqlineels.next().unwrap_or("");
-		let version = reqlineels.next().unwrap_or("");
+		};
 
 		if version != "HTTP/1.0" && version != "HTTP/1.1" {
-			if cfg!(debug_requests) {
-				println!("{}", data);
-			}
-			return Err("Invalid HTTP version");
+			debug!("{}", data);
+			return Err(format!("Invalid HTTP version '{}'", version));
 		}
 
-		let mut fields = HashMap::new();
+		let mut fields: HashMap<String, String> = HashMap::new();
 
 		for line in lines {
-			let mut line = line.splitn(2, ":").map(|s| s.trim());
+			let mut line = line.splitn(2, ":");
 			let key = line.next().unwrap();
 			let value = match line.next() {
-				Some(v) => v,
-				None => continue
+				Some(v) => v.trim(),
+				None => return Err(format!("Malformed header line '{}'", line_preview(key))),
 			};
 
-			fields.insert(key, value);
+			// Whitespace before the colon is explicitly disallowed, and catches obsolete line folding
+			if key.is_empty() || key.contains(|c: char| c.is_whitespace()) {
+				return Err(format!("Malformed header field name '{}'", line_preview(key)));
+			}
+
+			// Repeated fields are equivalent to one comma separated field
+			let key = key.to_lowercase();
+			if let Some(existing) = fields.get_mut(&key) {
+				existing.push_str(", ");
+				existing.push_str(value);
+				continue
+			}
+
+			fields.insert(key, value.to_string());
+		}
+
+		if version == "HTTP/1.1" && !fields.contains_key("host") {
+			return Err(String::from("HTTP/1.1 request without a Host"));
 		}
 
 		Ok(Request {
-			uri: requri,
-			fields: fields,
+			method: method.to_string(),
+			uri: uri.to_string(),
+			version: version.to_string(),
+			fields,
 		})
 	}
 
+	pub fn method(&self) -> &str {
+		&self.method
+	}
+
 	pub fn uri(&self) -> &str {
-		self.uri
+		&self.uri
 	}
 
 	pub fn get(&self, key: &str) -> Option<&str> {
-		if let Some(&val) = self.fields.get(&key) {
-			Some(val)
+		self.fields.get(&key.to_lowercase()).map(|v| v.as_str())
+	}
+
+	// Whether a comma separated field like Connection lists a token, ignoring case
+	pub fn has_token(&self, key: &str, token: &str) -> bool {
+		self.get(key).is_some_and(|v| v.split(',').any(|t| t.trim().to_lowercase() == token.to_lowercase()))
+	}
+
+	// HTTP/1.1 connections persist unless asked not to, HTTP/1.0 ones only if asked to
+	pub fn wants_keep_alive(&self) -> bool {
+		if self.version == "HTTP/1.1" {
+			!self.has_token("Connection", "close")
 		} else {
-			None
+			self.has_token("Connection", "keep-alive")
 		}
 	}
 }
 
+fn line_preview(line: &str) -> &str {
+	match line.char_indices().nth(40) {
+		Some((i, _)) => &line[..i],
+		None => line,
+	}
+}
+
 impl<'a> Response<'a> {
 	pub fn new(status: &'a str) -> Response<'a> {
 		Response {
 			status_line: status,
 			fields: HashMap::new(),
-			body: None
+			body: None,
+			omit_body: false,
 		}
 	}
 
@@ -92,9 +277,12 @@ impl<'a> Response<'a> {
 		self.body = Some(body); // once told me
 	}
 
-	pub fn write_to_stream(&self, stream: &mut TcpStream) -> io::Result<()> {
-		use std::io::Write;
+	// For HEAD requests. Headers, including Content-Length, still describe the body
+	pub fn omit_body(&mut self) {
+		self.omit_body = true;
+	}
 
+	pub fn write_to_stream<W: Write>(&self, stream: &mut W) -> io::Result<()> {
 		let it = std::iter::once(self.status_line.to_string());
 		let fieldit = self.fields.iter().map(|(k, v)| format!("{}: {}", k, v));
 		let mut response_str = it.chain(fieldit)
@@ -104,16 +292,90 @@ impl<'a> Response<'a> {
 				acc
 			});
 
+		// Needed to find where one response ends on a kept-alive connection
+		if self.has_content_length() {
+			let length = self.body.map_or(0, |b| b.len());
+			response_str.push_str(&format!("Content-Length: {}\r\n", length));
+		}
+
 		response_str.push_str("\r\n");
 
 		stream.write_all(response_str.as_bytes())?;
 
-		if let Some(ref body) = self.body {
-			stream.write_all(&body)?;
+		if let (Some(body), false) = (self.body, self.omit_body) {
+			stream.write_all(body)?;
 		}
 
 		Ok(())
 	}
+
+	// 1xx, 204 and 304 responses never have a body, so mustn't claim one
+	fn has_content_length(&self) -> bool {
+		match self.status_line.split(' ').nth(1) {
+			Some(code) => !code.starts_with('1') && code != "204" && code != "304",
+			None => true,
+		}
+	}
+}
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn format_http_date(time: SystemTime) -> String {
+	let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+	let days = (secs / 86400) as i64;
+	let (year, month, day) = civil_from_days(days);
+
+	// 1970-01-01 was a Thursday
+	let weekday = WEEKDAYS[((days + 4) % 7) as usize];
+
+	format!("{}, {:02} {} {} {:02}:{:02}:{:02} GMT", weekday, day, MONTHS[month as usize - 1], year,
+		secs / 3600 % 24, secs / 60 % 60, secs % 60)
+}
+
+// Only the preferred IMF-fixdate format, anything else is ignored
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+	let parts = date.split_whitespace().collect::<Vec<_>>();
+	if parts.len() != 6 || parts[5] != "GMT" { return None }
+
+	let day = match parts[1].parse::<i64>() { Ok(d @ 1..=31) => d, _ => return None };
+	let month = MONTHS.iter().position(|&m| m == parts[2])? as i64 + 1;
+	let year = match parts[3].parse::<i64>() { Ok(y) if y >= 1970 => y, _ => return None };
+
+	let mut time = parts[4].split(':').map(|s| s.parse::<u64>().ok());
+	let (hour, minute, second) = match (time.next(), time.next(), time.next(), time.next()) {
+		(Some(Some(h)), Some(Some(m)), Some(Some(s)), None) if h < 24 && m < 60 && s < 61 => (h, m, s),
+		_ => return None,
+	};
+
+	let days = days_from_civil(year, month, day) as u64;
+	Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hour * 3600 + minute * 60 + second))
+}
+
+// http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+	let z = days + 719468;
+	let era = if z >= 0 { z } else { z - 146096 } / 146097;
+	let doe = z - era * 146097;
+	let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+	let doy = doe - (365*yoe + yoe/4 - yoe/100);
+	let mp = (5*doy + 2) / 153;
+	let day = doy - (153*mp + 2)/5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+	(year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+	let year = if month <= 2 { year - 1 } else { year };
+	let era = if year >= 0 { year } else { year - 399 } / 400;
+	let yoe = year - era * 400;
+	let doy = (153 * if month > 2 { month - 3 } else { month + 9 } + 2) / 5 + day - 1;
+	let doe = yoe * 365 + yoe/4 - yoe/100 + doy;
+
+	era * 146097 + doe - 719468
 }
 
 // GET / HTTP/1.1
@@ -0,0 +1,51 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// In increasing order of verbosity
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+	Error,
+	Warn,
+	Info,
+	Debug,
+}
+
+pub const LEVEL_NAMES: [&str; 4] = ["error", "warn", "info", "debug"];
+
+// Only errors until set_level is called
+static LEVEL: AtomicUsize = AtomicUsize::new(0);
+
+impl Level {
+	pub fn from_name(name: &str) -> Result<Level, String> {
+		match name {
+			"error" => Ok(Level::Error),
+			"warn" => Ok(Level::Warn),
+			"info" => Ok(Level::Info),
+			"debug" => Ok(Level::Debug),
+			_ => Err(format!("Unknown log level '{}' (expected one of {})", name, LEVEL_NAMES.join(", "))),
+		}
+	}
+}
+
+pub fn set_level(level: Level) {
+	LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+	level as usize <= LEVEL.load(Ordering::Relaxed)
+}
+
+macro_rules! error {
+	($($arg:tt)*) => { if ::log::enabled(::log::Level::Error) { println!($($arg)*) } }
+}
+
+macro_rules! warn {
+	($($arg:tt)*) => { if ::log::enabled(::log::Level::Warn) { println!($($arg)*) } }
+}
+
+macro_rules! info {
+	($($arg:tt)*) => { if ::log::enabled(::log::Level::Info) { println!($($arg)*) } }
+}
+
+macro_rules! debug {
+	($($arg:tt)*) => { if ::log::enabled(::log::Level::Debug) { println!($($arg)*) } }
+}
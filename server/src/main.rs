@@ -1,11 +1,16 @@
-#![feature(ord_max_min)]
 
+#[macro_use]
+mod log;
+
+mod config;
 mod connections;
 mod fileserver;
 mod http;
+mod tls;
 mod ws;
 
 mod world;
+mod stats;
 
 #[macro_use]
 extern crate common;
@@ -13,169 +18,480 @@ extern crate common;
 extern crate sha1;
 extern crate base64;
 extern crate flate2;
-
-use std::net::{TcpStream, TcpListener};
-use std::io::Read;
-use std::sync::mpsc;
+extern crate toml;
+extern crate serde;
+extern crate rustls;
+extern crate rcgen;
+extern crate libc;
+#[macro_use]
+extern crate serde_derive;
+
+use std::collections::HashMap;
+use std::env;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::time;
 
 use common::*;
-use common::world::Species;
-use connections::ConnectionID;
-
-// main thread, sim -> network thread
+use common::world::{Species, SpeciesRegistry, ChunkCoord, SoilChannel, ALL_SOIL_CHANNELS, Season, Weather, Affliction, Genome, chunk_for_pos};
+use common::terrain::Terrain;
+use common::fauna::FaunaKind;
+use common::stats::WorldStats;
+use config::{Config, LimitsConfig, CompressionConfig};
+use connections::{ConnectionID, ConnectionManager};
+use world::{World, WorldID, WorldConfig};
+
+// main thread, sim threads -> network thread
 enum NetworkMessage {
-	NewConnection(TcpStream),
-	NewSession(ConnectionID, u32),
-	AuthSuccess(ConnectionID, u32),
-	AuthFail(ConnectionID),
+	NewConnection(tls::Stream, Option<ws::DeflateParams>),
+
+	ChunkStateReady(WorldID, ConnectionID, ChunkCoord, Vec<(u32, Vec2, Species, bool, f32)>, Vec<u8>, Vec<(u32, u8)>, Vec<(u32, Affliction)>),
+	ChunkSoilReady(WorldID, ConnectionID, ChunkCoord, SoilChannel, Vec<u8>),
+	TreeInfoReady(WorldID, ConnectionID, u32, Species, u32, Genome),
+	StatsReady(WorldID, ConnectionID, Vec<WorldStats>),
+	PlaceTree(WorldID, u32, Vec2, Species, bool, f32),
+	KillTree(WorldID, u32, Vec2),
+	AfflictTree(WorldID, u32, Vec2, Affliction, bool),
+
+	WorldTick(WorldID, ChunkCoord, Vec<u8>),
+	TreeTick(WorldID, ChunkCoord, Vec<(u32, u8)>),
+	SoilTick(WorldID, ChunkCoord, SoilChannel, Vec<u8>),
+	ClimateChanged(WorldID, Season, Weather),
+	FaunaSync(WorldID, Vec<(u32, FaunaKind, Vec2, Vec2)>),
+}
 
-	WorldStateReady(ConnectionID, Vec<(u32, Vec2, Species)>, Vec<u8>),
-	PlaceTree(u32, Vec2, Species),
-	KillTree(u32),
+// network thread -> a world's sim thread
+enum SimulationMessage {
+	RequestChunkState(ConnectionID, ChunkCoord),
+	RequestChunkSoil(ConnectionID, ChunkCoord, SoilChannel),
+	RequestPlaceTree(ConnectionID, Vec2, Species),
+	InspectTree(ConnectionID, u32),
+	RequestStats(ConnectionID, u16),
+}
 
-	WorldTick(Vec<u8>),
-	TreeTick(Vec<(u32, u8)>),
+// What the network thread needs to know about each hosted world
+struct WorldHandle {
+	name: String,
+	max_players: u16,
+
+	registry: SpeciesRegistry,
+	terrain: Terrain,
+	// Sent to clients as they join
+	climate: (Season, Weather),
+
+	tx: mpsc::Sender<SimulationMessage>,
 }
 
-// network thread -> sim thread
-enum SimulationMessage {
-	RequestNewSession(ConnectionID),
-	AttemptAuthSession(ConnectionID, u32),
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
 
-	RequestWorldState(ConnectionID),
-	RequestPlaceTree(ConnectionID, Vec2, Species),
+extern "C" fn on_shutdown_signal(_: libc::c_int) {
+	SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+// Sim threads save their worlds and stop once this is set, and then the process exits
+fn shutdown_on_signals() {
+	for &signal in [libc::SIGINT, libc::SIGTERM].iter() {
+		unsafe { libc::signal(signal, on_shutdown_signal as extern "C" fn(libc::c_int) as libc::sighandler_t); }
+	}
+}
+
+fn usage() -> ! {
+	let defaults = Config::default();
+
+	println!("Usage: wsserver [options]");
+	println!();
+	println!("  --config <path>                  config file (default {}, if it exists)", config::DEFAULT_CONFIG_PATH);
+	println!();
+	println!("These override the config file:");
+	println!("  --listen <addr>                  address for both http and websockets (default {})", defaults.http.listen);
+	println!("  --ws-listen <addr>               also accept websockets on their own address, like older clients expect (e.g. 0.0.0.0:9001)");
+	println!("  --websocket-url <url>            where clients open their websocket, if not back at the page's origin");
+	println!("  --doc-root <path>                directory served over http (default {})", defaults.http.doc_root.display());
+	println!("  --build-dir <path>               client build output, served after the doc root (default {})", defaults.http.build_dir.display());
+	println!("  --http-workers <n>               http connections handled at once (default {})", defaults.http.workers);
+	println!("  --worlds <path>                  list of hosted worlds (default {})", defaults.worlds.file);
+	println!("  --save-dir <path>                where world saves and stats go (default {})", defaults.worlds.save_dir);
+	println!("  --tick-duration <ms>             milliseconds between world ticks (default {})", defaults.worlds.tick_duration);
+	println!("  --world-size <n>                 tiles across for fresh worlds ({}-{}, default {})", world::MIN_WORLD_SIZE, world::MAX_WORLD_SIZE, defaults.worlds.size);
+	println!("  --max-packets-per-second <n>     per connection, anything over is dropped (default {})", defaults.limits.max_packets_per_second);
+	println!("  --max-connections <n>            websocket connections open at once (default {})", defaults.limits.max_connections);
+	println!("  --max-connections-per-ip <n>     websocket connections open at once from one address (default {})", defaults.limits.max_connections_per_ip);
+	println!("  --allowed-origin <origin>        let pages from another origin open websockets, can be given more than once");
+	println!("  --compression <true|false>       permessage-deflate for websocket messages, if the client offers it (default {})", defaults.compression.enabled);
+	println!("  --compression-context-takeover <true|false>");
+	println!("                                   let compressed messages refer back to earlier ones (default {})", defaults.compression.context_takeover);
+	println!("  --compression-min-size <bytes>   send shorter messages uncompressed (default {})", defaults.compression.min_size);
+	println!("  --log-level <level>              one of {} (default {})", log::LEVEL_NAMES.join(", "), defaults.log.level);
+	println!("  --tls-cert <path>                PEM certificate chain, serves https and wss along with --tls-key");
+	println!("  --tls-key <path>                 PEM private key for --tls-cert");
+	println!("  --tls-self-signed <true|false>   serve https and wss with a certificate generated for localhost");
+
+	std::process::exit(1)
+}
+
+// Command line options are applied over the config file
+fn parse_args(args: Vec<String>) -> Result<Config, String> {
+	let mut config_path = None;
+	let mut overrides = Vec::new();
+
+	let mut args = args.into_iter();
+
+	while let Some(arg) = args.next() {
+		if arg == "--help" || arg == "-h" { usage() }
+
+		let value = match args.next() {
+			Some(v) => v,
+			None => return Err(format!("Missing value for '{}'", arg)),
+		};
+
+		if arg == "--config" {
+			config_path = Some(value);
+		} else {
+			overrides.push((arg, value));
+		}
+	}
+
+	let mut config = match config_path {
+		Some(path) => Config::load(&path, true)?,
+		None => Config::load(config::DEFAULT_CONFIG_PATH, false)?,
+	};
+
+	for (arg, value) in overrides {
+		if !config.set(&arg, &value)? {
+			return Err(format!("Unknown option '{}'", arg));
+		}
+	}
+
+	config.validate()?;
+
+	Ok(config)
 }
 
 fn main() {
-	println!("Is Hosted:      {}", cfg!(hosted));
-	println!("Public address: {}", env!("PUBLIC_ADDRESS"));
+	let config = match parse_args(env::args().skip(1).collect()) {
+		Ok(c) => c,
+		Err(e) => {
+			println!("{}", e);
+			usage()
+		}
+	};
 
-	let listener = TcpListener::bind("0.0.0.0:9001").unwrap();
-	let fs_listener = TcpListener::bind("0.0.0.0:8000").unwrap();
+	log::set_level(config.get_log_level().unwrap());
+
+	config.print();
+	println!();
+
+	if !config.http.build_dir.is_dir() {
+		warn!("Warning: client build output '{}' doesn't exist yet", config.http.build_dir.display());
+	}
 
-	thread::spawn(move || fileserver::start(fs_listener));
+	let configs = if Path::new(&config.worlds.file).exists() {
+		world::load_world_configs(&config.worlds.file, &config.worlds)
+	} else {
+		Ok(world::default_world_configs(&config.worlds))
+	};
+
+	let configs = configs.unwrap_or_else(|e| {
+		error!("Failed to load worlds: {}", e);
+		std::process::exit(1);
+	});
+
+	if let Err(e) = std::fs::create_dir_all(&config.worlds.save_dir) {
+		error!("Failed to create save directory '{}': {}", config.worlds.save_dir, e);
+		std::process::exit(1);
+	}
+
+	let tls = config.tls.get_cert_source().map(|source| {
+		let acceptor = tls::Acceptor::new(source).unwrap_or_else(|e| {
+			error!("Failed to set up TLS: {}", e);
+			std::process::exit(1);
+		});
+
+		Arc::new(acceptor)
+	});
+
+	if let Some(ref acceptor) = tls {
+		if acceptor.is_reloadable() {
+			tls::reload_on_sighup(acceptor.clone());
+		}
+	}
+
+	shutdown_on_signals();
+
+	let listener = bind(&config.http.listen);
+	let ws_listener = config.http.ws_listen.as_ref().map(|addr| bind(addr));
 
 	let (main_tx, net_rx) = mpsc::channel::<NetworkMessage>();
-	let (net_tx, sim_rx) = mpsc::channel::<SimulationMessage>();
-	let sim_tx = main_tx.clone();
 
-	let connection_thd = thread::spawn(move || network_loop(net_rx, net_tx));
-	let simulation_thd = thread::spawn(move || sim_loop(sim_tx, sim_rx));
+	let mut handles = Vec::new();
+	let mut simulation_thds = Vec::new();
+
+	for (id, config) in configs.into_iter().enumerate() {
+		let world = world::create_world(&config).unwrap_or_else(|e| {
+			error!("Failed to create world '{}': {}", config.name, e);
+			std::process::exit(1);
+		});
 
+		info!("World '{}': {} species, {} trees, {}x{} terrain, seed {}",
+			config.name, world.registry.len(), world.trees.len(), world.terrain.width, world.terrain.height, world.terrain.seed);
+
+		let (net_tx, sim_rx) = mpsc::channel::<SimulationMessage>();
+		let sim_tx = main_tx.clone();
+
+		handles.push(WorldHandle {
+			name: config.name.clone(),
+			max_players: config.max_players,
+
+			registry: world.registry.clone(),
+			terrain: world.terrain.clone(),
+			climate: (world.climate.season, world.climate.weather),
+
+			tx: net_tx,
+		});
+
+		let id = id as WorldID;
+		simulation_thds.push(thread::spawn(move || sim_loop(id, config, world, sim_tx, sim_rx)));
+	}
+
+	let limits = config.limits.clone();
+	thread::spawn(move || network_loop(net_rx, handles, limits));
+
+	if let Some(ws_listener) = ws_listener {
+		let tx = main_tx.clone();
+		let tls = tls.clone();
+		let allowed_origins = config.http.allowed_origins.clone();
+		let compression = config.compression.clone();
+		thread::spawn(move || websocket_listener(ws_listener, tls, allowed_origins, compression, tx));
+	}
+
+	// Senders can't be shared between threads, only moved
+	let tx = Mutex::new(main_tx);
+	let allowed_origins = config.http.allowed_origins.clone();
+	let compression = config.compression.clone();
+	let on_websocket: fileserver::WebSocketHandler = Box::new(move |stream: tls::Stream, header: &http::Request| {
+		let tx = tx.lock().unwrap().clone();
+		accept_websocket(stream, header, &allowed_origins, &compression, &tx);
+	});
+
+	let fileserver_config = config.http.get_fileserver_config();
+	thread::spawn(move || fileserver::start(listener, fileserver_config, tls, Some(on_websocket)));
+
+	for thd in simulation_thds {
+		thd.join().unwrap();
+	}
+
+	info!("Worlds saved, shutting down");
+}
+
+fn bind(address: &str) -> TcpListener {
+	TcpListener::bind(address).unwrap_or_else(|e| {
+		error!("Failed to listen on {}: {}", address, e);
+		std::process::exit(1);
+	})
+}
+
+// Upgrades the connection and hands it to the network thread, unless it's from a page that isn't allowed
+fn accept_websocket(mut stream: tls::Stream, header: &http::Request, allowed_origins: &[String], compression: &CompressionConfig, tx: &mpsc::Sender<NetworkMessage>) {
+	let deflate = match ws::init_websocket_connection(&mut stream, header, compression) {
+		Ok(deflate) => deflate,
+		Err(e) => {
+			warn!("Error initialising connection: {}", e);
+			return
+		}
+	};
+
+	if !ws::is_origin_allowed(header, allowed_origins) {
+		let address = stream.get_ref().peer_addr().map(|a| a.to_string()).unwrap_or_default();
+		warn!("Refusing connection from {}: origin '{}' isn't allowed", address, header.get("Origin").unwrap_or(""));
+		ws::send_close(&mut stream, ws::CLOSE_ORIGIN_NOT_ALLOWED, "Origin not allowed");
+		return
+	}
+
+	tx.send(NetworkMessage::NewConnection(stream, deflate)).unwrap();
+}
+
+// The old dedicated websocket port
+fn websocket_listener(listener: TcpListener, tls: Option<Arc<tls::Acceptor>>, allowed_origins: Vec<String>, compression: CompressionConfig, tx: mpsc::Sender<NetworkMessage>) {
 	for stream in listener.incoming() {
 		match stream {
-			Ok(mut stream) => {
-				let mut buf = [0u8; 1024];
-
+			Ok(stream) => {
 				// TODO: poll or async instead of block until timeout
 				stream.set_read_timeout(Some(time::Duration::from_millis(500))).expect("set_read_timeout failed");
 
-				let size = match stream.read(&mut buf) {
-					Ok(s) => s, Err(_) => continue
-				};
+				let mut stream = tls::Stream::accept(stream, &tls);
 
-				if size == 0 { continue }
+				let header = match http::RequestReader::new().read_request(&mut stream) {
+					Ok(Some(header)) => header,
+					Ok(None) => continue,
+					Err(e) => {
+						warn!("Error parsing request: {}", e);
+						continue;
+					}
+				};
 
-				let data = std::str::from_utf8(&buf[0..size]);
-				if !data.is_ok() {
-					println!("Error parsing request: Non utf8 data encountered");
+				if header.method() != "GET" || !header.has_token("Upgrade", "websocket") {
 					continue;
 				}
 
-				match http::Request::parse(data.unwrap()) {
-					Ok(header) => {
-						if header.get("Upgrade") != Some("websocket") {
-							continue;
-						}
-
-						stream.set_read_timeout(None).expect("set_read_timeout failed");
-
-						match ws::init_websocket_connection(&mut stream, &header) {
-							Ok(_) => main_tx.send(NetworkMessage::NewConnection(stream)).unwrap(),
-							Err(e) => println!("Error initialising connection: {}", e)
-						}
-					},
+				stream.get_ref().set_read_timeout(None).expect("set_read_timeout failed");
 
-					Err(e) => {
-						println!("Error parsing request: {}", e);
-					},
-				}
+				accept_websocket(stream, &header, &allowed_origins, &compression, &tx);
 			},
 
-			Err(e) => { println!("Connection failed: {}", e); }
+			Err(e) => { warn!("Connection failed: {}", e); }
 		}
 	}
-
-	connection_thd.join().unwrap();
-	simulation_thd.join().unwrap();
 }
 
-fn network_loop(rx: mpsc::Receiver<NetworkMessage>, tx: mpsc::Sender<SimulationMessage>) {
-	let mut connections = connections::ConnectionManager::new();
+fn network_loop(rx: mpsc::Receiver<NetworkMessage>, mut worlds: Vec<WorldHandle>, limits: LimitsConfig) {
+	let mut connections = ConnectionManager::new(limits);
 	let mut packet_buffer = [0u8; 8<<10];
 
-	let mut packet_queue: Vec<(Option<ConnectionID>, Packet)> = Vec::new();
+	let mut packet_queue: Vec<(ConnectionID, Packet)> = Vec::new();
+	let mut world_packet_queue: Vec<(WorldID, Packet)> = Vec::new();
+	let mut chunk_packet_queue: Vec<(WorldID, ChunkCoord, Packet)> = Vec::new();
+	let mut soil_packet_queue: Vec<(WorldID, ChunkCoord, SoilChannel, Packet)> = Vec::new();
 
-	'main: loop {
-		while let Some(msg) = rx.try_recv().ok() {
+	// The world each session was last in, so reconnecting players end up back there
+	let mut session_worlds: HashMap<u32, WorldID> = HashMap::new();
+
+	loop {
+		while let Ok(msg) = rx.try_recv() {
 			use NetworkMessage as NM;
 
 			match msg {
-				NM::NewConnection(stream) => connections.register_connection(stream),
+				NM::NewConnection(stream, deflate) => {
+					connections.register_connection(stream, deflate);
+				}
+
+				NM::ChunkStateReady(world, id, chunk, state, health_state, tree_maturities, afflictions) => {
+					// The connection may have moved on since asking
+					if connections.get_world(id) != Some(world) { continue }
 
-				NM::NewSession(id, token) => {
-					if connections.notify_new_session(id) {
-						packet_queue.push((Some(id), Packet::NewSession(token)));
+					packet_queue.push((id, Packet::HealthUpdate(chunk.0, chunk.1, health_state)));
+
+					for (t_id, pos, species, natural, variation) in state {
+						// This is p' heavy - best not send hundreds of packets at once probably
+						packet_queue.push((id, Packet::TreePlaced(t_id, pos.x, pos.y, species, natural, variation)));
 					}
-				}
 
-				NM::AuthSuccess(id, token) => {
-					if connections.imbue_session(id, token) {
-						packet_queue.push((Some(id), Packet::AuthSuccessful(token)));
+					packet_queue.push((id, Packet::TreeUpdate(tree_maturities)));
+
+					for (t_id, affliction) in afflictions {
+						packet_queue.push((id, Packet::TreeAfflicted(t_id, affliction, false)));
 					}
 				}
 
-				NM::AuthFail(id) => {
-					connections.notify_auth_fail(id);
-					packet_queue.push((Some(id), Packet::AuthFail));
+				NM::ChunkSoilReady(world, id, chunk, ch, soil_state) => {
+					if connections.get_world(id) != Some(world) { continue }
+					packet_queue.push((id, Packet::SoilUpdate(chunk.0, chunk.1, ch, soil_state)));
 				}
 
-				NM::WorldStateReady(id, state, health_state) => {
-					packet_queue.push((Some(id), Packet::HealthUpdate(health_state)));
+				NM::TreeInfoReady(world, id, tree_id, species, generation, genome) => {
+					if connections.get_world(id) != Some(world) { continue }
+					packet_queue.push((id, Packet::TreeInfo(tree_id, species, generation, genome)));
+				}
 
-					for (t_id, pos, species) in state {
-						// This is p' heavy - best not send hundreds of packets at once probably
-						packet_queue.push((Some(id), Packet::TreePlaced(t_id, pos.x, pos.y, species)));
-					}
+				NM::StatsReady(world, id, samples) => {
+					if connections.get_world(id) != Some(world) { continue }
+					packet_queue.push((id, Packet::StatsHistory(samples)));
 				}
 
-				NM::PlaceTree(tree_id, pos, species) => packet_queue.push((None, Packet::TreePlaced(tree_id, pos.x, pos.y, species))),
-				NM::KillTree(tree_id) => packet_queue.push((None, Packet::TreeDied(tree_id))),
-				NM::WorldTick(health_state) => packet_queue.push((None, Packet::HealthUpdate(health_state))),
-				NM::TreeTick(tree_changes) => packet_queue.push((None, Packet::TreeUpdate(tree_changes))),
+				NM::PlaceTree(world, tree_id, pos, species, natural, variation) =>
+					chunk_packet_queue.push((world, chunk_for_pos(pos), Packet::TreePlaced(tree_id, pos.x, pos.y, species, natural, variation))),
+				NM::KillTree(world, tree_id, pos) =>
+					chunk_packet_queue.push((world, chunk_for_pos(pos), Packet::TreeDied(tree_id))),
+				NM::AfflictTree(world, tree_id, pos, affliction, origin) =>
+					chunk_packet_queue.push((world, chunk_for_pos(pos), Packet::TreeAfflicted(tree_id, affliction, origin))),
+				NM::WorldTick(world, chunk, health_state) =>
+					chunk_packet_queue.push((world, chunk, Packet::HealthUpdate(chunk.0, chunk.1, health_state))),
+				NM::TreeTick(world, chunk, tree_changes) =>
+					chunk_packet_queue.push((world, chunk, Packet::TreeUpdate(tree_changes))),
+				NM::SoilTick(world, chunk, ch, soil_state) =>
+					soil_packet_queue.push((world, chunk, ch, Packet::SoilUpdate(chunk.0, chunk.1, ch, soil_state))),
+
+				NM::FaunaSync(world, animals) =>
+					world_packet_queue.push((world, Packet::FaunaUpdate(animals))),
+
+				NM::ClimateChanged(world, season, weather) => {
+					worlds[world as usize].climate = (season, weather);
+					world_packet_queue.push((world, Packet::ClimateUpdate(season, weather)));
+				}
 			}
 		}
 
 		use SimulationMessage as SM;
 
 		while let Some((id, packet)) = connections.try_read(&mut packet_buffer) {
+			if let Packet::JoinWorld(world) = packet {
+				debug!("Join world ({}): {}", id, world);
+
+				if try_join_world(&mut connections, &worlds, id, world, &mut packet_queue) {
+					if let Some(token) = connections.get_session(id) {
+						session_worlds.insert(token, world);
+					}
+				} else {
+					// So the client can see why
+					packet_queue.push((id, Packet::WorldList(get_world_list(&connections, &worlds))));
+				}
+
+				continue
+			}
+
+			// Everything else is about the world the connection is in
+			let handle = match connections.get_world(id) {
+				Some(world) => &worlds[world as usize],
+				None => continue,
+			};
+
+			let tx = &handle.tx;
+
 			match packet {
 				Packet::Debug(s) => {
-					println!("Debug ({}): {}", id, s);
+					debug!("Debug ({}): {}", id, s);
+				}
+
+				Packet::SubscribeRegion(region) => {
+					let region = world::clamp_region(&handle.terrain, region);
+					debug!("Subscribe region ({}): {:?}", id, region);
+
+					let overlay = connections.get_overlay(id);
+
+					for chunk in connections.set_region(id, region) {
+						tx.send(SM::RequestChunkState(id, chunk)).unwrap();
+
+						if let Some(ch) = overlay {
+							tx.send(SM::RequestChunkSoil(id, chunk, ch)).unwrap();
+						}
+					}
 				}
 
-				Packet::RequestDownloadWorld => {
-					println!("Request world state ({})", id);
-					tx.send(SM::RequestWorldState(id)).unwrap();
+				Packet::RequestSoilOverlay(overlay) => {
+					debug!("Soil overlay ({}): {:?}", id, overlay);
+
+					for chunk in connections.set_overlay(id, overlay) {
+						if let Some(ch) = overlay {
+							tx.send(SM::RequestChunkSoil(id, chunk, ch)).unwrap();
+						}
+					}
 				}
 
 				Packet::RequestPlaceTree(x, y, species) => {
-					println!("place tree ({}): {}, {} - [{:?}]", id, x, y, species);
+					debug!("place tree ({}): {}, {} - [{:?}]", id, x, y, species);
 					tx.send(SM::RequestPlaceTree(id, Vec2::new(x, y), species)).unwrap();
 				}
 
+				Packet::InspectTree(tree_id) => {
+					tx.send(SM::InspectTree(id, tree_id)).unwrap();
+				}
+
+				Packet::RequestStats(count) => {
+					tx.send(SM::RequestStats(id, count)).unwrap();
+				}
+
 				_ => {}
 			}
 		}
@@ -183,108 +499,248 @@ fn network_loop(rx: mpsc::Receiver<NetworkMessage>, tx: mpsc::Sender<SimulationM
 		connections.flush();
 
 		while let Some(id) = connections.poll_new_sessions() {
-			tx.send(SM::RequestNewSession(id)).unwrap();
+			debug!("New Session requested for {}", id);
+
+			let mut rng = thread_rng();
+			let random_key = rng.gen_range(0, connections::MAX_SESSION_KEY);
+			// TODO: not this
+
+			if connections.notify_new_session(id) {
+				packet_queue.push((id, Packet::NewSession(random_key)));
+			}
 		}
 
 		while let Some((id, token)) = connections.poll_auth_attempts() {
-			tx.send(SM::AttemptAuthSession(id, token)).unwrap();
+			// Couldn't have come from the key ring, let alone from us
+			if token >= connections::MAX_SESSION_KEY {
+				connections.notify_auth_fail(id);
+				packet_queue.push((id, Packet::AuthFail));
+				continue
+			}
+
+			// Otherwise accept everything for now
+			if !connections.imbue_session(id, token) { continue }
+
+			packet_queue.push((id, Packet::AuthSuccessful(token)));
+			packet_queue.push((id, Packet::WorldList(get_world_list(&connections, &worlds))));
+
+			// Back to the session's last world if there's room, otherwise the first world that has some
+			let last_world = session_worlds.get(&token).cloned();
+			let candidates = last_world.into_iter().chain(0..worlds.len() as WorldID);
+
+			for world in candidates {
+				if try_join_world(&mut connections, &worlds, id, world, &mut packet_queue) {
+					session_worlds.insert(token, world);
+					break
+				}
+			}
 		}
 
 		for &(id, ref p) in &packet_queue {
 			if !p.is_valid_from_server() { continue }
-
-			if let Some(id) = id {
-				connections.send_to(id, &p);
-			} else {
-				connections.broadcast_to_authed(&p);
-			}
+			connections.send_to(id, p);
 		}
 
 		packet_queue.clear();
 
+		for &(world, ref p) in &world_packet_queue {
+			if !p.is_valid_from_server() { continue }
+			connections.broadcast_to_world(world, p);
+		}
+
+		world_packet_queue.clear();
+
+		for &(world, chunk, ref p) in &chunk_packet_queue {
+			if !p.is_valid_from_server() { continue }
+			connections.broadcast_to_chunk(world, chunk, p);
+		}
+
+		chunk_packet_queue.clear();
+
+		for &(world, chunk, ch, ref p) in &soil_packet_queue {
+			if !p.is_valid_from_server() { continue }
+			connections.broadcast_soil_to_chunk(world, chunk, ch, p);
+		}
+
+		soil_packet_queue.clear();
+
 		thread::sleep(time::Duration::from_millis(50));
 	}
 }
 
+fn get_world_list(connections: &ConnectionManager, worlds: &[WorldHandle]) -> Vec<(String, u16, u16)> {
+	worlds.iter().enumerate()
+		.map(|(i, w)| (w.name.clone(), connections.count_in_world(i as WorldID) as u16, w.max_players))
+		.collect()
+}
+
+// Moves a connection into a world if there's room for it, and queues up everything its client needs
+fn try_join_world(connections: &mut ConnectionManager, worlds: &[WorldHandle], id: ConnectionID, world: WorldID, packet_queue: &mut Vec<(ConnectionID, Packet)>) -> bool {
+	let handle = match worlds.get(world as usize) {
+		Some(h) => h,
+		None => return false,
+	};
+
+	let already_joined = connections.get_world(id) == Some(world);
+
+	if !already_joined && connections.count_in_world(world) >= handle.max_players as usize {
+		info!("World '{}' is full ({})", handle.name, id);
+		return false
+	}
+
+	if !connections.set_world(id, world) {
+		return false
+	}
+
+	packet_queue.push((id, Packet::WorldJoined(world)));
+	packet_queue.push((id, Packet::SpeciesRegistry(handle.registry.species.clone())));
+	packet_queue.extend(terrain_packets(&handle.terrain).into_iter().map(|p| (id, p)));
+	packet_queue.push((id, Packet::ClimateUpdate(handle.climate.0, handle.climate.1)));
+
+	true
+}
+
 //////////////////////////////
 
-fn sim_loop(tx: mpsc::Sender<NetworkMessage>, rx: mpsc::Receiver<SimulationMessage>) {
-	use world::World;
-	use world::Species;
+fn sim_loop(world_id: WorldID, config: WorldConfig, mut world: World, tx: mpsc::Sender<NetworkMessage>, rx: mpsc::Receiver<SimulationMessage>) {
+	use stats::StatsHistory;
 
 	use NetworkMessage as NM;
 	use SimulationMessage as SM;
 
-	let mut world = World::new_random();
-	let mut health_state = Vec::new();
-	let mut tree_maturities = Vec::new();
+	let mut history = StatsHistory::new(&config.get_stats_path(), &world.registry);
+
+	let mut last_update = time::Instant::now();
+	let mut last_fauna_sync = time::Instant::now();
+	let mut last_save = time::Instant::now();
+	let mut unsaved = false;
+
+	loop {
+		if SHUTDOWN_REQUESTED.load(Ordering::SeqCst) {
+			if let Err(e) = world::save_world(&config, &world) {
+				error!("{}", e);
+			}
+
+			return
+		}
 
-	'main: loop {
-		while let Some(msg) = rx.try_recv().ok() {
+		while let Ok(msg) = rx.try_recv() {
 
 			// TODO: Remove all of these unwraps, save world state and exit gracefully
 			match msg {
-				SM::RequestNewSession(con_id) => {
-					// Create new session
-					println!("New Session requested for {}", con_id);
+				SM::RequestChunkState(con_id, chunk) => {
+					let trees_in_chunk = world.trees.iter()
+						.filter(|t| !t.is_dead() && chunk_for_pos(t.pos) == chunk);
 
-					let max_key = 3u32.pow(9);
+					let trees = trees_in_chunk.clone()
+						.map(|t| (t.id, t.pos, t.species, t.natural, t.genome.color_variation))
+						.collect::<Vec<_>>();
 
-					let mut rng = thread_rng();
-					let random_key = rng.gen_range(0, max_key);
-					// TODO: not this
-					
-					tx.send(NM::NewSession(con_id, random_key)).unwrap();
+					let tree_maturities = trees_in_chunk.clone()
+						.map(|t| (t.id, t.get_maturity_stage(&world.registry[t.species])))
+						.collect::<Vec<_>>();
 
-					// TODO: Test if con_id already associated with pending session and delete
-					// 	associate new session with connection and flag as pending
-					// 	remove flag and persist once authed
-				}
+					let afflictions = trees_in_chunk
+						.filter_map(|t| t.affliction.map(|(a, _)| (t.id, a)))
+						.collect::<Vec<_>>();
 
-				SM::AttemptAuthSession(con_id, token) => {
-					// Just accept everything for now
-					tx.send(NM::AuthSuccess(con_id, token)).unwrap();
-				}
+					let health_state = world.get_chunk_health(chunk);
 
-				SM::RequestWorldState(con_id) => {
-					let trees = world.trees.iter()
-						.filter(|t| !t.is_dead())
-						.map(|t| (t.id, t.pos, t.species))
-						.collect::<Vec<_>>();
+					tx.send(NM::ChunkStateReady(world_id, con_id, chunk, trees, health_state, tree_maturities, afflictions)).unwrap();
+				}
 
-					tx.send(NM::WorldStateReady(con_id, trees, health_state.clone())).unwrap();
-					tx.send(NM::TreeTick(tree_maturities.clone())).unwrap();
+				SM::RequestChunkSoil(con_id, chunk, ch) => {
+					tx.send(NM::ChunkSoilReady(world_id, con_id, chunk, ch, world.get_chunk_soil(chunk, ch))).unwrap();
 				}
 
-				SM::RequestPlaceTree(con_id, pos, species) => {
+				SM::RequestPlaceTree(_con_id, pos, species) => {
 					// TODO: Check con_id has a session and hasn't already
 					//	placed too many trees
-					if let Some(t_id) = world.place_tree(species, pos) {
-						tx.send(NM::PlaceTree(t_id, pos, species)).unwrap();
+					// Announced along with naturally dropped seeds below
+					unsaved |= world.place_tree(species, pos).is_some();
+				}
+
+				SM::InspectTree(con_id, tree_id) => {
+					if let Some(t) = world.trees.iter().find(|t| t.id == tree_id) {
+						tx.send(NM::TreeInfoReady(world_id, con_id, t.id, t.species, t.generation, t.genome)).unwrap();
 					}
 				}
+
+				SM::RequestStats(con_id, count) => {
+					tx.send(NM::StatsReady(world_id, con_id, history.latest(count as usize))).unwrap();
+				}
 			}
 		}
 
-		if world.update() {
-			health_state = world.land_health.iter()
-				.map(|h| (h * 255.0) as u8)
-				.collect::<Vec<_>>();
+		let now = time::Instant::now();
+		let dt = now - last_update;
+		last_update = now;
+
+		if world.update(dt.as_secs() as f32 + dt.subsec_nanos() as f32 / 1_000_000_000.0) {
+			history.record(world.take_stats());
+			unsaved = true;
+
+			for chunk in world::all_chunks(&world.terrain).chunks() {
+				let tree_maturities = world.trees.iter()
+					.filter(|&t| !t.is_dead() && chunk_for_pos(t.pos) == chunk)
+					.map(|t| (t.id, t.get_maturity_stage(&world.registry[t.species])))
+					.collect::<Vec<_>>();
+
+				tx.send(NM::WorldTick(world_id, chunk, world.get_chunk_health(chunk))).unwrap();
+
+				if !tree_maturities.is_empty() {
+					tx.send(NM::TreeTick(world_id, chunk, tree_maturities)).unwrap();
+				}
+
+				for &ch in ALL_SOIL_CHANNELS.iter() {
+					tx.send(NM::SoilTick(world_id, chunk, ch, world.get_chunk_soil(chunk, ch))).unwrap();
+				}
+			}
+		}
 
-			tree_maturities = world.trees.iter()
-				.filter(|&t| !t.is_dead())
-				.map(|t| (t.id, t.get_maturity_stage()))
-				.collect::<Vec<_>>();
+		if unsaved && last_save.elapsed() > time::Duration::from_millis(world::SAVE_INTERVAL) {
+			if let Err(e) = world::save_world(&config, &world) {
+				error!("{}", e);
+			}
+
+			last_save = time::Instant::now();
+			unsaved = false;
+		}
+
+		if last_fauna_sync.elapsed() > time::Duration::from_millis(world::FAUNA_SYNC_INTERVAL) {
+			let animals = world.fauna.animals.iter()
+				.map(|a| (a.id, a.kind, a.pos, a.vel))
+				.collect();
+
+			tx.send(NM::FaunaSync(world_id, animals)).unwrap();
+			last_fauna_sync = time::Instant::now();
+		}
+
+		for &(t_id, pos) in &world.dead_trees {
+			tx.send(NM::KillTree(world_id, t_id, pos)).unwrap();
+		}
+
+		for &(t_id, pos, species, natural, variation) in &world.new_trees {
+			tx.send(NM::PlaceTree(world_id, t_id, pos, species, natural, variation)).unwrap();
+		}
+
+		for &(t_id, pos, affliction, origin) in &world.afflicted_trees {
+			if origin {
+				info!("{:?} broke out at tree {} ({})", affliction, t_id, config.name);
+			}
 
-			tx.send(NM::WorldTick(health_state.clone())).unwrap();
-			tx.send(NM::TreeTick(tree_maturities.clone())).unwrap();
+			tx.send(NM::AfflictTree(world_id, t_id, pos, affliction, origin)).unwrap();
 		}
 
-		for &t_id in &world.dead_trees {
-			tx.send(NM::KillTree(t_id)).unwrap();
+		if world.climate_changed {
+			info!("Climate ({}): {:?}, {:?}", config.name, world.climate.season, world.climate.weather);
+			tx.send(NM::ClimateChanged(world_id, world.climate.season, world.climate.weather)).unwrap();
+			world.climate_changed = false;
 		}
 
 		world.dead_trees.clear();
+		world.new_trees.clear();
+		world.afflicted_trees.clear();
 
 		thread::sleep(time::Duration::from_millis(50));
 	}
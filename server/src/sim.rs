@@ -0,0 +1,447 @@
+// Runs the world without any networking, for balancing experiments.
+// See `usage` for options
+
+extern crate common;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process;
+
+use common::*;
+use common::stats::WorldStats;
+use common::species;
+use common::world::{self, World, Tuning, TUNING_NAMES, Species, SpeciesRegistry};
+use common::terrain::{Terrain, TileKind};
+
+// Fauna only need long enough between ticks to settle around flowering trees
+const FAUNA_STEPS_PER_TICK: usize = 20;
+const FAUNA_STEP: f32 = 0.1;
+
+// Pixels per tile in ppm heatmaps
+const HEATMAP_SCALE: usize = 8;
+const HEATMAP_CHARS: &[u8] = b" .:-=+*#%@";
+
+#[derive(Copy, Clone)]
+enum Placement {
+	At(Species, Vec2),
+	Scatter(Species, usize),
+}
+
+struct Options {
+	seed: u32,
+	size: usize,
+	ticks: u32,
+	species_path: String,
+	plan_path: Option<String>,
+	stats_path: Option<String>,
+	heatmap_path: Option<String>,
+	tuning: Tuning,
+	sweeps: Vec<(String, Vec<f32>)>,
+}
+
+fn usage() -> ! {
+	println!("Usage: wssim [options]");
+	println!();
+	println!("  --seed <n>              terrain and simulation seed, random if omitted");
+	println!("  --size <n>              world width and height in tiles ({}-{}, default {})", world::MIN_WORLD_SIZE, world::MAX_WORLD_SIZE, world::DEFAULT_WORLD_SIZE);
+	println!("  --ticks <n>             ticks to run each variant for (default 500)");
+	println!("  --species <path>        species registry (default species.toml)");
+	println!("  --plan <path>           placement plan, scatters 50 of the first species if omitted");
+	println!("  --stats <path>          per tick statistics - json if the path ends in .json, csv otherwise");
+	println!("  --heatmap <path>        final land health - ppm if the path ends in .ppm, ascii otherwise");
+	println!("  --set <name>=<value>    override a tuning value");
+	println!("  --sweep <name>=<a,b,..> run once per value, multiple sweeps run every combination");
+	println!();
+	println!("Paths of '-' write to stdout. With sweeps, each variant's name is added to output paths.");
+	println!("Tuning values: {}", TUNING_NAMES.join(", "));
+	println!();
+	println!("Plans have one placement per line, '#' starts a comment:");
+	println!("  <tick> <species> <x> <y>");
+	println!("  <tick> <species> scatter <count>");
+	println!("where species is a name or index into the registry");
+
+	process::exit(1)
+}
+
+fn main() {
+	let options = match parse_args(env::args().skip(1).collect()) {
+		Ok(o) => o,
+		Err(e) => {
+			println!("{}", e);
+			usage()
+		}
+	};
+
+	let registry = species::load_registry(&options.species_path).unwrap_or_else(|e| exit_with(&e));
+
+	let plan = match options.plan_path {
+		Some(ref path) => load_plan(path, &registry).unwrap_or_else(|e| exit_with(&e)),
+		None => vec![(0, Placement::Scatter(Species(0), 50))],
+	};
+
+	let terrain = world::generate_terrain(options.seed, options.size);
+	println!("Seed: {}", options.seed);
+
+	let variants = build_variants(options.tuning, &options.sweeps);
+
+	println!("{:<40} {:>8} {:>8} {:>8} {:>8} {:>10}", "variant", "trees", "births", "deaths", "health", "diversity");
+
+	for &(ref name, tuning) in variants.iter() {
+		let (history, world) = run(registry.clone(), terrain.clone(), options.seed, tuning, &plan, options.ticks);
+
+		let final_stats = match history.last() {
+			Some(s) => s,
+			None => continue,
+		};
+
+		println!("{:<40} {:>8} {:>8} {:>8} {:>8.3} {:>10.3}",
+			if name.is_empty() { "default" } else { name.as_str() },
+			final_stats.get_total_population(),
+			history.iter().map(|s| s.births as u32).sum::<u32>(),
+			history.iter().map(|s| s.deaths as u32).sum::<u32>(),
+			final_stats.mean_health,
+			final_stats.global_diversity);
+
+		if let Some(ref path) = options.stats_path {
+			let contents = if path.ends_with(".json") {
+				stats_to_json(name, &registry, &history)
+			} else {
+				stats_to_csv(&registry, &history)
+			};
+
+			write_output(&variant_path(path, name), contents.as_bytes()).unwrap_or_else(|e| exit_with(&e));
+		}
+
+		if let Some(ref path) = options.heatmap_path {
+			let contents = if path.ends_with(".ppm") {
+				heatmap_ppm(&world)
+			} else {
+				heatmap_ascii(&world).into_bytes()
+			};
+
+			write_output(&variant_path(path, name), &contents).unwrap_or_else(|e| exit_with(&e));
+		}
+	}
+}
+
+fn exit_with(e: &str) -> ! {
+	println!("{}", e);
+	process::exit(1)
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+	let mut options = Options {
+		seed: thread_rng().gen::<u32>(),
+		size: world::DEFAULT_WORLD_SIZE,
+		ticks: 500,
+		species_path: String::from("species.toml"),
+		plan_path: None,
+		stats_path: None,
+		heatmap_path: None,
+		tuning: Tuning::new(),
+		sweeps: Vec::new(),
+	};
+
+	let mut args = args.into_iter();
+
+	while let Some(arg) = args.next() {
+		if arg == "--help" || arg == "-h" { usage() }
+
+		let value = match args.next() {
+			Some(v) => v,
+			None => return Err(format!("Missing value for '{}'", arg)),
+		};
+
+		match arg.as_str() {
+			"--seed" => options.seed = value.parse().map_err(|_| format!("Invalid seed '{}'", value))?,
+			"--size" => options.size = match value.parse() {
+				Ok(n) if (world::MIN_WORLD_SIZE..=world::MAX_WORLD_SIZE).contains(&n) => n,
+				_ => return Err(format!("Invalid world size '{}'", value)),
+			},
+			"--ticks" => options.ticks = value.parse().map_err(|_| format!("Invalid tick count '{}'", value))?,
+			"--species" => options.species_path = value,
+			"--plan" => options.plan_path = Some(value),
+			"--stats" => options.stats_path = Some(value),
+			"--heatmap" => options.heatmap_path = Some(value),
+
+			"--set" => {
+				let (name, value) = split_assignment(&value)?;
+				let value = parse_f32(value)?;
+
+				match options.tuning.get_mut(name) {
+					Some(v) => *v = value,
+					None => return Err(format!("Unknown tuning value '{}'", name)),
+				}
+			}
+
+			"--sweep" => {
+				let (name, values) = split_assignment(&value)?;
+
+				if options.tuning.get_mut(name).is_none() {
+					return Err(format!("Unknown tuning value '{}'", name));
+				}
+
+				let values = values.split(',')
+					.map(parse_f32)
+					.collect::<Result<Vec<_>, _>>()?;
+
+				options.sweeps.push((String::from(name), values));
+			}
+
+			_ => return Err(format!("Unknown option '{}'", arg)),
+		}
+	}
+
+	Ok(options)
+}
+
+fn split_assignment(s: &str) -> Result<(&str, &str), String> {
+	let mut parts = s.splitn(2, '=');
+
+	match (parts.next(), parts.next()) {
+		(Some(name), Some(value)) => Ok((name.trim(), value.trim())),
+		_ => Err(format!("Expected <name>=<value>, got '{}'", s)),
+	}
+}
+
+fn parse_f32(s: &str) -> Result<f32, String> {
+	s.trim().parse().map_err(|_| format!("Invalid number '{}'", s))
+}
+
+fn load_plan(path: &str, registry: &SpeciesRegistry) -> Result<Vec<(u32, Placement)>, String> {
+	let mut data = String::new();
+
+	File::open(path)
+		.and_then(|mut f| f.read_to_string(&mut data))
+		.map_err(|e| format!("Couldn't read '{}': {}", path, e))?;
+
+	let mut plan = Vec::new();
+
+	for (line_no, line) in data.lines().enumerate() {
+		let line = line.split('#').next().unwrap_or("").trim();
+		if line.is_empty() { continue }
+
+		let error = |msg: &str| format!("{}:{}: {}", path, line_no + 1, msg);
+		let words = line.split_whitespace().collect::<Vec<_>>();
+
+		if words.len() != 4 {
+			return Err(error("expected <tick> <species> <x> <y> or <tick> <species> scatter <count>"));
+		}
+
+		let tick = words[0].parse::<u32>().map_err(|_| error("invalid tick"))?;
+
+		let species = registry.species.iter()
+			.position(|info| info.name == words[1])
+			.map(|i| Species(i as u8))
+			// Parsed as a u8 so out of range indices don't wrap around to a valid one
+			.or_else(|| words[1].parse::<u8>().ok().map(Species))
+			.filter(|&s| registry.is_valid(s))
+			.ok_or_else(|| error(&format!("unknown species '{}'", words[1])))?;
+
+		let placement = if words[2] == "scatter" {
+			let count = words[3].parse::<usize>().map_err(|_| error("invalid count"))?;
+			Placement::Scatter(species, count)
+		} else {
+			let x = words[2].parse::<f32>().map_err(|_| error("invalid x"))?;
+			let y = words[3].parse::<f32>().map_err(|_| error("invalid y"))?;
+			Placement::At(species, Vec2::new(x, y))
+		};
+
+		plan.push((tick, placement));
+	}
+
+	Ok(plan)
+}
+
+// Every combination of swept values, named after the values that differ from the base
+fn build_variants(base: Tuning, sweeps: &[(String, Vec<f32>)]) -> Vec<(String, Tuning)> {
+	let mut variants = vec![(String::new(), base)];
+
+	for (name, values) in sweeps.iter() {
+		variants = variants.iter()
+			.flat_map(|&(ref prefix, tuning)| {
+				values.iter().map(move |&value| {
+					let mut tuning = tuning;
+					*tuning.get_mut(name).unwrap() = value;
+
+					let label = format!("{}={}", name, value);
+					let label = if prefix.is_empty() { label } else { format!("{},{}", prefix, label) };
+
+					(label, tuning)
+				})
+			})
+			.collect();
+	}
+
+	variants
+}
+
+// Every variant gets the same seed, so only the tuning differs between them
+fn run(registry: SpeciesRegistry, terrain: Terrain, seed: u32, tuning: Tuning, plan: &[(u32, Placement)], ticks: u32) -> (Vec<WorldStats>, World) {
+	let mut world = World::with_seed(registry, terrain, seed);
+	world.tuning = tuning;
+	world.seed_soil();
+
+	let mut history = Vec::with_capacity(ticks as usize);
+
+	for tick in 0..ticks {
+		for &(_, placement) in plan.iter().filter(|&&(t, _)| t == tick) {
+			match placement {
+				Placement::At(species, pos) => {
+					if world.place_tree(species, pos).is_none() {
+						println!("Couldn't place {:?} at {:?} on tick {}", species, pos, tick);
+					}
+				}
+
+				Placement::Scatter(species, count) => {
+					let mut placed = 0;
+
+					// Some of the map isn't plantable, so allow for misses
+					for _ in 0..count * 10 {
+						if placed >= count { break }
+
+						let pos = world.random_pos();
+						if world.place_tree(species, pos).is_some() {
+							placed += 1;
+						}
+					}
+				}
+			}
+		}
+
+		for _ in 0..FAUNA_STEPS_PER_TICK {
+			world.update_fauna(FAUNA_STEP);
+		}
+
+		world.tick();
+		history.push(world.take_stats());
+
+		world.dead_trees.clear();
+		world.new_trees.clear();
+		world.afflicted_trees.clear();
+	}
+
+	(history, world)
+}
+
+fn stats_to_csv(registry: &SpeciesRegistry, history: &[WorldStats]) -> String {
+	let mut csv = WorldStats::csv_header(registry);
+	csv.push('\n');
+
+	for stats in history.iter() {
+		csv.push_str(&stats.to_csv_row());
+		csv.push('\n');
+	}
+
+	csv
+}
+
+fn stats_to_json(variant: &str, registry: &SpeciesRegistry, history: &[WorldStats]) -> String {
+	let species = registry.species.iter()
+		.map(|info| format!("\"{}\"", info.name))
+		.collect::<Vec<_>>()
+		.join(",");
+
+	let ticks = history.iter()
+		.map(|s| s.to_json())
+		.collect::<Vec<_>>()
+		.join(",\n");
+
+	format!("{{\"variant\":\"{}\",\"species\":[{}],\"ticks\":[\n{}\n]}}\n", variant, species, ticks)
+}
+
+fn heatmap_ascii(world: &World) -> String {
+	let terrain = &world.terrain;
+	let mut map = String::with_capacity((terrain.width + 1) * terrain.height);
+
+	for y in 0..terrain.height {
+		for x in 0..terrain.width {
+			let idx = x + y * terrain.width;
+
+			map.push(match terrain.tiles[idx].kind {
+				TileKind::Water => '~',
+				TileKind::Rock => '^',
+				TileKind::Soil => {
+					let health = world.land_health[idx].clamp(0.0, 1.0);
+					let level = (health * (HEATMAP_CHARS.len() - 1) as f32).round() as usize;
+					HEATMAP_CHARS[level] as char
+				}
+			});
+		}
+
+		map.push('\n');
+	}
+
+	map
+}
+
+fn heatmap_ppm(world: &World) -> Vec<u8> {
+	let terrain = &world.terrain;
+	let (w, h) = (terrain.width * HEATMAP_SCALE, terrain.height * HEATMAP_SCALE);
+
+	let mut image = format!("P6\n{} {}\n255\n", w, h).into_bytes();
+	image.reserve(w * h * 3);
+
+	for py in 0..h {
+		for px in 0..w {
+			let idx = px / HEATMAP_SCALE + (py / HEATMAP_SCALE) * terrain.width;
+
+			let color = match terrain.tiles[idx].kind {
+				TileKind::Water => [0.2, 0.4, 0.8],
+				TileKind::Rock => [0.5, 0.5, 0.5],
+				TileKind::Soil => {
+					let health = world.land_health[idx].clamp(0.0, 1.0);
+					[0.8 - 0.6 * health, 0.2 + 0.6 * health, 0.1]
+				}
+			};
+
+			image.extend(color.iter().map(|&c: &f32| (c * 255.0) as u8));
+		}
+	}
+
+	image
+}
+
+// Adds the variant name before the extension, so sweeps don't overwrite each other
+fn variant_path(path: &str, variant: &str) -> String {
+	if variant.is_empty() || path == "-" {
+		return String::from(path)
+	}
+
+	match path.rfind('.') {
+		Some(dot) if !path[dot..].contains('/') => format!("{}-{}{}", &path[..dot], variant, &path[dot..]),
+		_ => format!("{}-{}", path, variant),
+	}
+}
+
+fn write_output(path: &str, contents: &[u8]) -> Result<(), String> {
+	if path == "-" {
+		let stdout = ::std::io::stdout();
+		return stdout.lock().write_all(contents)
+			.map_err(|e| format!("Couldn't write to stdout: {}", e))
+	}
+
+	File::create(path)
+		.and_then(|mut f| f.write_all(contents))
+		.map_err(|e| format!("Couldn't write '{}': {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn same_seed_gives_same_run() {
+		let registry = species::parse_registry(include_str!("../species.toml"), "species.toml").unwrap();
+		let terrain = world::generate_terrain(7, world::DEFAULT_WORLD_SIZE);
+		let plan = vec![(0, Placement::Scatter(Species(0), 50)), (20, Placement::Scatter(Species(1), 20))];
+
+		let stats = || {
+			let (history, _) = run(registry.clone(), terrain.clone(), 7, Tuning::new(), &plan, 200);
+			stats_to_csv(&registry, &history)
+		};
+
+		assert_eq!(stats(), stats());
+	}
+}
@@ -0,0 +1,92 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{Write, BufWriter};
+
+use common::packet::get_world_stats_size;
+use common::stats::WorldStats;
+use common::world::SpeciesRegistry;
+
+// Samples, one per tick: about 7 minutes at the default 2s tick, nearly two days at hosted.toml's 750s
+const HISTORY_LENGTH: usize = 200;
+// Replies have to fit in a connection's 4K packet buffer
+const MAX_REPLY_SIZE: usize = 4000;
+
+// Keeps the most recent samples in memory and appends every sample to a csv log
+pub struct StatsHistory {
+	samples: VecDeque<WorldStats>,
+	log: Option<BufWriter<File>>,
+}
+
+impl StatsHistory {
+	pub fn new(log_path: &str, registry: &SpeciesRegistry) -> StatsHistory {
+		let log = match open_log(log_path, registry) {
+			Ok(log) => Some(log),
+			Err(e) => {
+				warn!("Stats won't be logged to disk: {}", e);
+				None
+			}
+		};
+
+		StatsHistory {
+			samples: VecDeque::with_capacity(HISTORY_LENGTH),
+			log,
+		}
+	}
+
+	pub fn record(&mut self, stats: WorldStats) {
+		let mut failed = false;
+
+		if let Some(ref mut log) = self.log {
+			failed = writeln!(log, "{}", stats.to_csv_row())
+				.and_then(|_| log.flush())
+				.map_err(|e| warn!("Failed to write stats log: {}", e))
+				.is_err();
+		}
+
+		if failed { self.log = None; }
+
+		if self.samples.len() >= HISTORY_LENGTH {
+			self.samples.pop_front();
+		}
+
+		self.samples.push_back(stats);
+	}
+
+	// Oldest first. May return fewer than asked for so the reply fits in one packet
+	pub fn latest(&self, count: usize) -> Vec<WorldStats> {
+		let mut size = 0;
+
+		let mut samples = self.samples.iter().rev()
+			.take(count.min(255))
+			.take_while(|s| {
+				size += get_world_stats_size(s);
+				size <= MAX_REPLY_SIZE
+			})
+			.cloned()
+			.collect::<Vec<_>>();
+
+		samples.reverse();
+		samples
+	}
+}
+
+fn open_log(path: &str, registry: &SpeciesRegistry) -> Result<BufWriter<File>, String> {
+	let file = OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(path)
+		.map_err(|e| format!("Couldn't open '{}': {}", path, e))?;
+
+	let is_new = file.metadata().map(|m| m.len() == 0).unwrap_or(false);
+	let mut log = BufWriter::new(file);
+
+	// A header per run, so logs from runs with different species can be told apart
+	if !is_new {
+		writeln!(log).map_err(|e| format!("Couldn't write to '{}': {}", path, e))?;
+	}
+
+	writeln!(log, "{}", WorldStats::csv_header(registry))
+		.map_err(|e| format!("Couldn't write to '{}': {}", path, e))?;
+
+	Ok(log)
+}
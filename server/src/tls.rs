@@ -0,0 +1,188 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time;
+
+use libc;
+use rcgen;
+use rustls::{ServerConfig, ServerSession, StreamOwned, NoClientAuth, Certificate, PrivateKey};
+use rustls::internal::pemfile;
+
+// What a self-signed certificate is made out for
+const SELF_SIGNED_NAMES: &[&str] = &["localhost"];
+
+#[derive(Clone, Debug)]
+pub enum CertSource {
+	// PEM files, reread on SIGHUP
+	Files(String, String),
+	// Generated at startup for local testing. Browsers will warn about it
+	SelfSigned,
+}
+
+// Starts server sessions with whichever certificate was loaded last
+pub struct Acceptor {
+	source: CertSource,
+	config: RwLock<Arc<ServerConfig>>,
+}
+
+impl Acceptor {
+	pub fn new(source: CertSource) -> Result<Acceptor, String> {
+		let config = load_config(&source)?;
+
+		Ok(Acceptor {
+			source,
+			config: RwLock::new(config),
+		})
+	}
+
+	// Self-signed certificates are generated in memory, so there's nothing to reread
+	pub fn is_reloadable(&self) -> bool {
+		match self.source {
+			CertSource::Files(..) => true,
+			CertSource::SelfSigned => false,
+		}
+	}
+
+	// Connections already open keep the certificate they started with. On failure nothing changes
+	pub fn reload(&self) -> Result<(), String> {
+		if !self.is_reloadable() {
+			return Err(String::from("Self-signed certificates aren't reloaded"));
+		}
+
+		let config = load_config(&self.source)?;
+		*self.config.write().unwrap() = config;
+		Ok(())
+	}
+}
+
+// A connection that may or may not be encrypted, so the file server and websockets don't have to care
+pub enum Stream {
+	Plain(TcpStream),
+	Tls(Box<StreamOwned<ServerSession, TcpStream>>),
+}
+
+impl Stream {
+	// The handshake happens on the first read or write, under whatever timeouts the socket has
+	pub fn accept(stream: TcpStream, tls: &Option<Arc<Acceptor>>) -> Stream {
+		match *tls {
+			Some(ref acceptor) => {
+				let session = ServerSession::new(&acceptor.config.read().unwrap());
+				Stream::Tls(Box::new(StreamOwned::new(session, stream)))
+			}
+
+			None => Stream::Plain(stream),
+		}
+	}
+
+	// For timeouts and the like
+	pub fn get_ref(&self) -> &TcpStream {
+		match *self {
+			Stream::Plain(ref s) => s,
+			Stream::Tls(ref s) => &s.sock,
+		}
+	}
+}
+
+impl Read for Stream {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		match *self {
+			Stream::Plain(ref mut s) => s.read(buf),
+			Stream::Tls(ref mut s) => s.read(buf),
+		}
+	}
+}
+
+impl Write for Stream {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		match *self {
+			Stream::Plain(ref mut s) => s.write(buf),
+			Stream::Tls(ref mut s) => s.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		match *self {
+			Stream::Plain(ref mut s) => s.flush(),
+			Stream::Tls(ref mut s) => s.flush(),
+		}
+	}
+}
+
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sighup(_: libc::c_int) {
+	RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+// So renewed certificates are picked up without a restart
+pub fn reload_on_sighup(acceptor: Arc<Acceptor>) {
+	unsafe { libc::signal(libc::SIGHUP, on_sighup as extern "C" fn(libc::c_int) as libc::sighandler_t); }
+
+	thread::spawn(move || loop {
+		thread::sleep(time::Duration::from_millis(500));
+
+		if !RELOAD_REQUESTED.swap(false, Ordering::SeqCst) { continue }
+
+		match acceptor.reload() {
+			Ok(()) => info!("Reloaded TLS certificate"),
+			Err(e) => error!("Couldn't reload TLS certificate, keeping the old one: {}", e),
+		}
+	});
+}
+
+fn load_config(source: &CertSource) -> Result<Arc<ServerConfig>, String> {
+	let (certs, key) = match *source {
+		CertSource::Files(ref cert_path, ref key_path) => (load_certs(cert_path)?, load_key(key_path)?),
+		CertSource::SelfSigned => generate_self_signed()?,
+	};
+
+	let mut config = ServerConfig::new(NoClientAuth::new());
+	config.set_single_cert(certs, key)
+		.map_err(|e| format!("Certificate and key don't work together: {}", e))?;
+
+	Ok(Arc::new(config))
+}
+
+fn load_certs(path: &str) -> Result<Vec<Certificate>, String> {
+	let file = File::open(path)
+		.map_err(|e| format!("Couldn't open '{}': {}", path, e))?;
+
+	let certs = pemfile::certs(&mut BufReader::new(file))
+		.map_err(|_| format!("Couldn't parse '{}'", path))?;
+
+	if certs.is_empty() {
+		return Err(format!("'{}' doesn't contain any certificates", path));
+	}
+
+	Ok(certs)
+}
+
+// PKCS#8 or RSA, which covers what most tools produce
+fn load_key(path: &str) -> Result<PrivateKey, String> {
+	let mut data = Vec::new();
+
+	File::open(path)
+		.and_then(|mut f| f.read_to_end(&mut data))
+		.map_err(|e| format!("Couldn't read '{}': {}", path, e))?;
+
+	let pkcs8_keys = pemfile::pkcs8_private_keys(&mut &data[..]).unwrap_or_default();
+	let rsa_keys = pemfile::rsa_private_keys(&mut &data[..]).unwrap_or_default();
+
+	pkcs8_keys.into_iter().chain(rsa_keys).next()
+		.ok_or_else(|| format!("'{}' doesn't contain a private key", path))
+}
+
+fn generate_self_signed() -> Result<(Vec<Certificate>, PrivateKey), String> {
+	let names = SELF_SIGNED_NAMES.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+	let cert = rcgen::generate_simple_self_signed(names)
+		.map_err(|e| format!("Couldn't generate a certificate: {:?}", e))?;
+
+	let der = cert.serialize_der()
+		.map_err(|e| format!("Couldn't generate a certificate: {:?}", e))?;
+
+	Ok((vec![Certificate(der)], PrivateKey(cert.serialize_private_key_der())))
+}
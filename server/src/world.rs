@@ -1,234 +1,201 @@
-pub use common::world::*;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+
+use toml;
+use common::{thread_rng, Rng};
+use common::species;
+use config::WorldsConfig;
 
-use std::time::{Instant, Duration};
-use common::*;
+pub use common::world::*;
 
-const WORLD_DIMS: (usize,usize) = (28, 28);
-const DIVERSITY_RANGE: f32 = 1.3;
-const DEATH_AFFECT_RANGE: f32 = 2.5;
-const GROWTH_AFFECT_RANGE: f32 = 2.3;
-const TREE_RADIUS: f32 = 0.3;
+// Milliseconds between world ticks, unless configured otherwise
+pub const TICK_DURATION: u64 = 2000;
 
-#[cfg(not(hosted))]
-const TICK_DURATION: u64 = 2000;
+// How often fauna positions are sent to clients
+pub const FAUNA_SYNC_INTERVAL: u64 = 1000;
 
-#[cfg(hosted)]
-const TICK_DURATION: u64 = 750_000;
+// Milliseconds between saves of a world that's changed. Worlds are saved on shutdown too
+pub const SAVE_INTERVAL: u64 = 60000;
 
-pub struct World {
-	pub trees: Vec<Tree>,
-	pub land: [f32; WORLD_DIMS.0 * WORLD_DIMS.1],
-	pub land_health: [f32; WORLD_DIMS.0 * WORLD_DIMS.1],
+// Index into the list of hosted worlds
+pub type WorldID = u8;
 
-	next_tree_id: u32,
-	last_tick: Instant,
+// So the world list always fits in one packet
+const MAX_WORLDS: usize = 64;
+const MAX_NAME_LENGTH: usize = 32;
 
-	pub dead_trees: Vec<u32>,
+#[derive(Deserialize)]
+struct WorldsFile {
+	world: Vec<WorldEntry>,
 }
 
-impl World {
-	pub fn new() -> Self {
-		World {
-			trees: Vec::new(),
-			land: [0.0f32; WORLD_DIMS.0 * WORLD_DIMS.1],
-			land_health: [0.0f32; WORLD_DIMS.0 * WORLD_DIMS.1],
-			next_tree_id: 0,
+// As written in the worlds file. Anything left out comes from the server config
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorldEntry {
+	name: String,
 
-			last_tick: Instant::now(),
+	species: Option<String>,
+	max_players: Option<u16>,
+	tick_duration: Option<u64>,
+	size: Option<usize>,
 
-			dead_trees: Vec::new(),
-		}
-	}
-
-	pub fn new_random() -> Self {
-		let mut world = World::new();
+	seed: Option<u32>,
+	#[serde(default)]
+	tuning: HashMap<String, f32>,
+}
 
-		for _ in 0..50 {
-			world.place_tree(Species::A, rand_vec2(Vec2::new(WORLD_DIMS.0 as f32, WORLD_DIMS.1 as f32)));
-		}
+#[derive(Clone, Debug)]
+pub struct WorldConfig {
+	pub name: String,
 
-		let mut rng = thread_rng();
+	pub species: String,
+	pub max_players: u16,
+	// Milliseconds between ticks
+	pub tick_duration: u64,
+	// Tiles across, only used for fresh worlds
+	pub size: usize,
 
-		for _ in 0..10 {
-			let idx = rng.gen_range(0, world.land.len());
-			world.land[idx] = 100.0;
-		}
+	// Terrain seed for a fresh world, random if not set
+	pub seed: Option<u32>,
+	// Overrides for Tuning values, by name
+	pub tuning: HashMap<String, f32>,
 
-		for _ in 0..10 {
-			world.tick();
-		}
+	pub save_dir: String,
+}
 
-		world
+impl WorldConfig {
+	pub fn get_save_path(&self) -> String {
+		format!("{}/{}.world", self.save_dir, self.name)
 	}
 
-	pub fn place_tree(&mut self, s: Species, pos: Vec2) -> Option<u32> {
-		if pos.x < -0.5
-		|| pos.y < -0.5
-		|| pos.x > WORLD_DIMS.0 as f32 - 0.5
-		|| pos.y > WORLD_DIMS.1 as f32 - 0.5 {
-			return None
-		}
+	pub fn get_stats_path(&self) -> String {
+		format!("{}/{}.csv", self.save_dir, self.name)
+	}
+}
 
-		let pos_available = self.trees.iter()
-			.all(|x| (x.pos - pos).length() > TREE_RADIUS);
+// Used when there's no worlds file
+pub fn default_world_configs(defaults: &WorldsConfig) -> Vec<WorldConfig> {
+	vec![WorldConfig {
+		name: String::from("default"),
+		species: defaults.species.clone(),
+		max_players: defaults.max_players,
+		tick_duration: defaults.tick_duration,
+		size: defaults.size,
+		seed: None,
+		tuning: HashMap::new(),
+		save_dir: defaults.save_dir.clone(),
+	}]
+}
 
-		if pos_available {
-			let id = self.next_tree_id;
+pub fn load_world_configs(path: &str, defaults: &WorldsConfig) -> Result<Vec<WorldConfig>, String> {
+	let mut data = String::new();
 
-			self.trees.push(Tree {
-				species: s,
-				maturity: Maturity::Seed(0),
-				pos, id,
-			});
+	File::open(path)
+		.and_then(|mut f| f.read_to_string(&mut data))
+		.map_err(|e| format!("Couldn't read '{}': {}", path, e))?;
 
-			self.next_tree_id += 1;
+	let file: WorldsFile = toml::from_str(&data)
+		.map_err(|e| format!("Couldn't parse '{}': {}", path, e))?;
 
-			Some(id)
-		} else {
-			None
-		}
+	if file.world.is_empty() {
+		return Err(format!("'{}' doesn't define any worlds", path));
 	}
 
-	pub fn update(&mut self) -> bool {
-		let now = Instant::now();
-
-		let diff = now - self.last_tick;
-		if diff < Duration::from_millis(TICK_DURATION) { return false }
-		self.last_tick = now;
-
-		self.tick();
-
-		true
+	if file.world.len() > MAX_WORLDS {
+		return Err(format!("'{}' defines too many worlds ({} > {})", path, file.world.len(), MAX_WORLDS));
 	}
 
-	fn tick(&mut self) {
-		use self::Maturity::*;
+	let configs = file.world.into_iter()
+		.map(|entry| WorldConfig {
+			species: entry.species.unwrap_or_else(|| defaults.species.clone()),
+			max_players: entry.max_players.unwrap_or(defaults.max_players),
+			tick_duration: entry.tick_duration.unwrap_or(defaults.tick_duration),
+			size: entry.size.unwrap_or(defaults.size),
+			save_dir: defaults.save_dir.clone(),
+
+			name: entry.name,
+			seed: entry.seed,
+			tuning: entry.tuning,
+		})
+		.collect::<Vec<_>>();
+
+	for (i, config) in configs.iter().enumerate() {
+		// Names end up in file paths
+		let valid_name = !config.name.is_empty() && config.name.len() <= MAX_NAME_LENGTH
+			&& config.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+		if !valid_name {
+			return Err(format!("World name '{}' must be 1-{} letters, digits, '_' or '-'", config.name, MAX_NAME_LENGTH));
+		}
 
-		for t in &mut self.trees {
-			let p = t.pos;
-			let (x,y) = (p.x as usize, p.y as usize);
-			let health = self.land_health[x + y*WORLD_DIMS.0];
+		if configs[..i].iter().any(|w| w.name == config.name) {
+			return Err(format!("World '{}' is defined more than once", config.name));
+		}
 
-			let tick_rate = 100 + (200.0*(1.0 - health)) as i32;
+		if config.max_players == 0 {
+			return Err(format!("World '{}' has a max_players of 0", config.name));
+		}
 
-			t.maturity = match t.maturity {
-				Dead => Dead,
-				Adult(t) if t > 50 => Dead,
-				Child(t) if t > 1000 => Adult(0),
-				Seed(t) if t > 1000 => Child(0),
+		if config.tick_duration == 0 {
+			return Err(format!("World '{}' has a tick_duration of 0", config.name));
+		}
 
-				Adult(i) => Adult(i + 1),
-				Child(t) => Child(t + tick_rate),
-				Seed(t) => Seed(t + tick_rate),
-			};
+		if config.size < MIN_WORLD_SIZE || config.size > MAX_WORLD_SIZE {
+			return Err(format!("World '{}' has a size of {} (expected {}-{})", config.name, config.size, MIN_WORLD_SIZE, MAX_WORLD_SIZE));
 		}
 
-		let mut blur_buf = [0.0f32; WORLD_DIMS.0 * WORLD_DIMS.1];
-
-		let ww = WORLD_DIMS.0 as i32;
-		let wh = WORLD_DIMS.1 as i32;
-
-		for y in 0..wh {
-			for x in 0..ww {
-				let sample = |x: i32, y: i32| {
-					// Clamp to edge
-					let x = x.max(0).min(ww-1);
-					let y = y.max(0).min(wh-1);
-
-					let idx = x + y*ww;
-					self.land[idx as usize]
-				};
-
-				let c: f32 = sample(x, y);
-				let o: f32 = [
-					sample(x+1, y),
-					sample(x, y+1),
-					sample(x-1, y),
-					sample(x, y-1),
-				].iter().sum();
-
-				let d: f32 = [
-					sample(x+1, y+1),
-					sample(x+1, y-1),
-					sample(x-1, y+1),
-					sample(x-1, y-1),
-				].iter().sum();
-
-				blur_buf[(x + y*ww) as usize] = c * 0.3 + o * 0.15 + d * 0.025;
+		for name in config.tuning.keys() {
+			if !TUNING_NAMES.contains(&name.as_str()) {
+				return Err(format!("World '{}' has unknown tuning value '{}' (expected one of {})",
+					config.name, name, TUNING_NAMES.join(", ")));
 			}
 		}
+	}
 
-		self.land.copy_from_slice(&blur_buf);
-
-		for y in 0..WORLD_DIMS.1 {
-			for x in 0..WORLD_DIMS.0 {
-				let idx = x + y*WORLD_DIMS.0;
+	Ok(configs)
+}
 
-				let pos = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+// Picks up from the world's save if there is one, otherwise starts afresh
+pub fn create_world(config: &WorldConfig) -> Result<World, String> {
+	let registry = species::load_registry(&config.species)?;
+	let save_path = config.get_save_path();
 
-				let nearby_dead: f32 = self.trees.iter()
-					.filter(|&t| t.is_dead())
-					.map(|t| 1.0 - (t.pos-pos).length() / DEATH_AFFECT_RANGE)
-					.filter(|&d| d > 0.0)
-					.sum();
+	let mut world = match File::open(&save_path) {
+		Ok(mut file) => {
+			let mut data = Vec::new();
+			file.read_to_end(&mut data)
+				.map_err(|e| format!("Couldn't read '{}': {}", save_path, e))?;
 
-				let nearby_growing: f32 = self.trees.iter()
-					.filter(|&t| t.is_growing())
-					.map(|t| t.get_consumption_rate() * (1.0 - (t.pos-pos).length() / GROWTH_AFFECT_RANGE))
-					.filter(|&d| d > 0.0)
-					.sum();
+			World::load(registry, &data)
+				.map_err(|e| format!("Couldn't load '{}': {}", save_path, e))?
+		}
 
-				let nearby_mature: f32 = self.trees.iter()
-					.filter(|&t| t.is_mature())
-					.map(|t| (1.0 - (t.pos-pos).length() / GROWTH_AFFECT_RANGE).max(0.0))
-					.sum();
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+			let seed = config.seed.unwrap_or_else(|| thread_rng().gen());
+			World::new_random(registry, generate_terrain(seed, config.size))
+		}
 
-				let local_diversity = self.get_diversity_at(pos, DIVERSITY_RANGE);
+		Err(e) => return Err(format!("Couldn't open '{}': {}", save_path, e)),
+	};
 
-				let mut c = self.land[idx];
-				c -= 0.03 + ((c-15.0)/3.0).max(0.0); // decay
-				c += local_diversity * nearby_mature * 0.2;
-				c += nearby_dead * 3.0;
-				c -= nearby_growing * 0.2;
-				c = c.max(0.0);
+	for (name, &value) in config.tuning.iter() {
+		*world.tuning.get_mut(name).unwrap() = value;
+	}
 
-				self.land[idx] = c;
-				self.land_health[idx] = 1.0 - (c + 1.0).powf(1.0/3.0) / (c + 1.0);
-			}
-		}
+	world.tick_interval = config.tick_duration as f32 / 1000.0;
 
-		self.dead_trees.extend(self.trees.iter().filter(|x| x.is_dead()).map(|x| x.id));
+	Ok(world)
+}
 
-		self.trees.retain(|x| !x.is_dead());
-	}
+// Written to a temporary file first so a crash mid-save doesn't lose the world
+pub fn save_world(config: &WorldConfig, world: &World) -> Result<(), String> {
+	let path = config.get_save_path();
+	let tmp_path = format!("{}.tmp", path);
 
-	pub fn get_diversity_at(&self, p: Vec2, r: f32) -> f32 {
-		let q = 2.0;
-
-		let trees_in_range = self.trees.iter()
-			.map(|t| (t, (t.pos-p).length()))
-			.filter(|&(_, d)| d < r)
-			.collect::<Vec<_>>();
-
-		let total_potential_diversity = trees_in_range.iter()
-			.fold(0.0, |a, &(t, d)| a + t.get_diversity_contribution() * 4.0 / d);
-
-		let abundances: Vec<_> = ALL_SPECIES.iter()
-			.map(|x| trees_in_range.iter().filter(|&tp| tp.0.species == *x)
-				.fold(0.0, |a, &(t, d)| a + t.get_diversity_contribution() * 4.0 / d))
-			.filter(|&x| x > 0.0)
-			.map(|x| x / total_potential_diversity)
-			.collect();
-
-		let diversity = abundances.iter()
-			.map(|p| p * p.powf(q - 1.0))
-			.sum::<f32>();
-
-		if diversity > 0.0 {
-			let diversity = diversity.powf(-1.0 / (q - 1.0));
-			(diversity - 1.0) / (ALL_SPECIES.len() - 1) as f32
-		} else {
-			0.0
-		}
-	}
+	File::create(&tmp_path)
+		.and_then(|mut f| f.write_all(&world.save()))
+		.and_then(|_| fs::rename(&tmp_path, &path))
+		.map_err(|e| format!("Couldn't save '{}': {}", path, e))
 }
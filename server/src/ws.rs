@@ -1,9 +1,194 @@
-use std::net::TcpStream;
+use std::borrow::Cow;
+use std::io::Write;
 use base64;
 use sha1;
+use flate2::{Compress, Decompress, Compression, Flush, Status};
 use http;
+use config::CompressionConfig;
 
-pub fn init_websocket_connection(mut stream: &mut TcpStream, header: &http::Request) -> Result<(), String> {
+// Sent in close frames when the server turns a connection away, so clients can tell why.
+// 4000-4999 are left for applications to use
+pub const CLOSE_ORIGIN_NOT_ALLOWED: u16 = 4001;
+pub const CLOSE_TOO_MANY_FROM_ADDRESS: u16 = 4002;
+pub const CLOSE_SERVER_FULL: u16 = 4003;
+pub const CLOSE_RATE_LIMITED: u16 = 4004;
+
+// From RFC 6455, for clients that send frames the server can't make sense of
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_UNSUPPORTED_DATA: u16 = 1003;
+pub const CLOSE_INTERNAL_ERROR: u16 = 1011;
+
+// Every message compressed with a sync flush ends in these, so they're left off the wire
+const DEFLATE_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+// Client packets are tiny, anything inflating past this is garbage
+const MAX_INFLATED_SIZE: usize = 64<<10;
+
+// What was agreed for permessage-deflate (RFC 7692) during the handshake
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeflateParams {
+	// Whether each side starts every message with an empty window
+	pub server_no_context_takeover: bool,
+	pub client_no_context_takeover: bool,
+	// Only echoed back. miniz always compresses with the full window, so offers asking for less are declined
+	pub server_max_window_bits: Option<u8>,
+	// Shorter messages are sent as they are
+	pub min_size: usize,
+}
+
+impl DeflateParams {
+	// Picks the first permessage-deflate offer in a Sec-WebSocket-Extensions header that can be accepted
+	pub fn negotiate(offers: &str, config: &CompressionConfig) -> Option<DeflateParams> {
+		if !config.enabled { return None }
+
+		for offer in offers.split(',') {
+			let mut params = offer.split(';').map(|p| p.trim());
+			if params.next() != Some("permessage-deflate") { continue }
+
+			let mut accepted = DeflateParams {
+				server_no_context_takeover: !config.context_takeover,
+				client_no_context_takeover: !config.context_takeover,
+				server_max_window_bits: None,
+				min_size: config.min_size,
+			};
+
+			let mut seen = Vec::new();
+			let acceptable = params.all(|param| {
+				let mut param = param.splitn(2, '=');
+				let name = param.next().unwrap().trim();
+				let value = param.next().map(|v| v.trim().trim_matches('"'));
+
+				// Parameters can't be repeated within an offer
+				if seen.contains(&name) { return false }
+				seen.push(name);
+
+				match (name, value) {
+					("server_no_context_takeover", None) => { accepted.server_no_context_takeover = true; true }
+					("client_no_context_takeover", None) => { accepted.client_no_context_takeover = true; true }
+					("server_max_window_bits", Some("15")) => { accepted.server_max_window_bits = Some(15); true }
+					// Inflating with the full window works whatever the client compresses with
+					("client_max_window_bits", None) => true,
+					("client_max_window_bits", Some(bits)) => bits.parse::<u8>().is_ok_and(|b| (8..=15).contains(&b)),
+					_ => false,
+				}
+			});
+
+			if acceptable {
+				return Some(accepted)
+			}
+		}
+
+		None
+	}
+
+	// For the Sec-WebSocket-Extensions response
+	pub fn to_header(self) -> String {
+		let mut header = String::from("permessage-deflate");
+
+		if self.server_no_context_takeover {
+			header.push_str("; server_no_context_takeover");
+		}
+
+		if self.client_no_context_takeover {
+			header.push_str("; client_no_context_takeover");
+		}
+
+		if let Some(bits) = self.server_max_window_bits {
+			header.push_str(&format!("; server_max_window_bits={}", bits));
+		}
+
+		header
+	}
+}
+
+// A connection's compression state. Unless context takeover is off, each message can refer back to earlier ones
+pub struct Deflate {
+	params: DeflateParams,
+	compress: Compress,
+	decompress: Decompress,
+}
+
+impl Deflate {
+	pub fn new(params: DeflateParams) -> Deflate {
+		Deflate {
+			params,
+			compress: Compress::new(Compression::Default, false),
+			decompress: Decompress::new(false),
+		}
+	}
+
+	pub fn compress(&mut self, payload: &[u8]) -> Vec<u8> {
+		let start = self.compress.total_in();
+		let mut data = Vec::with_capacity(payload.len() + 64);
+
+		loop {
+			let consumed = (self.compress.total_in() - start) as usize;
+			self.compress.compress_vec(&payload[consumed..], &mut data, Flush::Sync);
+
+			// Only finished once everything's gone in and there was room to spare coming out
+			let consumed = (self.compress.total_in() - start) as usize;
+			if consumed == payload.len() && data.len() < data.capacity() { break }
+
+			data.reserve(256);
+		}
+
+		assert!(data.ends_with(&DEFLATE_TAIL));
+		let len = data.len() - DEFLATE_TAIL.len();
+		data.truncate(len);
+
+		if self.params.server_no_context_takeover {
+			self.compress.reset();
+		}
+
+		data
+	}
+
+	pub fn decompress(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+		let mut input = Vec::with_capacity(data.len() + DEFLATE_TAIL.len());
+		input.extend_from_slice(data);
+		input.extend_from_slice(&DEFLATE_TAIL);
+
+		let start = self.decompress.total_in();
+		let mut payload = Vec::with_capacity(data.len() * 4 + 64);
+
+		loop {
+			let consumed = (self.decompress.total_in() - start) as usize;
+			let status = self.decompress.decompress_vec(&input[consumed..], &mut payload, Flush::Sync)
+				.map_err(|_| String::from("Invalid deflate data"))?;
+
+			if payload.len() > MAX_INFLATED_SIZE {
+				return Err(format!("Message inflates to more than {} bytes", MAX_INFLATED_SIZE));
+			}
+
+			// A final block ends the stream, so the next message has to start a new one
+			if status == Status::StreamEnd {
+				self.decompress.reset(false);
+				return Ok(payload)
+			}
+
+			let consumed = (self.decompress.total_in() - start) as usize;
+			if payload.len() < payload.capacity() {
+				if consumed == input.len() { break }
+
+				// Stuck with input left over and room to write to
+				if status == Status::BufError {
+					return Err(String::from("Truncated deflate data"));
+				}
+			}
+
+			payload.reserve(256);
+		}
+
+		if self.params.client_no_context_takeover {
+			self.decompress.reset(false);
+		}
+
+		Ok(payload)
+	}
+}
+
+// Returns the compression to use if the client offered any we can do
+pub fn init_websocket_connection<W: Write>(mut stream: &mut W, header: &http::Request, compression: &CompressionConfig) -> Result<Option<DeflateParams>, String> {
 	if !header.get("Sec-WebSocket-Protocol").unwrap_or("").contains("binary") {
 		let _ = http::Response::new("HTTP/1.1 400 Bad Request")
 			.write_to_stream(&mut stream);
@@ -33,15 +218,70 @@ pub fn init_websocket_connection(mut stream: &mut TcpStream, header: &http::Requ
 	res.set("Sec-WebSocket-Protocol", "binary");
 	res.set("Sec-WebSocket-Accept", accept_key.as_str());
 
+	let deflate = DeflateParams::negotiate(header.get("Sec-WebSocket-Extensions").unwrap_or(""), compression);
+	let extensions = deflate.map(|d| d.to_header());
+	if let Some(ref extensions) = extensions {
+		res.set("Sec-WebSocket-Extensions", extensions.as_str());
+	}
+
 	res.set("Cache-Control", "no-cache");
 	res.set("Pragma", "no-cache");
 
 	match res.write_to_stream(&mut stream) {
-		Ok(_) => Ok(()),
+		Ok(_) => Ok(deflate),
 		Err(e) => Err(format!("{:?}", e))
 	}
 }
 
+// Browsers always send an Origin, so pages elsewhere can only open sockets here if they're allowed.
+// Pages from this server and clients that aren't browsers are always let in
+pub fn is_origin_allowed(header: &http::Request, allowed_origins: &[String]) -> bool {
+	let origin = match header.get("Origin") {
+		Some(o) => o.to_lowercase(),
+		None => return true,
+	};
+
+	if allowed_origins.iter().any(|a| a == "*" || a.to_lowercase() == origin) {
+		return true
+	}
+
+	// "http://example.com:8000" is the same origin as a "Host: example.com:8000"
+	let host = header.get("Host").unwrap_or("").to_lowercase();
+	!host.is_empty() && origin.split_once("://").map(|(_, rest)| rest) == Some(host.as_str())
+}
+
+// What a frame from a client turned out to be
+#[derive(Debug, PartialEq)]
+pub enum Frame<'a> {
+	Binary(Cow<'a, [u8]>),
+	// Carries whatever the pong has to echo back
+	Ping(&'a [u8]),
+	Pong,
+	Close,
+}
+
+// The close code to fail the connection with, and a reason short enough for a close frame
+pub type FrameError = (u16, &'static str);
+
+// Best effort, the connection is dropped straight after anyway
+pub fn send_close<W: Write>(stream: &mut W, code: u16, reason: &str) {
+	// Control frames can only carry 125 bytes
+	assert!(reason.len() <= 123);
+
+	let mut frame = vec![0x88, 2 + reason.len() as u8, (code >> 8) as u8, code as u8];
+	frame.extend_from_slice(reason.as_bytes());
+
+	let _ = stream.write_all(&frame);
+}
+
+// Pings can only carry 125 bytes, so the pong always fits in a short frame
+pub fn send_pong<W: Write>(stream: &mut W, data: &[u8]) {
+	let mut frame = vec![0x8A, data.len() as u8];
+	frame.extend_from_slice(data);
+
+	let _ = stream.write_all(&frame);
+}
+
  //  0                   1                   2                   3
  //  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
  // +-+-+-+-+-------+-+-------------+-------------------------------+
@@ -69,108 +309,404 @@ fn test_bit(v: u16, bit: u8) -> bool {
 	(v & 1<<bit) != 0
 }
 
-pub fn decode_ws_packet<'a>(buf: &'a mut [u8]) -> &'a [u8] {
+// Inflates messages sent compressed, which only happens once permessage-deflate is agreed
+pub fn decode_ws_packet<'a>(buf: &'a mut [u8], deflate: Option<&mut Deflate>) -> Result<Frame<'a>, FrameError> {
+	// RSV1 marks a compressed message
+	let compressed = buf.first().is_some_and(|b| b & 0x40 != 0);
+
+	let data = match decode_frame(buf)? {
+		Frame::Binary(data) => data,
+		_ if compressed => return Err((CLOSE_PROTOCOL_ERROR, "Control frames can't be compressed")),
+		frame => return Ok(frame),
+	};
+
+	if !compressed || data.is_empty() {
+		return Ok(Frame::Binary(data))
+	}
+
+	let deflate = match deflate {
+		Some(deflate) => deflate,
+		None => return Err((CLOSE_PROTOCOL_ERROR, "permessage-deflate wasn't agreed")),
+	};
+
+	match deflate.decompress(&data) {
+		Ok(payload) => Ok(Frame::Binary(Cow::Owned(payload))),
+		Err(e) => {
+			warn!("Couldn't decompress message: {}", e);
+			Err((CLOSE_PROTOCOL_ERROR, "Couldn't decompress message"))
+		}
+	}
+}
+
+fn decode_frame<'a>(buf: &'a mut [u8]) -> Result<Frame<'a>, FrameError> {
+	if buf.len() < 2 {
+		return Err((CLOSE_PROTOCOL_ERROR, "Truncated frame"))
+	}
+
 	let header = (buf[0] as u16) << 8 | buf[1] as u16;
 
 	let final_packet = test_bit(header, 0);
+	let reserved = extract_bits(header, 2, 2);
 	let opcode = extract_bits(header, 4, 4);
 	let masked = test_bit(header, 8); // Client packets should always be masked
 	let len = extract_bits(header, 9, 7) as usize;
 
-	// TODO: handle continuation
-	assert!(final_packet);
+	// RSV1 is left to decode_ws_packet, nothing was agreed for the others
+	if reserved != 0 {
+		return Err((CLOSE_PROTOCOL_ERROR, "Reserved bits set"))
+	}
 
-	match opcode {
-		0x0 => panic!("Continuation frames not implemented"),
-		0x1 => panic!("Text frames not implemented"), // Emscripten doesn't do text frames so this is fine
-		0x2 => {},
-		0x3...0x7 => panic!("Reserved opcode {}", opcode),
-		0x8 => {
-			return &buf[0..0];
-		},
-		0x9 => panic!("Ping frame not handled"),
-		0xA => panic!("Pong frame not handled"),
-		0xB...0xF => panic!("Reserved control frame {}", opcode),
-		_ => unreachable!()
-	}
-
-	assert!(buf.len() > 2);
-
-	let extlen = match len {
-		127 => unimplemented!(),
-		126 => (buf[2] as usize) << 8 | buf[3] as usize,
-		_ => len
-	};
+	// Control frames can't be fragmented or carry more than 125 bytes
+	if opcode >= 0x8 && (!final_packet || len > 125) {
+		return Err((CLOSE_PROTOCOL_ERROR, "Invalid control frame"))
+	}
 
-	let mut payload = match len {
-		127 => &mut buf[10..],
-		126 => &mut buf[4..],
-		_ => &mut buf[2..],
+	let (extlen, header_len) = match len {
+		127 if buf.len() >= 10 => (buf[2..10].iter().fold(0u64, |l, &b| l << 8 | b as u64), 10),
+		126 if buf.len() >= 4 => ((buf[2] as u64) << 8 | buf[3] as u64, 4),
+		126..=127 => return Err((CLOSE_PROTOCOL_ERROR, "Truncated frame")),
+		_ => (len as u64, 2),
 	};
 
-	let expected_len = if masked { extlen+4 } else { extlen };
-	if payload.len() < expected_len {
-		println!("Payload length doesn't match packet ({} != {})", payload.len(), expected_len);
-		return &payload[0..0];
+	let payload = &mut buf[header_len..];
+	let mask_len = if masked { 4 } else { 0 };
+
+	if payload.len() < mask_len || extlen > (payload.len() - mask_len) as u64 {
+		warn!("Payload length doesn't match packet ({} > {})", extlen, payload.len());
+		return Err((CLOSE_PROTOCOL_ERROR, "Frame longer than what arrived"))
 	}
 
-	if masked {
-		let mut mask = [0u8; 4];
-		mask.clone_from_slice(&payload[..4]);
+	let extlen = extlen as usize;
+
+	let data: &'a [u8] = if masked {
+		let (mask, data) = payload.split_at_mut(4);
 
-		for (i, val) in payload[4..expected_len].iter_mut().enumerate() {
+		for (i, val) in data[..extlen].iter_mut().enumerate() {
 			*val ^= mask[i % mask.len()];
 		}
 
-		&payload[4..expected_len]
+		&data[..extlen]
 	} else {
-		&payload[..expected_len]
+		&payload[..extlen]
+	};
+
+	match opcode {
+		0x2 if final_packet => Ok(Frame::Binary(Cow::Borrowed(data))),
+		0x0 | 0x2 => Err((CLOSE_UNSUPPORTED_DATA, "Fragmented messages aren't supported")),
+		0x1 => Err((CLOSE_UNSUPPORTED_DATA, "Text frames aren't supported")), // Emscripten doesn't do text frames
+		0x8 => Ok(Frame::Close),
+		0x9 => Ok(Frame::Ping(data)),
+		0xA => Ok(Frame::Pong),
+		_ => Err((CLOSE_PROTOCOL_ERROR, "Reserved opcode")),
 	}
 }
 
-pub fn encode_ws_packet<'a>(buf: &'a mut [u8], payload: &[u8]) -> &'a [u8] {
-	let short_len = match payload.len() {
-		l @ 0...125 => l,
-		126...65535 => 126,
-		_ => 127,
+// Compresses the payload if permessage-deflate was agreed and it's big enough to bother
+pub fn encode_ws_packet<'a>(buf: &'a mut [u8], payload: &[u8], deflate: Option<&mut Deflate>) -> Result<&'a [u8], String> {
+	let mut compressed = Vec::new();
+	let mut rsv1 = false;
+
+	if let Some(deflate) = deflate {
+		if payload.len() >= deflate.params.min_size {
+			compressed = deflate.compress(payload);
+			rsv1 = true;
+		}
+	}
+
+	let payload = if rsv1 { &compressed[..] } else { payload };
+	let len = payload.len();
+
+	let (short_len, header_len) = match len {
+		0..=125 => (len, 2),
+		126..=65535 => (126, 4),
+		_ => (127, 10),
 	};
 
+	if buf.len() < header_len + len {
+		return Err(format!("{} byte frame doesn't fit in a {} byte buffer", header_len + len, buf.len()))
+	}
+
 	// Compile header
 	let mut header = 0u16;
 	header |= 1 << 15; // FIN
+	if rsv1 { header |= 1 << 14; } // compressed
 	header |= 0x2 << 8; // opcode
 	header |= short_len as u16 & ((1<<7) - 1); // len field
 
 	buf[0] = (header >> 8) as u8;
 	buf[1] = (header & 0xFF) as u8;
 
-	let len = payload.len();
-
-	// Write payload length
-	match short_len {
-		127 => unimplemented!(), // 64b length
-		126 => {
-			buf[2] = (len >> 8) as u8;
-			buf[3] = (len & 0xFF) as u8;
-		},
-		_ => {},
+	// Write payload length, big endian
+	for (i, b) in buf[2..header_len].iter_mut().rev().enumerate() {
+		*b = (len as u64 >> (8 * i)) as u8;
 	}
 
 	// Copy payload
-	{
-		let mut payload_dst = match short_len {
-			127 => &mut buf[10..],
-			126 => &mut buf[4..],
-			_ => &mut buf[2..],
-		};
+	buf[header_len .. header_len + len].copy_from_slice(payload);
+
+	// Return slice containing the entire packet
+	Ok(&buf[.. header_len + len])
+}
 
-		payload_dst[..len].copy_from_slice(&payload[..]);
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn params(no_context_takeover: bool) -> DeflateParams {
+		DeflateParams {
+			server_no_context_takeover: no_context_takeover,
+			client_no_context_takeover: no_context_takeover,
+			server_max_window_bits: None,
+			min_size: 0,
+		}
 	}
 
-	// Return slice containing the entire packet
-	match short_len {
-		127 => &buf[.. 10 + len],
-		126 => &buf[.. 4 + len],
-		_ => &buf[.. 2 + len]
+	fn config() -> CompressionConfig {
+		CompressionConfig::default()
+	}
+
+	// Masks a payload the way a client would
+	fn client_frame(first_byte: u8, payload: &[u8]) -> Vec<u8> {
+		let mask = [0x37, 0xfa, 0x21, 0x3d];
+		let mut frame = vec![first_byte, 0x80 | payload.len() as u8];
+		frame.extend_from_slice(&mask);
+		frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+		frame
+	}
+
+	#[test]
+	fn negotiates_offers() {
+		let accepted = DeflateParams::negotiate("permessage-deflate; client_max_window_bits", &config()).unwrap();
+		assert_eq!(accepted.to_header(), "permessage-deflate");
+
+		let accepted = DeflateParams::negotiate("permessage-deflate; server_no_context_takeover; server_max_window_bits=\"15\"", &config()).unwrap();
+		assert_eq!(accepted.to_header(), "permessage-deflate; server_no_context_takeover; server_max_window_bits=15");
+
+		// miniz can't compress with a smaller window, so the fallback offer is taken
+		let accepted = DeflateParams::negotiate("permessage-deflate; server_max_window_bits=10, permessage-deflate; client_no_context_takeover", &config()).unwrap();
+		assert_eq!(accepted.to_header(), "permessage-deflate; client_no_context_takeover");
+
+		assert_eq!(DeflateParams::negotiate("permessage-deflate; server_max_window_bits=10", &config()), None);
+		assert_eq!(DeflateParams::negotiate("permessage-deflate; client_max_window_bits=16", &config()), None);
+		assert_eq!(DeflateParams::negotiate("permessage-deflate; server_no_context_takeover; server_no_context_takeover", &config()), None);
+		assert_eq!(DeflateParams::negotiate("permessage-deflate; unknown_parameter", &config()), None);
+		assert_eq!(DeflateParams::negotiate("x-webkit-deflate-frame", &config()), None);
+		assert_eq!(DeflateParams::negotiate("", &config()), None);
+
+		let without_takeover = CompressionConfig { context_takeover: false, .. config() };
+		let accepted = DeflateParams::negotiate("permessage-deflate", &without_takeover).unwrap();
+		assert_eq!(accepted.to_header(), "permessage-deflate; server_no_context_takeover; client_no_context_takeover");
+
+		let disabled = CompressionConfig { enabled: false, .. config() };
+		assert_eq!(DeflateParams::negotiate("permessage-deflate", &disabled), None);
+	}
+
+	// RFC 7692 7.2.3.1
+	#[test]
+	fn inflates_hello() {
+		let mut deflate = Deflate::new(params(false));
+		assert_eq!(deflate.decompress(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]).unwrap(), b"Hello");
+	}
+
+	// RFC 7692 7.2.3.2
+	#[test]
+	fn inflates_with_shared_window() {
+		let mut deflate = Deflate::new(params(false));
+		assert_eq!(deflate.decompress(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]).unwrap(), b"Hello");
+		assert_eq!(deflate.decompress(&[0xf2, 0x00, 0x11, 0x00, 0x00]).unwrap(), b"Hello");
+	}
+
+	// RFC 7692 7.2.3.3
+	#[test]
+	fn inflates_stored_block() {
+		let mut deflate = Deflate::new(params(false));
+		let data = [0x00, 0x05, 0x00, 0xfa, 0xff, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x00];
+		assert_eq!(deflate.decompress(&data).unwrap(), b"Hello");
+	}
+
+	// RFC 7692 7.2.3.4
+	#[test]
+	fn inflates_final_block() {
+		let mut deflate = Deflate::new(params(false));
+		let data = [0xf3, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, 0x00];
+		assert_eq!(deflate.decompress(&data).unwrap(), b"Hello");
+
+		// The next message starts a new stream
+		assert_eq!(deflate.decompress(&[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]).unwrap(), b"Hello");
+	}
+
+	// RFC 7692 7.2.3.5
+	#[test]
+	fn inflates_two_blocks() {
+		let mut deflate = Deflate::new(params(false));
+		let data = [0xf2, 0x48, 0x05, 0x00, 0x00, 0x00, 0xff, 0xff, 0xca, 0xc9, 0xc9, 0x07, 0x00];
+		assert_eq!(deflate.decompress(&data).unwrap(), b"Hello");
+	}
+
+	// RFC 7692 7.2.3.6
+	#[test]
+	fn inflates_empty_message() {
+		let mut deflate = Deflate::new(params(false));
+		assert_eq!(deflate.decompress(&[0x00]).unwrap(), b"");
+	}
+
+	// miniz doesn't pick the same blocks zlib did for the RFC's examples, so what it writes is checked by inflating it
+	#[test]
+	fn compresses_without_tail() {
+		let mut server = Deflate::new(params(false));
+		let mut client = Deflate::new(params(false));
+
+		for payload in [&b"Hello"[..], &b""[..], &[7u8; 300][..]].iter() {
+			let data = server.compress(payload);
+			assert!(!data.ends_with(&DEFLATE_TAIL));
+			assert_eq!(client.decompress(&data).unwrap(), *payload);
+		}
+	}
+
+	#[test]
+	fn compresses_with_shared_window() {
+		let payload = (0..200u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect::<Vec<_>>();
+
+		// Repeating a message costs next to nothing when the last one is still in the window
+		let mut deflate = Deflate::new(params(false));
+		let first = deflate.compress(&payload);
+		let second = deflate.compress(&payload);
+		assert!(second.len() < first.len() / 4);
+
+		let mut deflate = Deflate::new(params(true));
+		let first = deflate.compress(&payload);
+		let second = deflate.compress(&payload);
+		assert_eq!(first, second);
+	}
+
+	#[test]
+	fn refuses_bad_data() {
+		let mut deflate = Deflate::new(params(false));
+		assert!(deflate.decompress(&[0xff, 0xff, 0xff]).is_err());
+
+		let mut deflate = Deflate::new(params(false));
+		let bomb = deflate.compress(&vec![0u8; MAX_INFLATED_SIZE + 1]);
+		assert!(deflate.decompress(&bomb).is_err());
+	}
+
+	#[test]
+	fn round_trips_large_messages() {
+		let payload = (0..3000u32).map(|i| (i * i % 251) as u8).collect::<Vec<_>>();
+
+		let mut server = Deflate::new(params(false));
+		let mut client = Deflate::new(params(false));
+
+		for _ in 0..3 {
+			let data = server.compress(&payload);
+			assert_eq!(client.decompress(&data).unwrap(), payload);
+		}
+	}
+
+	#[test]
+	fn encodes_compressed_frame() {
+		let mut buf = [0u8; 64];
+		let mut deflate = Deflate::new(params(false));
+		let data = Deflate::new(params(false)).compress(b"Hello");
+
+		let frame = encode_ws_packet(&mut buf, b"Hello", Some(&mut deflate)).unwrap();
+		assert_eq!(frame[..2], [0xc2, data.len() as u8]);
+		assert_eq!(frame[2..], data[..]);
+
+		let frame = encode_ws_packet(&mut buf, b"Hello", None).unwrap();
+		assert_eq!(frame, [0x82, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+
+		// Under min_size
+		let mut deflate = Deflate::new(DeflateParams { min_size: 6, .. params(false) });
+		let frame = encode_ws_packet(&mut buf, b"Hello", Some(&mut deflate)).unwrap();
+		assert_eq!(frame, [0x82, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f]);
+	}
+
+	// The first frame from RFC 7692 7.2.3.1, as binary rather than text
+	#[test]
+	fn decodes_compressed_frame() {
+		let mut deflate = Deflate::new(params(false));
+
+		let mut frame = client_frame(0xc2, &[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+		assert_eq!(decode_ws_packet(&mut frame, Some(&mut deflate)), Ok(Frame::Binary(Cow::Borrowed(b"Hello"))));
+
+		let mut frame = client_frame(0x82, b"Hello");
+		assert_eq!(decode_ws_packet(&mut frame, Some(&mut deflate)), Ok(Frame::Binary(Cow::Borrowed(b"Hello"))));
+
+		// Compressed without having agreed to it
+		let mut frame = client_frame(0xc2, &[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00]);
+		assert_eq!(decode_ws_packet(&mut frame, None).err().map(|(code, _)| code), Some(CLOSE_PROTOCOL_ERROR));
+
+		let mut frame = client_frame(0xc2, &[0xff, 0xff, 0xff]);
+		assert_eq!(decode_ws_packet(&mut frame, Some(&mut deflate)).err().map(|(code, _)| code), Some(CLOSE_PROTOCOL_ERROR));
+	}
+
+	#[test]
+	fn encodes_long_lengths() {
+		let mut buf = vec![0u8; 70000];
+
+		let payload = vec![7u8; 300];
+		let frame = encode_ws_packet(&mut buf, &payload, None).unwrap();
+		assert_eq!(frame[..4], [0x82, 126, 0x01, 0x2c]);
+		assert_eq!(frame[4..], payload[..]);
+
+		let payload = vec![7u8; 65536];
+		let frame = encode_ws_packet(&mut buf, &payload, None).unwrap();
+		assert_eq!(frame[..10], [0x82, 127, 0, 0, 0, 0, 0, 0x01, 0, 0]);
+		assert_eq!(frame[10..], payload[..]);
+
+		let mut frame = frame.to_vec();
+		assert_eq!(decode_ws_packet(&mut frame, None), Ok(Frame::Binary(Cow::Borrowed(&payload[..]))));
+
+		assert!(encode_ws_packet(&mut buf[..300], &payload[..300], None).is_err());
+	}
+
+	#[test]
+	fn decodes_control_frames() {
+		let mut frame = client_frame(0x89, b"ping");
+		assert_eq!(decode_ws_packet(&mut frame, None), Ok(Frame::Ping(b"ping")));
+
+		let mut frame = client_frame(0x8A, b"");
+		assert_eq!(decode_ws_packet(&mut frame, None), Ok(Frame::Pong));
+
+		let mut frame = client_frame(0x88, &[0x03, 0xe8]);
+		assert_eq!(decode_ws_packet(&mut frame, None), Ok(Frame::Close));
+
+		let mut pong = Vec::new();
+		send_pong(&mut pong, b"ping");
+		assert_eq!(pong, b"\x8A\x04ping");
+	}
+
+	#[test]
+	fn decodes_long_lengths() {
+		let payload = vec![7u8; 300];
+
+		let mut frame = vec![0x82, 126, 0x01, 0x2c];
+		frame.extend_from_slice(&payload);
+		assert_eq!(decode_ws_packet(&mut frame, None), Ok(Frame::Binary(Cow::Borrowed(&payload[..]))));
+
+		let mut frame = vec![0x82, 127, 0, 0, 0, 0, 0, 0, 0x01, 0x2c];
+		frame.extend_from_slice(&payload);
+		assert_eq!(decode_ws_packet(&mut frame, None), Ok(Frame::Binary(Cow::Borrowed(&payload[..]))));
+	}
+
+	#[test]
+	fn refuses_unsupported_frames() {
+		let refused = |mut frame: Vec<u8>| decode_ws_packet(&mut frame, None).err().map(|(code, _)| code);
+
+		assert_eq!(refused(client_frame(0x81, b"text")), Some(CLOSE_UNSUPPORTED_DATA));
+		assert_eq!(refused(client_frame(0x02, b"start")), Some(CLOSE_UNSUPPORTED_DATA));
+		assert_eq!(refused(client_frame(0x80, b"end")), Some(CLOSE_UNSUPPORTED_DATA));
+
+		assert_eq!(refused(client_frame(0x83, b"")), Some(CLOSE_PROTOCOL_ERROR));
+		assert_eq!(refused(client_frame(0x8B, b"")), Some(CLOSE_PROTOCOL_ERROR));
+		assert_eq!(refused(client_frame(0x92, b"")), Some(CLOSE_PROTOCOL_ERROR));
+		assert_eq!(refused(client_frame(0x09, b"")), Some(CLOSE_PROTOCOL_ERROR));
+		assert_eq!(refused(client_frame(0xc9, b"")), Some(CLOSE_PROTOCOL_ERROR));
+
+		// Shorter than the lengths they claim
+		assert_eq!(refused(vec![0x82]), Some(CLOSE_PROTOCOL_ERROR));
+		assert_eq!(refused(vec![0x82, 0x85, 0, 0, 0, 0, 1]), Some(CLOSE_PROTOCOL_ERROR));
+		assert_eq!(refused(vec![0x82, 126, 0x01]), Some(CLOSE_PROTOCOL_ERROR));
+		assert_eq!(refused(vec![0x82, 127, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 1]), Some(CLOSE_PROTOCOL_ERROR));
 	}
 }
@@ -8,4 +8,4 @@ done
 
 screen -dmS "server" bash -i
 sleep 0.1 # shutup
-screen -r -S "server" -p 0 -X stuff $'RUST_BACKTRACE=1 cargo run\r'
+screen -r -S "server" -p 0 -X stuff $'RUST_BACKTRACE=1 cargo run -- --config hosted.toml\r'